@@ -0,0 +1,47 @@
+use std::{path::Path, process::Command};
+
+fn main() {
+    // The migrations are embedded by `sqlx::migrate!`, rebuild when one is added
+    println!("cargo:rerun-if-changed=migrations");
+
+    // `/version` reports the commit, images built without `.git` can pass it in instead
+    println!("cargo:rerun-if-env-changed=KAIROS_GIT_SHA");
+    let sha = std::env::var("KAIROS_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(git_sha)
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=KAIROS_GIT_SHA={}", sha);
+}
+
+fn git_sha() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    // Rebuild when a commit moves HEAD or the branch it points to
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = std::fs::read_to_string(head)
+            .ok()
+            .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+        {
+            // Missing paths count as always changed, packed branches live in `packed-refs`
+            let branch = Path::new(".git").join(branch);
+            let branch = if branch.exists() {
+                branch
+            } else {
+                Path::new(".git/packed-refs").to_path_buf()
+            };
+            if branch.exists() {
+                println!("cargo:rerun-if-changed={}", branch.display());
+            }
+        }
+    }
+
+    let sha = String::from_utf8(output.stdout).ok()?;
+    Some(sha.trim().to_string())
+}
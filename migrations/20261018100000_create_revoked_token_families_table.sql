@@ -0,0 +1,8 @@
+-- Revoked logins, access tokens carrying one of these families are rejected until they expire
+CREATE TABLE revoked_token_families (
+    family_id UUID PRIMARY KEY,
+    revoked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX idx_revoked_token_families_expires_at ON revoked_token_families (expires_at);
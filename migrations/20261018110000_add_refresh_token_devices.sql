@@ -0,0 +1,5 @@
+ALTER TABLE refresh_tokens ADD COLUMN device_name VARCHAR(255);
+ALTER TABLE refresh_tokens ADD COLUMN platform VARCHAR(50);
+ALTER TABLE refresh_tokens ADD COLUMN app_version VARCHAR(50);
+ALTER TABLE refresh_tokens ADD COLUMN ip_address VARCHAR(64);
+ALTER TABLE refresh_tokens ADD COLUMN last_used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
@@ -0,0 +1,12 @@
+-- Passwordless logins, a six digit code and a link token sent by email, stored as SHA-256
+CREATE TABLE login_codes (
+    code_id UUID PRIMARY KEY,
+    email VARCHAR(255) NOT NULL,
+    code_hash VARCHAR(64) NOT NULL,
+    link_hash VARCHAR(64) NOT NULL UNIQUE,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_login_codes_email ON login_codes (email);
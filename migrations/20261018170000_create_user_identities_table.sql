@@ -0,0 +1,13 @@
+-- Provider accounts linked to a Kairos account, logins look users up by (provider, subject)
+-- Accounts from before this table get their identity linked by email on their next login
+CREATE TABLE user_identities (
+    provider VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    -- Email the provider reported when the identity was linked
+    email VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX idx_user_identities_user_id ON user_identities (user_id);
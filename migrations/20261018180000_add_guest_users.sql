@@ -0,0 +1,4 @@
+-- Anonymous accounts, they have a placeholder email until they're upgraded
+ALTER TABLE users ADD COLUMN guest BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_users_guest ON users (created_at) WHERE guest;
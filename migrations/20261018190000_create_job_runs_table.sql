@@ -0,0 +1,13 @@
+-- History of the background jobs, one row per scheduled run so no two replicas run it twice
+CREATE TABLE job_runs (
+    run_id UUID PRIMARY KEY,
+    job VARCHAR(64) NOT NULL,
+    scheduled_for TIMESTAMP WITH TIME ZONE NOT NULL,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMP WITH TIME ZONE,
+    status VARCHAR(16) NOT NULL,
+    detail TEXT,
+    UNIQUE (job, scheduled_for)
+);
+
+CREATE INDEX idx_job_runs_started_at ON job_runs (started_at);
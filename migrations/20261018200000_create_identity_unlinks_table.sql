@@ -0,0 +1,8 @@
+-- Providers a user unlinked an identity of, logins with that provider are no longer linked to
+-- the account by email, the user links the identity again while signed in
+CREATE TABLE identity_unlinks (
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    provider VARCHAR(255) NOT NULL,
+    unlinked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, provider)
+);
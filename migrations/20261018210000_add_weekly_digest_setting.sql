@@ -0,0 +1,2 @@
+-- Users opt in to the weekly digest email, nobody gets it without asking
+ALTER TABLE users ADD COLUMN weekly_digest BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,8 @@
+-- Login codes sent per email, for the hourly limit on requests
+CREATE TABLE login_code_requests (
+    request_id UUID PRIMARY KEY,
+    email VARCHAR(255) NOT NULL,
+    requested_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_login_code_requests_email ON login_code_requests (email, requested_at);
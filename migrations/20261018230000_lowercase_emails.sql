@@ -0,0 +1,12 @@
+-- Emails are stored lowercased, provider logins used to keep the case the provider reported
+-- Accounts that only differ in the case of their email keep it as it is, merging them is left
+-- to support
+UPDATE users
+SET email = LOWER(TRIM(email))
+WHERE email <> LOWER(TRIM(email))
+  AND (
+      SELECT COUNT(*) FROM users same
+      WHERE LOWER(TRIM(same.email)) = LOWER(TRIM(users.email))
+  ) = 1;
+
+UPDATE user_identities SET email = LOWER(TRIM(email)) WHERE email <> LOWER(TRIM(email));
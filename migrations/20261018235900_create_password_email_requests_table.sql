@@ -0,0 +1,8 @@
+-- Password reset and verification emails requested per email, for the hourly limit on requests
+CREATE TABLE password_email_requests (
+    request_id UUID PRIMARY KEY,
+    email VARCHAR(255) NOT NULL,
+    requested_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_password_email_requests_email ON password_email_requests (email, requested_at);
@@ -0,0 +1,70 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    keys::{self, AccessTokenKey, KeyError, KeyRing},
+    mail::{FileMailer, LogMailer, MailError, Mailer, SmtpMailer},
+    webauthn::RelyingParty,
+};
+
+pub mod server;
+
+pub use server::*;
+
+impl TokenConfig {
+    /// Access token keys, loaded from the files the config points at
+    pub fn access_token_keys(&self) -> Result<KeyRing, ConfigError> {
+        let invalid = |e: KeyError| ConfigError(vec![e.to_string()]);
+        if let Some(manifest) = &self.keyring_path {
+            return KeyRing::load(manifest).map_err(invalid);
+        }
+        let (Some(private_path), Some(public_path)) =
+            (&self.private_key_path, &self.public_key_path)
+        else {
+            let key = AccessTokenKey::from_secret(None, self.access_secret.as_bytes());
+            return Ok(KeyRing::single(key));
+        };
+        let algorithm = keys::parse_algorithm(&self.algorithm).map_err(invalid)?;
+        let key = keys::load_pem_files(
+            algorithm,
+            self.key_id.clone(),
+            Some(Path::new(private_path)),
+            Path::new(public_path),
+        )
+        .map_err(invalid)?;
+        Ok(KeyRing::single(key))
+    }
+}
+
+impl MailConfig {
+    pub fn mailer(&self) -> Result<Arc<dyn Mailer>, ConfigError> {
+        let invalid = |e: MailError| ConfigError(vec![format!("mail: {}", e.0)]);
+        let mailer: Arc<dyn Mailer> = match (self.transport, &self.dir, &self.smtp_url) {
+            (MailTransport::File, Some(dir), _) => {
+                Arc::new(FileMailer::new(dir, &self.from).map_err(invalid)?)
+            }
+            (MailTransport::Smtp, _, Some(url)) => {
+                Arc::new(SmtpMailer::new(url, &self.from).map_err(invalid)?)
+            }
+            _ => Arc::new(LogMailer),
+        };
+        Ok(mailer)
+    }
+}
+
+impl Config {
+    /// Passkey relying party, the origins default to the web app
+    pub fn relying_party(&self) -> RelyingParty {
+        let origins = if self.webauthn.origins.is_empty() {
+            vec![self.app.public_url.clone()]
+        } else {
+            self.webauthn.origins.clone()
+        };
+        RelyingParty {
+            id: self.webauthn.rp_id.clone(),
+            name: self.webauthn.rp_name.clone(),
+            origins,
+        }
+    }
+}
+
+pub const UNIQUE_VIOLATION: &str = "23505";
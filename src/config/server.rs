@@ -0,0 +1,763 @@
+use std::{
+    collections::BTreeMap, env, fmt::Display, fs, io::ErrorKind, net::SocketAddr, str::FromStr,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::postgres::PgConnectOptions;
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    jobs::Schedule,
+    keys,
+    oauth::{GITHUB_API_URL, GITHUB_TOKEN_URL},
+};
+
+/// Config file read when `KAIROS_CONFIG` isn't set, optional
+const DEFAULT_CONFIG_PATH: &str = "kairos.toml";
+
+/// Names an OIDC provider can't take, the built-in providers, the fixed `/login/guest` route and
+/// the other login methods counted in the metrics
+const RESERVED_OIDC_NAMES: [&str; 6] = [
+    "google",
+    "github",
+    "guest",
+    "password",
+    "passkey",
+    "login_code",
+];
+
+const CORS_METHODS: [&str; 7] = ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+/// Server settings, read from a TOML file and overridden by `KAIROS_<SECTION>_<KEY>` environment
+/// variables (`DATABASE_URL` for the database url)
+/// Secrets, providers, mail and passkeys keep their own variable names, see `.env.example`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cors: CorsConfig,
+    pub database: DatabaseConfig,
+    pub tokens: TokenConfig,
+    pub logging: LoggingConfig,
+    pub jobs: JobsConfig,
+    pub app: AppConfig,
+    pub mail: MailConfig,
+    pub webauthn: WebauthnConfig,
+    pub google: GoogleConfig,
+    pub github: GithubConfig,
+    /// OpenID Connect providers by name, served under `/login/{name}`
+    pub oidc: BTreeMap<String, OidcConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    /// How long a stop waits for in-flight requests and running jobs
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins of the web apps, scheme and host without a trailing slash
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    /// Apply pending migrations at startup instead of through `kairos-server migrate up`
+    pub run_migrations: bool,
+    /// Statements are logged at debug level with their duration, slower ones as warnings
+    pub slow_query_ms: u64,
+    /// How long startup keeps retrying an unreachable database before giving up
+    pub startup_timeout_secs: u64,
+    /// How long a request waits for a connection, it fails with a 503 after that
+    pub acquire_timeout_secs: u64,
+    /// Idle connections above the minimum are closed after this long
+    pub idle_timeout_secs: u64,
+    /// Connections are replaced after this long, e.g. to follow a failover
+    pub max_lifetime_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `tracing` filter directives, e.g. `info,sqlx::query=debug`
+    pub filter: String,
+}
+
+/// Background jobs, schedules are cron expressions in UTC
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Schedule the jobs in this process, each run still happens on a single replica
+    pub enabled: bool,
+    pub purge_expired_tokens: Schedule,
+    pub stop_runaway_sessions: Schedule,
+    pub collect_guests: Schedule,
+    /// Weekly digest emails to the users who asked for them, off unless scheduled
+    pub send_digests: Option<Schedule>,
+    /// Sessions still running after this many hours are ended
+    pub max_session_hours: i32,
+    /// How long the job run history is kept
+    pub keep_runs_days: i32,
+    /// Guest accounts are deleted after this many days without use
+    pub guest_retention_days: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// Base url of the web app, links in emails point there (`PUBLIC_APP_URL`)
+    pub public_url: String,
+}
+
+/// How emails are sent (`MAILER`, `MAIL_FROM`, `MAIL_DIR`, `SMTP_URL`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    /// Where the `file` transport writes the emails
+    pub dir: Option<String>,
+    pub smtp_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Logged instead of sent, for local setups without a mail server
+    #[default]
+    Log,
+    /// `.eml` files in `mail.dir`
+    File,
+    Smtp,
+}
+
+/// Passkey relying party (`WEBAUTHN_RP_ID`, `WEBAUTHN_RP_NAME`, `WEBAUTHN_ORIGINS`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebauthnConfig {
+    /// Domain the passkeys are bound to
+    pub rp_id: String,
+    pub rp_name: String,
+    /// Web app origins allowed to use the passkeys, `app.public_url` when empty
+    pub origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GoogleConfig {
+    /// OAuth client ids of our apps, ID tokens must be issued to one of them (`GOOGLE_CLIENT_IDS`)
+    pub client_ids: Vec<String>,
+}
+
+/// GitHub OAuth app (`GITHUB_CLIENT_ID`, `GITHUB_CLIENT_SECRET`), the endpoints can be pointed at
+/// a mock server (`GITHUB_TOKEN_URL`, `GITHUB_API_URL`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GithubConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub token_url: String,
+    pub api_url: String,
+}
+
+/// OpenID Connect provider, also set through `OIDC_PROVIDERS` (comma separated names) and
+/// `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID`, `OIDC_<NAME>_CLIENT_SECRET`, `OIDC_<NAME>_SCOPES`
+/// (space separated)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Multi-line and colored, for people
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("{:?} is not pretty or json", s)),
+        }
+    }
+}
+
+impl FromStr for MailTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(MailTransport::Log),
+            "file" => Ok(MailTransport::File),
+            "smtp" => Ok(MailTransport::Smtp),
+            _ => Err(format!("{:?} is not log, file or smtp", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenConfig {
+    pub access_token_minutes: i64,
+    pub refresh_token_days: i64,
+    /// Signs refresh and 2FA challenge tokens (`JWT_REFRESH_SECRET`)
+    pub refresh_secret: String,
+    /// Access tokens are signed with the key ring at `keyring_path` (`JWT_KEYRING_PATH`) when set,
+    /// else with the key pair at `private_key_path` and `public_key_path` (`JWT_PRIVATE_KEY_PATH`,
+    /// `JWT_PUBLIC_KEY_PATH`, `JWT_ALGORITHM`, `JWT_KEY_ID`), else with `access_secret`
+    /// (`JWT_ACCESS_SECRET`)
+    pub access_secret: String,
+    pub keyring_path: Option<String>,
+    pub private_key_path: Option<String>,
+    pub public_key_path: Option<String>,
+    pub algorithm: String,
+    pub key_id: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0:33333".to_string(),
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["http://localhost:6080".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            max_connections: 5,
+            run_migrations: false,
+            slow_query_ms: 1000,
+            startup_timeout_secs: 60,
+            acquire_timeout_secs: 5,
+            idle_timeout_secs: 10 * 60,
+            max_lifetime_secs: 30 * 60,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Pretty,
+            filter: "info".to_string(),
+        }
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        let schedule = |expression: &str| expression.parse().expect("valid default schedule");
+        JobsConfig {
+            enabled: true,
+            purge_expired_tokens: schedule("15 * * * *"),
+            stop_runaway_sessions: schedule("*/5 * * * *"),
+            collect_guests: schedule("@hourly"),
+            send_digests: None,
+            max_session_hours: 6,
+            keep_runs_days: 30,
+            guest_retention_days: 30,
+        }
+    }
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        TokenConfig {
+            access_token_minutes: 60,
+            refresh_token_days: 7,
+            refresh_secret: String::new(),
+            access_secret: String::new(),
+            keyring_path: None,
+            private_key_path: None,
+            public_key_path: None,
+            algorithm: "RS256".to_string(),
+            key_id: None,
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            public_url: "http://localhost:6080".to_string(),
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: MailTransport::Log,
+            from: "Kairos <noreply@localhost>".to_string(),
+            dir: None,
+            smtp_url: None,
+        }
+    }
+}
+
+impl Default for WebauthnConfig {
+    fn default() -> Self {
+        WebauthnConfig {
+            rp_id: "localhost".to_string(),
+            rp_name: "Kairos".to_string(),
+            origins: Vec::new(),
+        }
+    }
+}
+
+impl Default for GithubConfig {
+    fn default() -> Self {
+        GithubConfig {
+            client_id: String::new(),
+            client_secret: String::new(),
+            token_url: GITHUB_TOKEN_URL.to_string(),
+            api_url: GITHUB_API_URL.to_string(),
+        }
+    }
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        OidcConfig {
+            issuer: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            scopes: ["openid", "email", "profile"].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl TokenConfig {
+    /// Expiry of an access token issued now, also how long a revoked family has to stay on the
+    /// revocation list
+    pub fn access_token_expiry(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::minutes(self.access_token_minutes)
+    }
+
+    pub fn refresh_token_expiry(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::days(self.refresh_token_days)
+    }
+}
+
+/// Everything wrong with the configuration, so it can be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Read the file at `KAIROS_CONFIG` (or `kairos.toml` if it exists), apply the environment
+    /// overrides and validate the result
+    pub fn load() -> Result<Config, ConfigError> {
+        let (path, required) = match env::var("KAIROS_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_PATH.to_string(), false),
+        };
+        let toml = match fs::read_to_string(&path) {
+            Ok(toml) => toml,
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => String::new(),
+            Err(e) => return Err(ConfigError(vec![format!("can't read {}: {}", path, e)])),
+        };
+        Config::parse(&toml, |name| env::var(name).ok())
+    }
+
+    /// Parse a config file and apply the overrides `env` returns, validated
+    pub fn parse(toml: &str, env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let mut config: Config = toml::from_str(toml)
+            .map_err(|e| ConfigError(vec![e.to_string().trim_end().to_string()]))?;
+        let mut problems = Vec::new();
+
+        let mut set = |name: &str, apply: &mut dyn FnMut(&str) -> Result<(), String>| {
+            if let Some(value) = env(name) {
+                if let Err(e) = apply(&value) {
+                    problems.push(format!("{}: {}", name, e));
+                }
+            }
+        };
+        set("KAIROS_SERVER_BIND_ADDRESS", &mut |value| {
+            config.server.bind_address = value.to_string();
+            Ok(())
+        });
+        set("KAIROS_SERVER_SHUTDOWN_TIMEOUT_SECS", &mut |value| {
+            parse_into(value, &mut config.server.shutdown_timeout_secs)
+        });
+        set("KAIROS_CORS_ALLOWED_ORIGINS", &mut |value| {
+            config.cors.allowed_origins = split_list(value);
+            Ok(())
+        });
+        set("KAIROS_CORS_ALLOWED_METHODS", &mut |value| {
+            config.cors.allowed_methods = split_list(value);
+            Ok(())
+        });
+        set("DATABASE_URL", &mut |value| {
+            config.database.url = value.to_string();
+            Ok(())
+        });
+        set("KAIROS_DATABASE_MAX_CONNECTIONS", &mut |value| {
+            parse_into(value, &mut config.database.max_connections)
+        });
+        set("KAIROS_DATABASE_RUN_MIGRATIONS", &mut |value| {
+            parse_into(value, &mut config.database.run_migrations)
+        });
+        set("KAIROS_DATABASE_SLOW_QUERY_MS", &mut |value| {
+            parse_into(value, &mut config.database.slow_query_ms)
+        });
+        set("KAIROS_DATABASE_STARTUP_TIMEOUT_SECS", &mut |value| {
+            parse_into(value, &mut config.database.startup_timeout_secs)
+        });
+        set("KAIROS_DATABASE_ACQUIRE_TIMEOUT_SECS", &mut |value| {
+            parse_into(value, &mut config.database.acquire_timeout_secs)
+        });
+        set("KAIROS_DATABASE_IDLE_TIMEOUT_SECS", &mut |value| {
+            parse_into(value, &mut config.database.idle_timeout_secs)
+        });
+        set("KAIROS_DATABASE_MAX_LIFETIME_SECS", &mut |value| {
+            parse_into(value, &mut config.database.max_lifetime_secs)
+        });
+        set("KAIROS_TOKENS_ACCESS_TOKEN_MINUTES", &mut |value| {
+            parse_into(value, &mut config.tokens.access_token_minutes)
+        });
+        set("KAIROS_TOKENS_REFRESH_TOKEN_DAYS", &mut |value| {
+            parse_into(value, &mut config.tokens.refresh_token_days)
+        });
+        set("KAIROS_LOGGING_FORMAT", &mut |value| {
+            config.logging.format = value.parse()?;
+            Ok(())
+        });
+        set("KAIROS_LOGGING_FILTER", &mut |value| {
+            config.logging.filter = value.to_string();
+            Ok(())
+        });
+        set("KAIROS_JOBS_ENABLED", &mut |value| {
+            parse_into(value, &mut config.jobs.enabled)
+        });
+        set("KAIROS_JOBS_PURGE_EXPIRED_TOKENS", &mut |value| {
+            config.jobs.purge_expired_tokens = value.parse()?;
+            Ok(())
+        });
+        set("KAIROS_JOBS_STOP_RUNAWAY_SESSIONS", &mut |value| {
+            config.jobs.stop_runaway_sessions = value.parse()?;
+            Ok(())
+        });
+        set("KAIROS_JOBS_COLLECT_GUESTS", &mut |value| {
+            config.jobs.collect_guests = value.parse()?;
+            Ok(())
+        });
+        set("KAIROS_JOBS_SEND_DIGESTS", &mut |value| {
+            config.jobs.send_digests = match value.trim() {
+                "" | "off" => None,
+                value => Some(value.parse()?),
+            };
+            Ok(())
+        });
+        set("KAIROS_JOBS_MAX_SESSION_HOURS", &mut |value| {
+            parse_into(value, &mut config.jobs.max_session_hours)
+        });
+        set("KAIROS_JOBS_KEEP_RUNS_DAYS", &mut |value| {
+            parse_into(value, &mut config.jobs.keep_runs_days)
+        });
+        set("KAIROS_JOBS_GUEST_RETENTION_DAYS", &mut |value| {
+            parse_into(value, &mut config.jobs.guest_retention_days)
+        });
+
+        set("JWT_REFRESH_SECRET", &mut |value| {
+            config.tokens.refresh_secret = value.to_string();
+            Ok(())
+        });
+        set("JWT_ACCESS_SECRET", &mut |value| {
+            config.tokens.access_secret = value.to_string();
+            Ok(())
+        });
+        set("JWT_KEYRING_PATH", &mut |value| {
+            config.tokens.keyring_path = Some(value.to_string());
+            Ok(())
+        });
+        set("JWT_PRIVATE_KEY_PATH", &mut |value| {
+            config.tokens.private_key_path = Some(value.to_string());
+            Ok(())
+        });
+        set("JWT_PUBLIC_KEY_PATH", &mut |value| {
+            config.tokens.public_key_path = Some(value.to_string());
+            Ok(())
+        });
+        set("JWT_ALGORITHM", &mut |value| {
+            config.tokens.algorithm = value.to_string();
+            Ok(())
+        });
+        set("JWT_KEY_ID", &mut |value| {
+            config.tokens.key_id = Some(value.to_string());
+            Ok(())
+        });
+        set("PUBLIC_APP_URL", &mut |value| {
+            config.app.public_url = value.to_string();
+            Ok(())
+        });
+        set("MAILER", &mut |value| {
+            config.mail.transport = value.parse()?;
+            Ok(())
+        });
+        set("MAIL_FROM", &mut |value| {
+            config.mail.from = value.to_string();
+            Ok(())
+        });
+        set("MAIL_DIR", &mut |value| {
+            config.mail.dir = Some(value.to_string());
+            Ok(())
+        });
+        set("SMTP_URL", &mut |value| {
+            config.mail.smtp_url = Some(value.to_string());
+            Ok(())
+        });
+        set("WEBAUTHN_RP_ID", &mut |value| {
+            config.webauthn.rp_id = value.to_string();
+            Ok(())
+        });
+        set("WEBAUTHN_RP_NAME", &mut |value| {
+            config.webauthn.rp_name = value.to_string();
+            Ok(())
+        });
+        set("WEBAUTHN_ORIGINS", &mut |value| {
+            config.webauthn.origins = split_list(value);
+            Ok(())
+        });
+        set("GOOGLE_CLIENT_IDS", &mut |value| {
+            config.google.client_ids = split_list(value);
+            Ok(())
+        });
+        set("GITHUB_CLIENT_ID", &mut |value| {
+            config.github.client_id = value.to_string();
+            Ok(())
+        });
+        set("GITHUB_CLIENT_SECRET", &mut |value| {
+            config.github.client_secret = value.to_string();
+            Ok(())
+        });
+        set("GITHUB_TOKEN_URL", &mut |value| {
+            config.github.token_url = value.to_string();
+            Ok(())
+        });
+        set("GITHUB_API_URL", &mut |value| {
+            config.github.api_url = value.to_string();
+            Ok(())
+        });
+        let oidc_names = env("OIDC_PROVIDERS").unwrap_or_default();
+        for name in split_list(&oidc_names.to_lowercase()) {
+            let var = |key: &str| format!("OIDC_{}_{}", name.to_uppercase(), key);
+            let provider = config.oidc.entry(name.clone()).or_default();
+            set(&var("ISSUER"), &mut |value| {
+                provider.issuer = value.to_string();
+                Ok(())
+            });
+            set(&var("CLIENT_ID"), &mut |value| {
+                provider.client_id = value.to_string();
+                Ok(())
+            });
+            set(&var("CLIENT_SECRET"), &mut |value| {
+                provider.client_secret = value.to_string();
+                Ok(())
+            });
+            set(&var("SCOPES"), &mut |value| {
+                provider.scopes = value.split_whitespace().map(String::from).collect();
+                Ok(())
+            });
+        }
+
+        problems.extend(config.problems());
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.server.bind_address.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "server.bind_address {:?} is not an ip:port address",
+                self.server.bind_address
+            ));
+        }
+
+        if self.cors.allowed_origins.is_empty() {
+            problems.push("cors.allowed_origins is empty".to_string());
+        }
+        for origin in &self.cors.allowed_origins {
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && !origin.ends_with('/');
+            if !valid {
+                problems.push(format!(
+                    "cors.allowed_origins {:?} must be an http(s) origin without a trailing slash",
+                    origin
+                ));
+            }
+        }
+        for method in &self.cors.allowed_methods {
+            if !CORS_METHODS.contains(&method.as_str()) {
+                problems.push(format!(
+                    "cors.allowed_methods {:?} is not one of {}",
+                    method,
+                    CORS_METHODS.join(", ")
+                ));
+            }
+        }
+
+        if self.database.url.is_empty() {
+            problems.push("database.url is not set, use DATABASE_URL".to_string());
+        } else if let Err(e) = PgConnectOptions::from_str(&self.database.url) {
+            problems.push(format!("database.url is invalid: {}", e));
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if self.database.acquire_timeout_secs == 0 {
+            problems.push("database.acquire_timeout_secs must be at least 1".to_string());
+        }
+
+        let tokens = &self.tokens;
+        if tokens.access_token_minutes <= 0 {
+            problems.push("tokens.access_token_minutes must be positive".to_string());
+        } else if !expires_in_range(Duration::try_minutes(tokens.access_token_minutes)) {
+            problems.push("tokens.access_token_minutes is too large".to_string());
+        }
+        if tokens.refresh_token_days <= 0 {
+            problems.push("tokens.refresh_token_days must be positive".to_string());
+        } else if !expires_in_range(Duration::try_days(tokens.refresh_token_days)) {
+            problems.push("tokens.refresh_token_days is too large".to_string());
+        } else if tokens.refresh_token_days * 24 * 60 <= tokens.access_token_minutes {
+            problems.push(
+                "tokens.refresh_token_days must outlast tokens.access_token_minutes".to_string(),
+            );
+        }
+        if tokens.refresh_secret.is_empty() {
+            problems.push("tokens.refresh_secret is not set, use JWT_REFRESH_SECRET".to_string());
+        }
+        if tokens.keyring_path.is_none() {
+            match (&tokens.private_key_path, &tokens.public_key_path) {
+                (Some(_), None) => problems
+                    .push("tokens.public_key_path is not set, use JWT_PUBLIC_KEY_PATH".to_string()),
+                (Some(_), Some(_)) => {
+                    if let Err(e) = keys::parse_algorithm(&tokens.algorithm) {
+                        problems.push(format!("tokens.algorithm: {}", e));
+                    }
+                }
+                (None, _) if tokens.access_secret.is_empty() => problems.push(
+                    "tokens.access_secret is not set, use JWT_ACCESS_SECRET or a key pair"
+                        .to_string(),
+                ),
+                (None, _) => {}
+            }
+        }
+        if let Err(e) = EnvFilter::try_new(&self.logging.filter) {
+            problems.push(format!(
+                "logging.filter {:?} is invalid: {}",
+                self.logging.filter, e
+            ));
+        }
+        if self.jobs.max_session_hours <= 0 {
+            problems.push("jobs.max_session_hours must be positive".to_string());
+        }
+        if self.jobs.keep_runs_days <= 0 {
+            problems.push("jobs.keep_runs_days must be positive".to_string());
+        }
+        if self.jobs.guest_retention_days <= 0 {
+            problems.push("jobs.guest_retention_days must be positive".to_string());
+        }
+
+        match self.mail.transport {
+            MailTransport::File if self.mail.dir.is_none() => {
+                problems.push("mail.dir is not set, use MAIL_DIR".to_string())
+            }
+            MailTransport::Smtp if self.mail.smtp_url.is_none() => {
+                problems.push("mail.smtp_url is not set, use SMTP_URL".to_string())
+            }
+            _ => {}
+        }
+        for (name, provider) in &self.oidc {
+            if RESERVED_OIDC_NAMES.contains(&name.as_str()) {
+                problems.push(format!("oidc provider can't be named {}", name));
+            }
+            for (key, value) in [
+                ("issuer", &provider.issuer),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+            ] {
+                if value.is_empty() {
+                    problems.push(format!(
+                        "oidc.{}.{} is not set, use OIDC_{}_{}",
+                        name,
+                        key,
+                        name.to_uppercase(),
+                        key.to_uppercase()
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Whether tokens issued now with that lifetime get an expiry we can represent
+fn expires_in_range(lifetime: Option<Duration>) -> bool {
+    lifetime.is_some_and(|lifetime| Utc::now().checked_add_signed(lifetime).is_some())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn parse_into<T: FromStr>(value: &str, target: &mut T) -> Result<(), String> {
+    *target = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} is not a valid {}", value, std::any::type_name::<T>()))?;
+    Ok(())
+}
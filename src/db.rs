@@ -1,19 +1,84 @@
 use actix_web::web;
-use chrono::{DateTime, Utc};
-use sqlx::postgres::{PgPoolOptions, PgQueryResult};
-use sqlx::PgPool;
-use std::env;
+use chrono::{DateTime, NaiveDate, Utc};
+use log::LevelFilter;
+use rand::Rng;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgQueryResult};
+use sqlx::{ConnectOptions, PgPool};
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
 use uuid::Uuid;
 
-use crate::models::{OauthProvider, Project, User, UserPlan};
+use crate::{
+    config::DatabaseConfig,
+    models::{
+        ChallengePurpose, Device, DeviceInfo, EmailTokenPurpose, Identity, JobStatus,
+        OauthProvider, OauthUser, Passkey, PasswordCredentials, PersonalAccessToken, Project,
+        ProjectFocus, RefreshToken, Scope, Session, StoredAccessToken, StoredPasskey, StoredTotp,
+        User, UserPlan, UserSettings, WebauthnChallenge,
+    },
+    webauthn::RegisteredCredential,
+};
 
-pub async fn create_pool() -> PgPool {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .expect("Error connecting to the database")
+/// Connect to the database, retrying with backoff while it is unreachable (e.g. restarting during
+/// a deploy) for up to `startup_timeout_secs`
+pub async fn create_pool(config: &DatabaseConfig) -> Result<PgPool, sqlx::Error> {
+    let options = PgConnectOptions::from_str(&config.url)?
+        .log_statements(LevelFilter::Debug)
+        .log_slow_statements(
+            LevelFilter::Warn,
+            Duration::from_millis(config.slow_query_ms),
+        );
+    let pool_options = PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(config.idle_timeout_secs))
+        .max_lifetime(Duration::from_secs(config.max_lifetime_secs))
+        // Every acquire is logged with its wait time, which feeds the pool metrics
+        .acquire_time_level(LevelFilter::Debug);
+
+    let give_up = Instant::now() + Duration::from_secs(config.startup_timeout_secs);
+    let mut attempt = 0;
+    loop {
+        let error = match pool_options.clone().connect_with(options.clone()).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) => e,
+        };
+        let left = give_up.saturating_duration_since(Instant::now());
+        if !is_unavailable(&error) || left.is_zero() {
+            return Err(error);
+        }
+        attempt += 1;
+        let delay = backoff(attempt).min(left);
+        tracing::warn!(
+            attempt,
+            error = %error,
+            retry_in_ms = delay.as_millis() as u64,
+            "Database unavailable, retrying"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Doubles from 250ms up to 10s, with jitter so restarted replicas don't retry in lockstep
+fn backoff(attempt: u32) -> Duration {
+    let cap = Duration::from_millis(250)
+        .saturating_mul(1 << attempt.min(6))
+        .min(Duration::from_secs(10));
+    rand::thread_rng().gen_range(cap / 2..=cap)
+}
+
+/// The database can't be reached right now, as opposed to rejecting the query
+pub fn is_unavailable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => true,
+        // Connection exceptions, shutting down or starting up, too many connections
+        sqlx::Error::Database(err) => err.code().is_some_and(|code| {
+            code.starts_with("08") || code.starts_with("57P") || code == "53300"
+        }),
+        _ => false,
+    }
 }
 
 // pub async fn get_client(pool: &PgPool) -> PgPool {
@@ -28,14 +93,15 @@ pub async fn create_user(
     let oauth_provider = user.oauth_provider.map(|provider| provider.to_string());
 
     sqlx::query!(
-        "INSERT INTO users (user_id, name, email, oauth_provider, picture, user_type)
-         VALUES ($1, $2, $3, $4, $5, $6)",
+        "INSERT INTO users (user_id, name, email, oauth_provider, picture, user_type, guest)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
         user.user_id,
         user.name,
         user.email,
         oauth_provider,
         user.picture,
-        user.u_type.to_string()
+        user.u_type.to_string(),
+        user.guest
     )
     .execute(&**pool)
     .await
@@ -49,56 +115,1492 @@ pub async fn get_user(pool: web::Data<PgPool>, user_email: String) -> Result<Use
         user_email
     )
     .fetch_optional(&**pool)
-    .await;
-    if let Ok(Some(user)) = row {
-        Ok(User::new(
+    .await?;
+    let user = row.ok_or(sqlx::Error::RowNotFound)?;
+    Ok(User::new(
+        user.user_id,
+        user.name,
+        user.email,
+        user.oauth_provider
+            .and_then(|p| p.parse::<OauthProvider>().ok()),
+        user.picture,
+        UserPlan::free,
+    ))
+}
+
+pub async fn get_user_by_id(pool: web::Data<PgPool>, u_id: Uuid) -> Result<User, sqlx::Error> {
+    let user = sqlx::query!(
+        "SELECT user_id, name, email, oauth_provider, picture, guest
+         FROM users
+         WHERE user_id = $1",
+        u_id
+    )
+    .fetch_one(&**pool)
+    .await?;
+    Ok(User {
+        guest: user.guest,
+        ..User::new(
             user.user_id,
             user.name,
             user.email,
-            user.oauth_provider.map(|_p| OauthProvider::google),
+            user.oauth_provider
+                .and_then(|p| p.parse::<OauthProvider>().ok()),
             user.picture,
             UserPlan::free,
-        ))
-    } else {
-        Err(sqlx::Error::RowNotFound)
+        )
+    })
+}
+
+/// Settings of the user, RowNotFound if there is no such user
+pub async fn get_settings(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<UserSettings, sqlx::Error> {
+    sqlx::query_as!(
+        UserSettings,
+        "SELECT weekly_digest FROM users WHERE user_id = $1",
+        u_id
+    )
+    .fetch_one(&**pool)
+    .await
+}
+
+pub async fn update_settings(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    settings: UserSettings,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE users SET weekly_digest = $2 WHERE user_id = $1",
+        u_id,
+        settings.weekly_digest
+    )
+    .execute(&**pool)
+    .await
+}
+
+// guests
+
+/// Turn the guest into a regular account of the provider user, false if it isn't a guest
+pub async fn upgrade_guest(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    provider: &str,
+    o_user: &OauthUser,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        "UPDATE users SET guest = FALSE, name = $2, email = $3, picture = $4, oauth_provider = $5
+         WHERE user_id = $1 AND guest",
+        u_id,
+        o_user.name,
+        o_user.email,
+        o_user.picture,
+        provider
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
     }
+    sqlx::query!(
+        "INSERT INTO user_identities (provider, subject, user_id, email)
+         VALUES ($1, $2, $3, $4)",
+        provider,
+        o_user.sub,
+        u_id,
+        o_user.email
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
 }
 
-// tokens
+/// Move the guest's projects and sessions to an existing account and delete the guest
+/// Sessions of the guest's default project go to the account's default project
+pub async fn merge_guest(
+    pool: web::Data<PgPool>,
+    guest_id: Uuid,
+    u_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE sessions SET project_id = target.project_id
+         FROM projects AS guest_default, projects AS target
+         WHERE sessions.project_id = guest_default.project_id
+           AND guest_default.user_id = $1 AND guest_default.project_name = 'Unset'
+           AND target.user_id = $2 AND target.project_name = 'Unset'",
+        guest_id,
+        u_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM projects
+         WHERE user_id = $1 AND project_name = 'Unset'
+           AND EXISTS (SELECT 1 FROM projects WHERE user_id = $2 AND project_name = 'Unset')",
+        guest_id,
+        u_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE projects SET user_id = $2 WHERE user_id = $1",
+        guest_id,
+        u_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE sessions SET user_id = $2 WHERE user_id = $1",
+        guest_id,
+        u_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM users WHERE user_id = $1 AND guest", guest_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
 
-pub async fn store_refresh_token(
+/// Delete guests that haven't used the app for `days` days, with everything they own
+pub async fn delete_inactive_guests(
+    pool: web::Data<PgPool>,
+    days: i32,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM users
+         WHERE guest
+           AND created_at < NOW() - make_interval(days => $1)
+           AND NOT EXISTS (
+               SELECT 1 FROM refresh_tokens
+               WHERE refresh_tokens.user_id = users.user_id
+                 AND refresh_tokens.last_used_at > NOW() - make_interval(days => $1)
+           )",
+        days
+    )
+    .execute(&**pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// identities
+
+/// User the provider account is linked to
+pub async fn get_identity_user(
+    pool: web::Data<PgPool>,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT users.user_id, users.name, users.email, users.oauth_provider, users.picture,
+            users.guest
+         FROM user_identities
+         JOIN users ON users.user_id = user_identities.user_id
+         WHERE user_identities.provider = $1 AND user_identities.subject = $2",
+        provider,
+        subject
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|user| User {
+        guest: user.guest,
+        ..User::new(
+            user.user_id,
+            user.name,
+            user.email,
+            user.oauth_provider
+                .and_then(|p| p.parse::<OauthProvider>().ok()),
+            user.picture,
+            UserPlan::free,
+        )
+    }))
+}
+
+pub async fn create_identity(
     pool: web::Data<PgPool>,
     u_id: Uuid,
-    token_id: Uuid,
-    refresh_token: String,
+    provider: &str,
+    o_user: &OauthUser,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO user_identities (provider, subject, user_id, email)
+         VALUES ($1, $2, $3, $4)",
+        provider,
+        o_user.sub,
+        u_id,
+        o_user.email
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn get_identities(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Vec<Identity>, sqlx::Error> {
+    sqlx::query_as!(
+        Identity,
+        "SELECT provider, subject, email, created_at
+         FROM user_identities
+         WHERE user_id = $1
+         ORDER BY created_at",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await
+}
+
+/// Delete the identity and remember that the user unlinked one of that provider
+pub async fn delete_identity(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    provider: &str,
+    subject: &str,
+) -> Result<PgQueryResult, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        "DELETE FROM user_identities WHERE user_id = $1 AND provider = $2 AND subject = $3",
+        u_id,
+        provider,
+        subject
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() > 0 {
+        sqlx::query!(
+            "INSERT INTO identity_unlinks (user_id, provider) VALUES ($1, $2)
+             ON CONFLICT (user_id, provider) DO UPDATE SET unlinked_at = NOW()",
+            u_id,
+            provider
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Whether the user has, or had before unlinking it, an identity of the provider
+pub async fn had_identity(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    provider: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT EXISTS (
+             SELECT 1 FROM user_identities WHERE user_id = $1 AND provider = $2
+         ) OR EXISTS (
+             SELECT 1 FROM identity_unlinks WHERE user_id = $1 AND provider = $2
+         ) AS had",
+        u_id,
+        provider
+    )
+    .fetch_one(&**pool)
+    .await?;
+    Ok(row.had.unwrap_or(false))
+}
+
+// password accounts
+
+pub async fn create_password_user(
+    pool: web::Data<PgPool>,
+    user: User,
+    password_hash: String,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO users (user_id, name, email, picture, user_type, password_hash)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        user.user_id,
+        user.name,
+        user.email,
+        user.picture,
+        user.u_type.to_string(),
+        password_hash
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn get_password_credentials(
+    pool: web::Data<PgPool>,
+    user_email: &str,
+) -> Result<Option<PasswordCredentials>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT user_id, name, email, oauth_provider, picture, password_hash, email_verified_at
+         FROM users
+         WHERE email = $1",
+        user_email
+    )
+    .fetch_optional(&**pool)
+    .await?;
+
+    Ok(row.map(|row| PasswordCredentials {
+        user: User::new(
+            row.user_id,
+            row.name,
+            row.email,
+            row.oauth_provider
+                .and_then(|p| p.parse::<OauthProvider>().ok()),
+            row.picture,
+            UserPlan::free,
+        ),
+        password_hash: row.password_hash,
+        email_verified: row.email_verified_at.is_some(),
+    }))
+}
+
+pub async fn get_password_hash(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!("SELECT password_hash FROM users WHERE user_id = $1", u_id)
+        .fetch_optional(&**pool)
+        .await?;
+    Ok(row.and_then(|row| row.password_hash))
+}
+
+pub async fn set_password(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    password_hash: String,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE users SET password_hash = $2 WHERE user_id = $1",
+        u_id,
+        password_hash
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn mark_email_verified(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW())
+         WHERE user_id = $1",
+        u_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// Drop the password of an account whose email was never verified
+/// Whoever registered it didn't prove they own the email, the provider login just did
+pub async fn drop_unverified_password(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE users SET password_hash = NULL
+         WHERE user_id = $1 AND email_verified_at IS NULL",
+        u_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn store_email_token(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    token_hash: String,
+    purpose: EmailTokenPurpose,
     expires_at: DateTime<Utc>,
 ) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(
-        "INSERT INTO refresh_tokens (token_id, user_id, refresh_token, expires_at )
+        "INSERT INTO email_tokens (token_hash, user_id, purpose, expires_at)
          VALUES ($1, $2, $3, $4)",
-        token_id,
+        token_hash,
         u_id,
-        refresh_token,
+        purpose.as_str(),
         expires_at
     )
     .execute(&**pool)
     .await
 }
 
-pub async fn add_project(
+/// Use up an unexpired email token, returns the user it was sent to
+/// Every other token of the user for the same purpose goes with it
+pub async fn consume_email_token(
     pool: web::Data<PgPool>,
-    project: Project,
+    token_hash: String,
+    purpose: EmailTokenPurpose,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "DELETE FROM email_tokens
+         WHERE purpose = $2 AND user_id = (
+             SELECT user_id FROM email_tokens
+             WHERE token_hash = $1 AND purpose = $2 AND expires_at > NOW()
+         )
+         RETURNING user_id",
+        token_hash,
+        purpose.as_str()
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows.first().map(|row| row.user_id))
+}
+
+// login codes
+
+/// First key of the advisory locks taken per email for password emails
+const PASSWORD_EMAIL_LOCK_NAMESPACE: i32 = 0x70617373;
+
+/// Count a request for a password reset or verification email to the address, false once
+/// `per_hour` were made in the last hour
+pub async fn record_password_email_request(
+    pool: web::Data<PgPool>,
+    email: &str,
+    per_hour: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Requests for the same email wait for each other so they can't both slip under the limit
+    sqlx::query!(
+        "SELECT 1 AS locked FROM pg_advisory_xact_lock($1, hashtext($2))",
+        PASSWORD_EMAIL_LOCK_NAMESPACE,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let requests = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "requests!" FROM password_email_requests
+           WHERE email = $1 AND requested_at > NOW() - INTERVAL '1 hour'"#,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if requests >= per_hour {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "DELETE FROM password_email_requests
+         WHERE email = $1 AND requested_at <= NOW() - INTERVAL '1 hour'",
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO password_email_requests (request_id, email) VALUES ($1, $2)",
+        Uuid::new_v4(),
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// First key of the advisory locks taken per email for login codes
+const LOGIN_CODE_LOCK_NAMESPACE: i32 = 0x636f6465;
+
+/// Store a new login code for the email, any earlier one stops working
+/// Refused while the current code is locked after `max_attempts` misses, or once `per_hour` codes
+/// were sent in the last hour
+pub async fn replace_login_code(
+    pool: web::Data<PgPool>,
+    email: &str,
+    code_hash: String,
+    link_hash: String,
+    expires_at: DateTime<Utc>,
+    max_attempts: i32,
+    per_hour: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Requests for the same email wait for each other so they can't both slip under the limit
+    sqlx::query!(
+        "SELECT 1 AS locked FROM pg_advisory_xact_lock($1, hashtext($2))",
+        LOGIN_CODE_LOCK_NAMESPACE,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let row = sqlx::query!(
+        r#"SELECT
+             EXISTS (
+                 SELECT 1 FROM login_codes
+                 WHERE email = $1 AND expires_at > NOW() AND attempts >= $2
+             ) AS "locked!",
+             (
+                 SELECT COUNT(*) FROM login_code_requests
+                 WHERE email = $1 AND requested_at > NOW() - INTERVAL '1 hour'
+             ) AS "requests!""#,
+        email,
+        max_attempts
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if row.locked || row.requests >= per_hour {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "DELETE FROM login_code_requests
+         WHERE email = $1 AND requested_at <= NOW() - INTERVAL '1 hour'",
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO login_code_requests (request_id, email) VALUES ($1, $2)",
+        Uuid::new_v4(),
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM login_codes WHERE email = $1", email)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO login_codes (code_id, email, code_hash, link_hash, expires_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        Uuid::new_v4(),
+        email,
+        code_hash,
+        link_hash,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Use up the login code of the email if it matches
+/// Every miss counts, after `max_attempts` of them the code is locked until it expires
+pub async fn use_login_code(
+    pool: web::Data<PgPool>,
+    email: &str,
+    code_hash: &str,
+    max_attempts: i32,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query!(
+        "SELECT code_id, code_hash FROM login_codes
+         WHERE email = $1 AND expires_at > NOW() AND attempts < $2
+         FOR UPDATE",
+        email,
+        max_attempts
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(row) = row else {
+        return Ok(false);
+    };
+
+    let matches = row.code_hash == code_hash;
+    if matches {
+        sqlx::query!("DELETE FROM login_codes WHERE code_id = $1", row.code_id)
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        sqlx::query!(
+            "UPDATE login_codes SET attempts = attempts + 1 WHERE code_id = $1",
+            row.code_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(matches)
+}
+
+/// Use up the login code the link belongs to, returns its email
+pub async fn use_login_link(
+    pool: web::Data<PgPool>,
+    link_hash: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        "DELETE FROM login_codes WHERE link_hash = $1 AND expires_at > NOW() RETURNING email",
+        link_hash
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|row| row.email))
+}
+
+// two factor
+
+pub async fn get_totp(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Option<StoredTotp>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT secret, enabled_at, locked_until FROM user_totp WHERE user_id = $1",
+        u_id
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|row| StoredTotp {
+        secret: row.secret,
+        enabled: row.enabled_at.is_some(),
+        locked_until: row.locked_until,
+    }))
+}
+
+/// Start (or restart) an enrolment, an enabled TOTP is left alone
+pub async fn set_pending_totp(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    secret: String,
 ) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(
-        "INSERT INTO projects (project_id, user_id, project_name, colour, deadline, priority)
-         VALUES ($1, $2, $3, $4, $5, $6)",
-        project.project_id,
-        project.user_id,
-        project.project_name,
-        project.colour,
-        project.deadline,
-        project.priority,
+        "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2)
+         ON CONFLICT (user_id) DO UPDATE
+         SET secret = EXCLUDED.secret, last_used_step = NULL, failed_attempts = 0
+         WHERE user_totp.enabled_at IS NULL",
+        u_id,
+        secret
     )
     .execute(&**pool)
     .await
 }
+
+pub async fn enable_totp(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    recovery_code_hashes: Vec<String>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE user_totp SET enabled_at = NOW() WHERE user_id = $1",
+        u_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    replace_recovery_codes_in(&mut tx, u_id, recovery_code_hashes).await?;
+    tx.commit().await
+}
+
+pub async fn disable_totp(pool: web::Data<PgPool>, u_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", u_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = $1", u_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Accept a code of the given time step, false if that step or a later one was already used
+pub async fn record_totp_use(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    step: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE user_totp SET last_used_step = $2, failed_attempts = 0
+         WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+        u_id,
+        step
+    )
+    .execute(&**pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Count a wrong code, after `max_attempts` in a row the second step is locked until `lock_until`
+pub async fn record_totp_failure(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    max_attempts: i32,
+    lock_until: DateTime<Utc>,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE user_totp
+         SET failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0
+                                    ELSE failed_attempts + 1 END,
+             locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $3
+                                 ELSE locked_until END
+         WHERE user_id = $1",
+        u_id,
+        max_attempts,
+        lock_until
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn use_recovery_code(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    code_hash: String,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash = $2",
+        u_id,
+        code_hash
+    )
+    .execute(&**pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn replace_recovery_codes(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    recovery_code_hashes: Vec<String>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    replace_recovery_codes_in(&mut tx, u_id, recovery_code_hashes).await?;
+    tx.commit().await
+}
+
+async fn replace_recovery_codes_in(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    u_id: Uuid,
+    recovery_code_hashes: Vec<String>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = $1", u_id)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO totp_recovery_codes (code_hash, user_id)
+         SELECT UNNEST($2::VARCHAR[]), $1",
+        u_id,
+        &recovery_code_hashes
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+// passkeys
+
+pub async fn store_webauthn_challenge(
+    pool: web::Data<PgPool>,
+    challenge_id: Uuid,
+    u_id: Option<Uuid>,
+    challenge: &str,
+    purpose: ChallengePurpose,
+    expires_at: DateTime<Utc>,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO webauthn_challenges (challenge_id, user_id, challenge, purpose, expires_at)
+         VALUES ($1, $2, $3, $4, $5)",
+        challenge_id,
+        u_id,
+        challenge,
+        purpose.as_str(),
+        expires_at
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// Use up an unexpired challenge, each one can only be answered once
+pub async fn take_webauthn_challenge(
+    pool: web::Data<PgPool>,
+    challenge_id: Uuid,
+    purpose: ChallengePurpose,
+) -> Result<Option<WebauthnChallenge>, sqlx::Error> {
+    let row = sqlx::query!(
+        "DELETE FROM webauthn_challenges
+         WHERE challenge_id = $1 AND purpose = $2 AND expires_at > NOW()
+         RETURNING user_id, challenge",
+        challenge_id,
+        purpose.as_str()
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|row| WebauthnChallenge {
+        user_id: row.user_id,
+        challenge: row.challenge,
+    }))
+}
+
+pub async fn create_passkey(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    passkey_id: Uuid,
+    credential_id: String,
+    credential: &RegisteredCredential,
+    name: &str,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO webauthn_credentials
+         (passkey_id, user_id, credential_id, public_key, algorithm, sign_count, name)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        passkey_id,
+        u_id,
+        credential_id,
+        credential.public_key,
+        credential.algorithm as i32,
+        i64::from(credential.sign_count),
+        name
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn get_passkeys(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Vec<Passkey>, sqlx::Error> {
+    sqlx::query_as!(
+        Passkey,
+        "SELECT passkey_id, name, created_at, last_used_at
+         FROM webauthn_credentials
+         WHERE user_id = $1
+         ORDER BY created_at",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await
+}
+
+/// Credential ids of the user's passkeys, base64url
+pub async fn get_passkey_credential_ids(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT credential_id FROM webauthn_credentials WHERE user_id = $1",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows.into_iter().map(|row| row.credential_id).collect())
+}
+
+pub async fn get_passkey_by_credential_id(
+    pool: web::Data<PgPool>,
+    credential_id: &str,
+) -> Result<Option<StoredPasskey>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT passkey_id, user_id, public_key, algorithm, sign_count
+         FROM webauthn_credentials
+         WHERE credential_id = $1",
+        credential_id
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|row| StoredPasskey {
+        passkey_id: row.passkey_id,
+        user_id: row.user_id,
+        public_key: row.public_key,
+        algorithm: i64::from(row.algorithm),
+        sign_count: row.sign_count as u32,
+    }))
+}
+
+/// Store the new sign counter, false if another login got in first
+pub async fn use_passkey(
+    pool: web::Data<PgPool>,
+    passkey_id: Uuid,
+    old_sign_count: u32,
+    new_sign_count: u32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE webauthn_credentials SET sign_count = $3, last_used_at = NOW()
+         WHERE passkey_id = $1 AND sign_count = $2",
+        passkey_id,
+        i64::from(old_sign_count),
+        i64::from(new_sign_count)
+    )
+    .execute(&**pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn delete_passkey(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    passkey_id: Uuid,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM webauthn_credentials WHERE user_id = $1 AND passkey_id = $2",
+        u_id,
+        passkey_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+// tokens
+
+pub async fn store_refresh_token(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    token_id: Uuid,
+    family_id: Uuid,
+    refresh_token: String,
+    expires_at: DateTime<Utc>,
+    device: DeviceInfo,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (token_id, user_id, family_id, refresh_token, expires_at,
+                                     device_name, platform, app_version, ip_address)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        token_id,
+        u_id,
+        family_id,
+        refresh_token,
+        expires_at,
+        device.device_name,
+        device.platform,
+        device.app_version,
+        device.ip_address
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn get_refresh_token(
+    pool: web::Data<PgPool>,
+    token_id: Uuid,
+) -> Result<RefreshToken, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT user_id, family_id, refresh_token, rotated_at,
+                device_name, platform, app_version, ip_address
+         FROM refresh_tokens
+         WHERE token_id = $1",
+        token_id
+    )
+    .fetch_one(&**pool)
+    .await?;
+    Ok(RefreshToken {
+        user_id: row.user_id,
+        family_id: row.family_id,
+        refresh_token: row.refresh_token,
+        rotated_at: row.rotated_at,
+        device: DeviceInfo {
+            device_name: row.device_name,
+            platform: row.platform,
+            app_version: row.app_version,
+            ip_address: row.ip_address,
+        },
+    })
+}
+
+/// Signed in devices of the user, the live token of every login
+pub async fn get_devices(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    current_family: Option<Uuid>,
+) -> Result<Vec<Device>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT t.family_id, t.device_name, t.platform, t.app_version, t.ip_address,
+                t.last_used_at,
+                (SELECT MIN(f.created_at) FROM refresh_tokens f
+                 WHERE f.family_id = t.family_id) AS signed_in_at
+         FROM refresh_tokens t
+         WHERE t.user_id = $1 AND t.rotated_at IS NULL AND t.expires_at > NOW()
+         ORDER BY t.last_used_at DESC",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Device {
+            device_id: row.family_id,
+            device_name: row.device_name,
+            platform: row.platform,
+            app_version: row.app_version,
+            ip_address: row.ip_address,
+            signed_in_at: row.signed_in_at,
+            last_used_at: row.last_used_at,
+            current: Some(row.family_id) == current_family,
+        })
+        .collect())
+}
+
+/// Whether the refresh token family belongs to the user
+pub async fn owns_token_family(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    family_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT EXISTS (
+             SELECT 1 FROM refresh_tokens WHERE family_id = $1 AND user_id = $2
+         ) AS owned",
+        family_id,
+        u_id
+    )
+    .fetch_one(&**pool)
+    .await?;
+    Ok(row.owned.unwrap_or(false))
+}
+
+/// Marks the token as used, only succeeds (1 row affected) for the first caller
+pub async fn rotate_refresh_token(
+    pool: web::Data<PgPool>,
+    token_id: Uuid,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET rotated_at = NOW()
+         WHERE token_id = $1 AND rotated_at IS NULL",
+        token_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// Deletes every refresh token issued from the same login and puts the family on the revocation
+/// list until the last access token issued from it has expired
+pub async fn revoke_token_family(
+    pool: web::Data<PgPool>,
+    family_id: Uuid,
+    access_valid_until: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO revoked_token_families (family_id, expires_at)
+         VALUES ($1, $2)
+         ON CONFLICT (family_id) DO UPDATE SET expires_at = EXCLUDED.expires_at",
+        family_id,
+        access_valid_until
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM refresh_tokens WHERE family_id = $1", family_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Revokes every login of the user but `keep_family`
+pub async fn revoke_user_tokens(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    keep_family: Option<Uuid>,
+    access_valid_until: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO revoked_token_families (family_id, expires_at)
+         SELECT DISTINCT family_id, $3::TIMESTAMPTZ FROM refresh_tokens
+         WHERE user_id = $1 AND family_id IS DISTINCT FROM $2
+         ON CONFLICT (family_id) DO UPDATE SET expires_at = EXCLUDED.expires_at",
+        u_id,
+        keep_family,
+        access_valid_until
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE user_id = $1 AND family_id IS DISTINCT FROM $2",
+        u_id,
+        keep_family
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Delete refresh tokens past their expiry and revocations of access tokens that have expired,
+/// returns how many rows were deleted
+pub async fn delete_expired_tokens(pool: web::Data<PgPool>) -> Result<u64, sqlx::Error> {
+    let tokens = sqlx::query!("DELETE FROM refresh_tokens WHERE expires_at < NOW()")
+        .execute(&**pool)
+        .await?;
+    let families = sqlx::query!("DELETE FROM revoked_token_families WHERE expires_at < NOW()")
+        .execute(&**pool)
+        .await?;
+    Ok(tokens.rows_affected() + families.rows_affected())
+}
+
+pub async fn is_token_family_revoked(pool: &PgPool, family_id: Uuid) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT EXISTS (SELECT 1 FROM revoked_token_families WHERE family_id = $1) AS revoked",
+        family_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.revoked.unwrap_or(false))
+}
+
+// personal access tokens
+
+pub async fn create_access_token(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    token: &PersonalAccessToken,
+    token_hash: String,
+) -> Result<PgQueryResult, sqlx::Error> {
+    let scopes: Vec<String> = token.scopes.iter().map(|s| s.to_string()).collect();
+    sqlx::query!(
+        "INSERT INTO personal_access_tokens
+             (token_id, user_id, name, token_hash, scopes, expires_at, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        token.token_id,
+        u_id,
+        token.name,
+        token_hash,
+        &scopes,
+        token.expires_at,
+        token.created_at
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn get_access_tokens(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Vec<PersonalAccessToken>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT token_id, name, scopes, expires_at, last_used_at, created_at
+         FROM personal_access_tokens
+         WHERE user_id = $1
+         ORDER BY created_at DESC",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| PersonalAccessToken {
+            token_id: row.token_id,
+            name: row.name,
+            scopes: parse_scopes(&row.scopes),
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            created_at: row.created_at,
+        })
+        .collect())
+}
+
+/// Token a request authenticates with, the caller checks the secret and expiry
+pub async fn get_access_token(
+    pool: &PgPool,
+    token_id: Uuid,
+) -> Result<Option<StoredAccessToken>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT user_id, token_hash, scopes, expires_at FROM personal_access_tokens
+         WHERE token_id = $1",
+        token_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| StoredAccessToken {
+        user_id: row.user_id,
+        token_hash: row.token_hash,
+        scopes: parse_scopes(&row.scopes),
+        expires_at: row.expires_at,
+    }))
+}
+
+pub async fn record_access_token_use(pool: &PgPool, token_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE personal_access_tokens SET last_used_at = NOW() WHERE token_id = $1",
+        token_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_access_token(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    token_id: Uuid,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM personal_access_tokens WHERE token_id = $1 AND user_id = $2",
+        token_id,
+        u_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+fn parse_scopes(scopes: &[String]) -> Vec<Scope> {
+    scopes.iter().filter_map(|s| s.parse().ok()).collect()
+}
+
+// projects
+
+pub async fn add_project(
+    pool: web::Data<PgPool>,
+    project: Project,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO projects (project_id, user_id, project_name, colour, deadline, priority)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        project.project_id,
+        project.user_id,
+        project.project_name,
+        project.colour,
+        project.deadline,
+        project.priority,
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// Only updates a project of `project.user_id`
+pub async fn update_project(
+    pool: web::Data<PgPool>,
+    project: Project,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE projects SET project_name = $1, colour = $2, deadline = $3, priority = $4
+         WHERE user_id = $5 AND project_id = $6",
+        project.project_name,
+        project.colour,
+        project.deadline,
+        project.priority,
+        project.user_id,
+        project.project_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn delete_project(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    project_id: Uuid,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM projects WHERE project_id = $1 AND user_id = $2",
+        project_id,
+        u_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+pub async fn get_projects(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Vec<Project>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT project_id, user_id, project_name, colour, deadline, priority
+         FROM projects
+         WHERE user_id = $1",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Project {
+            project_id: row.project_id,
+            user_id: row.user_id,
+            project_name: row.project_name,
+            colour: row.colour,
+            deadline: row.deadline,
+            priority: row.priority,
+        })
+        .collect())
+}
+
+// sessions
+
+/// Inserts nothing (0 rows affected) unless the project is one of `session.user_id`'s
+pub async fn add_session(
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO sessions (session_id, user_id, project_id, started_at, ended_at, duration)
+         SELECT $1, $2, $3, $4, $5, $6
+         WHERE EXISTS (SELECT 1 FROM projects WHERE project_id = $3 AND user_id = $2)",
+        session.session_id,
+        session.user_id,
+        session.project_id,
+        session.started_at,
+        session.ended_at,
+        session.duration
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// Only updates a session of `session.user_id`
+pub async fn update_session(
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE sessions SET ended_at = $1, duration = $2
+         WHERE user_id = $3 AND session_id = $4",
+        session.ended_at,
+        session.duration,
+        session.user_id,
+        session.session_id
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// A session of the user that hasn't ended yet
+pub async fn get_active_session(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Option<Session>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT session_id, user_id, project_id, started_at, ended_at, duration
+         FROM sessions
+         WHERE user_id = $1 AND ended_at IS NULL
+         LIMIT 1",
+        u_id
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|row| {
+        Session::new(
+            row.session_id,
+            row.user_id,
+            row.project_id,
+            row.started_at,
+            row.ended_at,
+            row.duration,
+        )
+    }))
+}
+
+pub async fn get_sessions(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+) -> Result<Vec<Session>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT session_id, user_id, project_id, started_at, ended_at, duration
+         FROM sessions
+         WHERE user_id = $1",
+        u_id
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            Session::new(
+                row.session_id,
+                row.user_id,
+                row.project_id,
+                row.started_at,
+                row.ended_at,
+                row.duration,
+            )
+        })
+        .collect())
+}
+
+/// Seconds of the sessions the user started on `day`
+pub async fn get_focus_time_on(
+    pool: web::Data<PgPool>,
+    u_id: Uuid,
+    day: NaiveDate,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT COALESCE(SUM(duration), 0)::BIGINT AS "seconds!"
+           FROM sessions
+           WHERE user_id = $1 AND DATE(started_at) = $2"#,
+        u_id,
+        day
+    )
+    .fetch_one(&**pool)
+    .await?;
+    Ok(row.seconds)
+}
+
+/// End sessions left running for more than `max_hours`, as if they were stopped at that point
+pub async fn stop_runaway_sessions(
+    pool: web::Data<PgPool>,
+    max_hours: i32,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE sessions
+         SET ended_at = started_at + make_interval(hours => $1), duration = $1 * 60 * 60
+         WHERE ended_at IS NULL AND started_at < NOW() - make_interval(hours => $1)",
+        max_hours
+    )
+    .execute(&**pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Focus time per project of every user who asked for the digest and finished a session since
+/// `since`
+/// Only proven emails: verified ones, or those of accounts without a password, which only a
+/// provider login or an emailed code can create
+pub async fn get_focus_since(
+    pool: web::Data<PgPool>,
+    since: DateTime<Utc>,
+) -> Result<Vec<ProjectFocus>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT users.user_id, users.name, users.email, projects.project_name,
+                  SUM(sessions.duration)::BIGINT AS "seconds!"
+           FROM sessions
+           JOIN users ON users.user_id = sessions.user_id
+           JOIN projects ON projects.project_id = sessions.project_id
+           WHERE users.weekly_digest AND NOT users.guest
+             AND (users.email_verified_at IS NOT NULL OR users.password_hash IS NULL)
+             AND sessions.ended_at IS NOT NULL AND sessions.started_at >= $1
+           GROUP BY users.user_id, projects.project_id
+           ORDER BY users.user_id, 5 DESC, projects.project_name"#,
+        since
+    )
+    .fetch_all(&**pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| ProjectFocus {
+            user_id: row.user_id,
+            name: row.name,
+            email: row.email,
+            project_name: row.project_name,
+            seconds: row.seconds,
+        })
+        .collect())
+}
+
+// jobs
+
+/// Record the start of a scheduled run, `None` if another replica already took it
+pub async fn claim_job_run(
+    pool: web::Data<PgPool>,
+    job: &str,
+    scheduled_for: DateTime<Utc>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        "INSERT INTO job_runs (run_id, job, scheduled_for, status)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (job, scheduled_for) DO NOTHING
+         RETURNING run_id",
+        Uuid::new_v4(),
+        job,
+        scheduled_for,
+        JobStatus::Running.to_string()
+    )
+    .fetch_optional(&**pool)
+    .await?;
+    Ok(row.map(|row| row.run_id))
+}
+
+pub async fn finish_job_run(
+    pool: web::Data<PgPool>,
+    run_id: Uuid,
+    status: JobStatus,
+    detail: &str,
+) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE job_runs SET finished_at = NOW(), status = $2, detail = $3 WHERE run_id = $1",
+        run_id,
+        status.to_string(),
+        detail
+    )
+    .execute(&**pool)
+    .await
+}
+
+/// Forget runs older than `days` days, returns how many were deleted
+pub async fn delete_old_job_runs(pool: web::Data<PgPool>, days: i32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM job_runs WHERE started_at < NOW() - make_interval(days => $1)",
+        days
+    )
+    .execute(&**pool)
+    .await?;
+    Ok(result.rows_affected())
+}
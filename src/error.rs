@@ -0,0 +1,189 @@
+use std::fmt::Display;
+
+use actix_web::{
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{config::UNIQUE_VIOLATION, db, oauth::OauthError, repo::RepoError};
+
+/// Sent with 503s, about how long the database takes to come back from a restart
+const RETRY_AFTER_SECS: u32 = 5;
+
+/// Error of a request, rendered as `{"code", "message", "details"}`
+/// `code` is stable for clients to match on, `message` is for people
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is malformed or a value is out of range
+    Validation(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    TooManyRequests(String),
+    /// An identity provider couldn't be reached or answered nonsense, the message is logged
+    BadGateway(String),
+    /// The database is down or overloaded, clients should retry, the message is logged
+    Unavailable(String),
+    /// Bug or failure on our side, the message is logged but not sent
+    Internal(String),
+    Database(sqlx::Error),
+    /// Any of the above with machine readable details
+    WithDetails(Box<ApiError>, Value),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+    details: Option<&'a Value>,
+}
+
+impl ApiError {
+    pub fn with_details(self, details: Value) -> Self {
+        ApiError::WithDetails(Box::new(self), details)
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::BadGateway(_) => "bad_gateway",
+            ApiError::Unavailable(_) => "service_unavailable",
+            ApiError::Internal(_) | ApiError::Database(_) => "internal_error",
+            ApiError::WithDetails(error, _) => error.code(),
+        }
+    }
+
+    /// Message sent to the client, internal failures stay vague
+    fn public_message(&self) -> String {
+        match self {
+            ApiError::Validation(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::TooManyRequests(message) => message.clone(),
+            ApiError::BadGateway(_) => "Identity provider unavailable".to_string(),
+            ApiError::Unavailable(_) => "Temporarily unavailable, try again shortly".to_string(),
+            ApiError::Internal(_) | ApiError::Database(_) => "Internal server error".to_string(),
+            ApiError::WithDetails(error, _) => error.public_message(),
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Database(e) => write!(f, "database: {}", e),
+            ApiError::Internal(message) => write!(f, "internal: {}", message),
+            ApiError::BadGateway(message) => write!(f, "bad gateway: {}", message),
+            ApiError::Unavailable(message) => write!(f, "unavailable: {}", message),
+            ApiError::WithDetails(error, _) => error.fmt(f),
+            _ => write!(f, "{}: {}", self.code(), self.public_message()),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) | ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::WithDetails(error, _) => error.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            tracing::error!(error = %self, "Request failed");
+        }
+        let details = match self {
+            ApiError::WithDetails(_, details) => Some(details),
+            _ => None,
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::SERVICE_UNAVAILABLE {
+            response.insert_header((RETRY_AFTER, RETRY_AFTER_SECS));
+        }
+        response.json(ErrorBody {
+            code: self.code(),
+            message: self.public_message(),
+            details,
+        })
+    }
+}
+
+/// Missing rows are 404s, unique violations 409s and an unreachable database 503s, anything else
+/// is our problem
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => ApiError::NotFound("Not found".to_string()),
+            sqlx::Error::Database(err) if err.code() == Some(UNIQUE_VIOLATION.into()) => {
+                let details = json!({ "constraint": err.constraint() });
+                ApiError::Conflict("Already exists".to_string()).with_details(details)
+            }
+            e if db::is_unavailable(&e) => ApiError::Unavailable(e.to_string()),
+            e => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<RepoError> for ApiError {
+    fn from(e: RepoError) -> Self {
+        match e {
+            RepoError::Conflict => ApiError::Conflict("Already exists".to_string()),
+            RepoError::NotFound => ApiError::NotFound("Not found".to_string()),
+            RepoError::Database(e) => e.into(),
+        }
+    }
+}
+
+impl From<OauthError> for ApiError {
+    fn from(e: OauthError) -> Self {
+        match e {
+            OauthError::InvalidToken(_) => ApiError::Unauthorized("Invalid token".to_string()),
+            OauthError::Provider(message) => ApiError::BadGateway(message),
+            OauthError::Unavailable(message) => ApiError::Unavailable(message),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<bcrypt::BcryptError> for ApiError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+/// Bodies, paths and queries that don't deserialize fail like any other invalid input
+pub fn extractor_error<E: std::error::Error>(e: E, _: &HttpRequest) -> actix_web::Error {
+    ApiError::Validation(e.to_string()).into()
+}
+
+/// Map a unique violation to a conflict with a specific message, other errors as usual
+pub fn conflict_on_unique<E: Into<RepoError>>(message: &str) -> impl Fn(E) -> ApiError + '_ {
+    move |e| match e.into() {
+        RepoError::Conflict => ApiError::Conflict(message.to_string()),
+        e => e.into(),
+    }
+}
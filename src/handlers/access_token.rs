@@ -0,0 +1,122 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    error::ApiError,
+    handlers::AuthUser,
+    models::{CreateAccessToken, CreatedAccessToken, PersonalAccessToken},
+    repo::Repos,
+};
+
+pub const ACCESS_TOKEN_PREFIX: &str = "kairos_pat_";
+
+/// Personal access tokens look like `kairos_pat_<token id>_<secret>`, the id is used to find the
+/// row, only the SHA-256 of the secret is stored
+pub fn parse_access_token(token: &str) -> Option<(Uuid, &str)> {
+    let (token_id, secret) = token.strip_prefix(ACCESS_TOKEN_PREFIX)?.split_once('_')?;
+    Some((Uuid::try_parse(token_id).ok()?, secret))
+}
+
+/// Secrets are long and random, a fast hash is enough to keep them from leaking with the database
+pub fn hash_token_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Compares in constant time so the response time doesn't tell how much of the hash matched
+pub fn token_secret_matches(secret: &str, token_hash: &str) -> bool {
+    let hash = hash_token_secret(secret);
+    hash.len() == token_hash.len()
+        && hash
+            .bytes()
+            .zip(token_hash.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Create a personal access token, the token is only returned in this response
+pub async fn create_access_token(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<CreateAccessToken>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let name = request.name.trim().to_string();
+    if name.is_empty() || name.len() > 255 {
+        return Err(ApiError::Validation(
+            "Name must be 1 to 255 characters".to_string(),
+        ));
+    }
+    if request.scopes.is_empty() {
+        return Err(ApiError::Validation(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    if request
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(ApiError::Validation(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let mut scopes = Vec::new();
+    for scope in request.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let access_token = PersonalAccessToken {
+        token_id: Uuid::new_v4(),
+        name,
+        scopes,
+        expires_at: request.expires_at,
+        last_used_at: None,
+        created_at: Utc::now(),
+    };
+    let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 40);
+    let token = format!(
+        "{}{}_{}",
+        ACCESS_TOKEN_PREFIX,
+        access_token.token_id.simple(),
+        secret
+    );
+
+    let hash = hash_token_secret(&secret);
+    repos
+        .access_tokens
+        .create_access_token(user.user_id, &access_token, hash)
+        .await?;
+    Ok(HttpResponse::Ok().json(CreatedAccessToken {
+        access_token,
+        token,
+    }))
+}
+
+/// Get the user's personal access tokens
+pub async fn get_access_tokens(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let tokens = repos.access_tokens.get_access_tokens(user.user_id).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Revoke a personal access token
+pub async fn revoke_access_token(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    token_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    if !repos
+        .access_tokens
+        .delete_access_token(user.user_id, token_id.into_inner())
+        .await?
+    {
+        return Err(ApiError::NotFound("Access token not found".to_string()));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,71 +1,303 @@
+use std::future::{ready, Ready};
+
 use actix_web::{
     body::MessageBody,
-    dev::{ServiceRequest, ServiceResponse},
+    dev::{Payload, ServiceRequest, ServiceResponse},
     middleware::Next,
-    Error, HttpMessage,
+    web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
 };
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use jsonwebtoken::{DecodingKey, Validation};
-use serde_json::Value;
 use uuid::Uuid;
 
 use crate::{
-    config::{jwt_access_secret, jwt_refresh_secret},
-    models::{Claims, OauthUser, TokenResponse},
+    config::{Config, TokenConfig},
+    error::ApiError,
+    handlers::{parse_access_token, token_secret_matches, ACCESS_TOKEN_PREFIX},
+    keys::KeyRing,
+    metrics::Metrics,
+    models::{Claims, DeviceInfo, RefreshRequest, Scope, TokenResponse},
+    repo::Repos,
+    routes::required_scopes,
 };
 
-pub fn create_jwt_tokens(user_id: &Uuid) -> Result<TokenResponse, jsonwebtoken::errors::Error> {
+/// User the access token was issued to, put into the request extensions by `jwt_middleware`
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    /// Login (refresh token family) the access token was issued for, `None` for personal access
+    /// tokens
+    pub family_id: Option<Uuid>,
+}
+
+impl AuthUser {
+    /// Forbidden unless the resource belonging to `user_id` is this user's
+    pub fn check_owns(&self, user_id: &Uuid) -> Result<(), ApiError> {
+        if &self.user_id == user_id {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(
+                "Resource belongs to another user".to_string(),
+            ))
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthUser>()
+                .copied()
+                .ok_or_else(|| ApiError::Unauthorized("Missing or invalid token".to_string())),
+        )
+    }
+}
+
+/// Device details sent by the apps as `X-Device-Name`, `X-Device-Platform` and `X-App-Version`
+impl FromRequest for DeviceInfo {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let header = |name: &str, max_len: usize| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().chars().take(max_len).collect::<String>())
+                .filter(|value| !value.is_empty())
+        };
+        ready(Ok(DeviceInfo {
+            device_name: header("X-Device-Name", 255),
+            platform: header("X-Device-Platform", 50),
+            app_version: header("X-App-Version", 50),
+            // Only shown to the user, so trusting forwarding headers is fine here
+            ip_address: req
+                .connection_info()
+                .realip_remote_addr()
+                .map(|ip| ip.chars().take(64).collect()),
+        }))
+    }
+}
+
+pub fn create_jwt_tokens(
+    keys: &KeyRing,
+    tokens: &TokenConfig,
+    user_id: &Uuid,
+    family_id: &Uuid,
+) -> Result<TokenResponse, jsonwebtoken::errors::Error> {
     // (Refresh token rotation)
     // Create new Refresh and Access token everytime there's a need to create new access token
-    let a_expiry = Utc::now()
-        .checked_add_signed(Duration::hours(1))
-        .expect("valid timestamp")
-        .timestamp() as usize;
-
-    let r_expiry = Utc::now()
-        .checked_add_signed(Duration::days(7))
-        .expect("valid timestamp");
+    let token_id = Uuid::new_v4();
+    let a_expiry = tokens.access_token_expiry().timestamp() as usize;
+    let r_expiry = tokens.refresh_token_expiry();
 
     let a_claims = Claims {
         sub: user_id.to_string(),
         exp: a_expiry,
+        jti: token_id.to_string(),
+        fam: family_id.to_string(),
     };
 
     let r_claims = Claims {
         sub: user_id.to_string(),
         exp: r_expiry.timestamp() as usize,
+        jti: token_id.to_string(),
+        fam: family_id.to_string(),
     };
 
-    let header = jsonwebtoken::Header::default();
-
-    let access_token = jsonwebtoken::encode(
-        &header,
-        &a_claims,
-        &jsonwebtoken::EncodingKey::from_secret(&jwt_access_secret()),
-    )?;
+    let access_token = keys.sign(&a_claims)?;
 
+    // Refresh tokens are only ever verified by us, so they stay on the shared secret
     let refresh_token = jsonwebtoken::encode(
-        &header,
+        &jsonwebtoken::Header::default(),
         &r_claims,
-        &jsonwebtoken::EncodingKey::from_secret(&jwt_refresh_secret()),
+        &jsonwebtoken::EncodingKey::from_secret(tokens.refresh_secret.as_bytes()),
     )?;
     let res = TokenResponse {
         access_token,
         refresh_token,
         expiry: r_expiry,
+        token_id,
+        family_id: *family_id,
     };
     Ok(res)
 }
 
+/// Create a new token pair in the given family and store the hashed refresh token
+pub async fn issue_tokens(
+    repos: &Repos,
+    keys: &KeyRing,
+    tokens: &TokenConfig,
+    user_id: Uuid,
+    family_id: Uuid,
+    device: DeviceInfo,
+) -> Result<TokenResponse, ApiError> {
+    let token = create_jwt_tokens(keys, tokens, &user_id, &family_id)?;
+    let hash = bcrypt::hash(&token.refresh_token, 10)?;
+    repos
+        .tokens
+        .store_refresh_token(
+            user_id,
+            token.token_id,
+            token.family_id,
+            hash,
+            token.expiry,
+            device,
+        )
+        .await?;
+    Ok(token)
+}
+
+/// Exchange a refresh token for a new token pair
+/// The presented token is marked as rotated and can't be used again, presenting an already rotated
+/// token means it was leaked so the whole family gets revoked
+pub async fn refresh_token(
+    repos: web::Data<Repos>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    device: DeviceInfo,
+    json: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let presented = json.into_inner().refresh_token;
+    let invalid = || ApiError::Unauthorized("Invalid token".to_string());
+
+    let (token_id, family_id) =
+        decode_refresh_token(&config.tokens, &presented).ok_or_else(invalid)?;
+    let stored = repos
+        .tokens
+        .get_refresh_token(token_id)
+        .await?
+        .ok_or_else(invalid)?;
+    if stored.family_id != family_id
+        || !bcrypt::verify(&presented, &stored.refresh_token).unwrap_or(false)
+    {
+        return Err(invalid());
+    }
+
+    let rotated = repos.tokens.rotate_refresh_token(token_id).await?;
+    // Reuse of a rotated token, revoke every token of this login
+    if stored.rotated_at.is_some() || !rotated {
+        repos
+            .tokens
+            .revoke_token_family(family_id, config.tokens.access_token_expiry())
+            .await?;
+        metrics.token_refresh("reuse_detected");
+        return Err(ApiError::Unauthorized("Token reuse detected".to_string()));
+    }
+
+    let device = device.or(stored.device);
+    let token = issue_tokens(
+        &repos,
+        &keys,
+        &config.tokens,
+        stored.user_id,
+        family_id,
+        device,
+    )
+    .await?;
+    metrics.token_refresh("rotated");
+    Ok(HttpResponse::Ok().json(token))
+}
+
+/// Token and family id of a valid refresh token
+fn decode_refresh_token(tokens: &TokenConfig, token: &str) -> Option<(Uuid, Uuid)> {
+    let claims = jsonwebtoken::decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(tokens.refresh_secret.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()?
+    .claims;
+    Some((
+        Uuid::parse_str(&claims.jti).ok()?,
+        Uuid::parse_str(&claims.fam).ok()?,
+    ))
+}
+
+/// Public keys access tokens can be verified with
+pub async fn jwks(keys: web::Data<KeyRing>) -> HttpResponse {
+    HttpResponse::Ok().json(keys.jwks())
+}
+
+/// Log out the device the refresh token belongs to
+/// Doesn't need an access token, the refresh token proves the login being ended
+pub async fn logout(
+    repos: web::Data<Repos>,
+    config: web::Data<Config>,
+    json: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let (_, family_id) = decode_refresh_token(&config.tokens, &json.refresh_token)
+        .ok_or_else(|| ApiError::Unauthorized("Invalid token".to_string()))?;
+    repos
+        .tokens
+        .revoke_token_family(family_id, config.tokens.access_token_expiry())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Log out everywhere, revokes every login of the user including the current one
+pub async fn logout_all(
+    repos: web::Data<Repos>,
+    config: web::Data<Config>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    repos
+        .tokens
+        .revoke_user_tokens(user.user_id, None, config.tokens.access_token_expiry())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
 pub async fn jwt_middleware(
     req: ServiceRequest,
-    next: Next<impl MessageBody>,
+    next: Next<impl MessageBody + 'static>,
 ) -> Result<ServiceResponse<impl MessageBody>, Error> {
-    let ignore_jwt = ["/login"];
+    match authenticate_request(&req).await {
+        Ok(Some(auth_user)) => {
+            // Store user claims and continue
+            tracing::Span::current().record("user_id", tracing::field::display(auth_user.user_id));
+            req.extensions_mut().insert(auth_user);
+        }
+        Ok(None) => {}
+        // Answered here instead of returned as an error, so outer middleware still sees a response
+        Err(e) => return Ok(req.error_response(e).map_into_right_body()),
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// User of the bearer token, `None` for routes that don't need one
+async fn authenticate_request(req: &ServiceRequest) -> Result<Option<AuthUser>, ApiError> {
+    let ignore_jwt = [
+        "/login",
+        "/logout",
+        "/token/refresh",
+        "/.well-known",
+        "/register",
+        "/password/login",
+        "/password/reset",
+        "/login_code",
+        "/2fa/verify",
+        "/passkeys/login",
+        "/metrics",
+        "/livez",
+        "/readyz",
+        "/version",
+    ];
 
-    // Skip JWT check for ignored paths
-    if ignore_jwt.iter().any(|pat| req.path().starts_with(pat)) {
-        return next.call(req).await;
+    // Skip JWT check for ignored paths and everything below them
+    let path = req.path();
+    if ignore_jwt.iter().any(|pat| {
+        path.strip_prefix(pat)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }) {
+        return Ok(None);
     }
 
     let token = req
@@ -73,61 +305,86 @@ pub async fn jwt_middleware(
         .get("Authorization")
         .and_then(|header| header.to_str().ok())
         .and_then(|auth| auth.strip_prefix("Bearer "))
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing or invalid token"))?;
+        .ok_or_else(|| ApiError::Unauthorized("Missing or invalid token".to_string()))?;
+
+    let repos = req
+        .app_data::<web::Data<Repos>>()
+        .expect("repositories are registered as app data");
 
+    let auth_user = if token.starts_with(ACCESS_TOKEN_PREFIX) {
+        let required = req
+            .match_pattern()
+            .and_then(|pattern| required_scopes(&pattern));
+        authenticate_access_token(repos, token, required).await?
+    } else {
+        let keys = req
+            .app_data::<web::Data<KeyRing>>()
+            .expect("key ring is registered as app data");
+        authenticate_jwt(repos, keys, token).await?
+    };
+    Ok(Some(auth_user))
+}
+
+async fn authenticate_jwt(
+    repos: &Repos,
+    keys: &KeyRing,
+    token: &str,
+) -> Result<AuthUser, ApiError> {
+    let invalid = || ApiError::Unauthorized("Invalid token".to_string());
     // Decode JWT token
-    let decoded = jsonwebtoken::decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(&jwt_access_secret()),
-        &Validation::default(),
-    )
-    .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid token"))?;
+    let claims = keys.verify(token).map_err(|_| invalid())?;
+    let (Ok(user_id), Ok(family_id)) = (Uuid::parse_str(&claims.sub), Uuid::parse_str(&claims.fam))
+    else {
+        return Err(invalid());
+    };
 
-    // Store user claims and continue
-    req.extensions_mut().insert(decoded.claims.sub);
-    next.call(req).await
+    // Logged out or revoked before the access token expired
+    if repos.tokens.is_token_family_revoked(family_id).await? {
+        return Err(ApiError::Unauthorized("Token revoked".to_string()));
+    }
+    Ok(AuthUser {
+        user_id,
+        family_id: Some(family_id),
+    })
 }
 
-pub async fn fetch_google_user_info(
-    id_token: &str,
-) -> Result<OauthUser, Box<dyn std::error::Error>> {
-    let user_info_url = format!(
-        "https://oauth2.googleapis.com/tokeninfo?id_token={}",
-        id_token, // will verify the token, if valid it will provide the user details
-    );
-
-    let client = reqwest::Client::new();
-    let response = client.get(user_info_url).send().await?.error_for_status()?;
-
-    let user_info: Value = response.json().await?;
-    let sub = user_info["sub"] // unique identifier of the google account
-        .as_str()
-        .ok_or("Missing 'sub' field")?
-        .to_string();
-    let picture = user_info["picture"]
-        .as_str()
-        .ok_or("Missing 'picture' field")?
-        .to_string();
-    let email = user_info["email"]
-        .as_str()
-        .ok_or("Missing 'email' field")?
-        .to_string();
-    let name = user_info["name"]
-        .as_str()
-        .ok_or("Missing 'name' field")?
-        .to_string();
-
-    let _aud = user_info["aud"]
-        .as_str()
-        .ok_or("Missing 'aud' field")?
-        .to_string();
-    //TODO: aud needs to be verified with the app id
-    // https://developers.google.com/identity/sign-in/android/backend-auth
-
-    Ok(OauthUser {
-        sub,
-        name,
-        email,
-        picture,
+/// Personal access tokens only work on routes that declare scopes, and need all of them
+async fn authenticate_access_token(
+    repos: &Repos,
+    token: &str,
+    required: Option<&[Scope]>,
+) -> Result<AuthUser, ApiError> {
+    let invalid = || ApiError::Unauthorized("Invalid token".to_string());
+    let (token_id, secret) = parse_access_token(token).ok_or_else(invalid)?;
+    let stored = repos
+        .access_tokens
+        .get_access_token(token_id)
+        .await?
+        .ok_or_else(invalid)?;
+    if !token_secret_matches(secret, &stored.token_hash)
+        || stored
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(invalid());
+    }
+    repos
+        .access_tokens
+        .record_access_token_use(token_id)
+        .await?;
+
+    let Some(required) = required else {
+        return Err(ApiError::Forbidden(
+            "Personal access tokens can't be used here".to_string(),
+        ));
+    };
+    if let Some(missing) = required.iter().find(|scope| !stored.scopes.contains(scope)) {
+        return Err(ApiError::Forbidden(format!("Missing scope {}", missing))
+            .with_details(serde_json::json!({ "missing_scope": missing.to_string() })));
+    }
+
+    Ok(AuthUser {
+        user_id: stored.user_id,
+        family_id: None,
     })
 }
@@ -0,0 +1,39 @@
+use actix_web::{web, HttpResponse};
+use uuid::Uuid;
+
+use crate::{config::Config, error::ApiError, handlers::AuthUser, repo::Repos};
+
+/// Get the devices the user is signed in on
+pub async fn get_devices(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let devices = repos
+        .tokens
+        .get_devices(user.user_id, user.family_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(devices))
+}
+
+/// Sign out a device remotely, its refresh and access tokens stop working right away
+pub async fn revoke_device(
+    repos: web::Data<Repos>,
+    config: web::Data<Config>,
+    user: AuthUser,
+    device_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let device_id = device_id.into_inner();
+    if !repos
+        .tokens
+        .owns_token_family(user.user_id, device_id)
+        .await?
+    {
+        return Err(ApiError::NotFound("Device not found".to_string()));
+    }
+
+    repos
+        .tokens
+        .revoke_token_family(device_id, config.tokens.access_token_expiry())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,101 @@
+use actix_web::{web, HttpResponse};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::ApiError,
+    handlers::{
+        complete_login, create_default_project, find_or_create_identity_user, login_response,
+        two_factor_challenge, verify_provider_credential, AuthUser,
+    },
+    keys::KeyRing,
+    metrics::Metrics,
+    models::{DeviceInfo, LoginRequest, OauthProvider, User},
+    oauth::Providers,
+    repo::{RepoError, Repos},
+};
+
+/// Anonymous login, the guest gets the default project and tokens like any new user
+pub async fn login_guest(
+    repos: web::Data<Repos>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    device: DeviceInfo,
+) -> Result<HttpResponse, ApiError> {
+    let user = User::guest(Uuid::new_v4());
+    repos.users.create_user(user.clone()).await?;
+    create_default_project(&repos, user.user_id).await?;
+    metrics.login("guest");
+    complete_login(&repos, &keys, &config.tokens, user, device).await
+}
+
+/// Sign a guest in with a provider, keeping their projects and sessions
+/// If the provider account already has a Kairos account, the guest's data moves there and the
+/// guest is deleted, either way the response is a login to the resulting account
+/// With 2FA on that account the move waits for the second step
+#[allow(clippy::too_many_arguments)]
+pub async fn upgrade_guest(
+    repos: web::Data<Repos>,
+    providers: web::Data<Providers>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    user: AuthUser,
+    provider: web::Path<String>,
+    device: DeviceInfo,
+    json: web::Json<LoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let not_guest = || ApiError::Conflict("Not a guest account".to_string());
+    if !repos.users.get_user(user.user_id).await?.guest {
+        return Err(not_guest());
+    }
+    let Ok(provider) = provider.parse::<OauthProvider>();
+    let o_user = verify_provider_credential(&providers, &provider, json.into_inner()).await?;
+
+    let provider_name = provider.to_string();
+    metrics.login(&provider_name);
+    let linked = repos
+        .identities
+        .get_identity_user(&provider_name, &o_user.sub)
+        .await?;
+    let account = match linked {
+        Some(account) => account,
+        None => match repos
+            .guests
+            .upgrade_guest(user.user_id, &provider_name, &o_user)
+            .await
+        {
+            Ok(true) => {
+                let upgraded = repos.users.get_user(user.user_id).await?;
+                return login_response(&repos, &keys, &config.tokens, upgraded, device).await;
+            }
+            Ok(false) => return Err(not_guest()),
+            // The email or the identity belongs to an existing account
+            Err(RepoError::Conflict) => {
+                find_or_create_identity_user(&repos, provider, o_user).await?
+            }
+            Err(e) => return Err(e.into()),
+        },
+    };
+
+    if repos
+        .two_factor
+        .get_totp(account.user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled)
+    {
+        // The guest's data only moves in once the second step passes
+        return two_factor_challenge(&config.tokens, account.user_id, Some(user.user_id));
+    }
+    // The guest is deleted with the merge, its logins must not outlive it
+    repos
+        .tokens
+        .revoke_user_tokens(user.user_id, None, config.tokens.access_token_expiry())
+        .await?;
+    repos
+        .guests
+        .merge_guest(user.user_id, account.user_id)
+        .await?;
+    complete_login(&repos, &keys, &config.tokens, account, device).await
+}
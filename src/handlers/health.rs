@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::migrate::{self, MigrationState};
+
+/// A dependency that takes longer than this to answer counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ComponentStatus {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Serialize, Debug)]
+struct Component {
+    status: ComponentStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u128>,
+    /// Latest migration applied to the database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<i64>,
+    /// Latest migration embedded in this binary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_version: Option<i64>,
+    /// Fixed per failure, the probe is public so the details only go to the logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+impl Component {
+    fn ok() -> Self {
+        Component {
+            status: ComponentStatus::Ok,
+            latency_ms: None,
+            version: None,
+            expected_version: None,
+            error: None,
+        }
+    }
+
+    fn unavailable(error: &'static str) -> Self {
+        Component {
+            status: ComponentStatus::Unavailable,
+            error: Some(error),
+            ..Component::ok()
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self.status, ComponentStatus::Ok)
+    }
+}
+
+/// The process is up and serving requests, doesn't touch any dependency
+pub async fn livez() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Whether this replica can serve traffic, 503 with the failing components otherwise
+pub async fn readyz(pool: web::Data<PgPool>) -> impl Responder {
+    let database = check_database(&pool).await;
+    let migrations = if database.is_ok() {
+        check_migrations(&pool).await
+    } else {
+        Component::unavailable("database is unavailable")
+    };
+
+    let ready = database.is_ok() && migrations.is_ok();
+    let body = json!({
+        "status": if ready { ComponentStatus::Ok } else { ComponentStatus::Unavailable },
+        "components": {
+            "database": database,
+            "migrations": migrations,
+        },
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Version and commit this binary was built from
+pub async fn version() -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("KAIROS_GIT_SHA"),
+    }))
+}
+
+async fn check_database(pool: &PgPool) -> Component {
+    let started = Instant::now();
+    let ping = sqlx::query("SELECT 1").execute(pool);
+    match tokio::time::timeout(CHECK_TIMEOUT, ping).await {
+        Ok(Ok(_)) => Component {
+            latency_ms: Some(started.elapsed().as_millis()),
+            ..Component::ok()
+        },
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Readiness check of the database failed");
+            Component::unavailable("unreachable")
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check of the database timed out");
+            Component::unavailable("timed out")
+        }
+    }
+}
+
+/// Ready once every embedded migration is applied, newer ones from a rolling deploy are fine
+async fn check_migrations(pool: &PgPool) -> Component {
+    let statuses = match tokio::time::timeout(CHECK_TIMEOUT, migrate::status(pool)).await {
+        Ok(Ok(statuses)) => statuses,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Readiness check of the migrations failed");
+            return Component::unavailable("unreadable");
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check of the migrations timed out");
+            return Component::unavailable("timed out");
+        }
+    };
+
+    let expected_version = migrate::MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .max();
+    let version = statuses
+        .iter()
+        .filter(|migration| {
+            matches!(
+                migration.state,
+                MigrationState::Applied | MigrationState::Unknown
+            )
+        })
+        .map(|migration| migration.version)
+        .max();
+    let behind: Vec<String> = statuses
+        .iter()
+        .filter(|migration| {
+            !matches!(
+                migration.state,
+                MigrationState::Applied | MigrationState::Unknown
+            )
+        })
+        .map(|migration| format!("{} is {}", migration.version, migration.state))
+        .collect();
+
+    let component = if behind.is_empty() {
+        Component::ok()
+    } else {
+        tracing::warn!(migrations = %behind.join(", "), "Not ready, migrations not applied");
+        Component::unavailable("not applied")
+    };
+    Component {
+        version,
+        expected_version,
+        ..component
+    }
+}
@@ -0,0 +1,89 @@
+use actix_web::{web, HttpResponse};
+
+use crate::{
+    error::{conflict_on_unique, ApiError},
+    handlers::{verify_provider_credential, AuthUser},
+    models::{LoginRequest, OauthProvider},
+    oauth::Providers,
+    repo::Repos,
+};
+
+const LINKED_ELSEWHERE: &str = "Identity is linked to another account";
+
+pub async fn get_identities(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let identities = repos.identities.get_identities(user.user_id).await?;
+    Ok(HttpResponse::Ok().json(identities))
+}
+
+/// Link another provider account to the signed in user, with the same credential as a login
+/// Guests use [`upgrade_guest`](crate::handlers::upgrade_guest) instead
+pub async fn link_identity(
+    repos: web::Data<Repos>,
+    providers: web::Data<Providers>,
+    user: AuthUser,
+    provider: web::Path<String>,
+    json: web::Json<LoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if repos.users.get_user(user.user_id).await?.guest {
+        return Err(ApiError::Conflict(
+            "Guest accounts are upgraded instead".to_string(),
+        ));
+    }
+    let Ok(provider) = provider.parse::<OauthProvider>();
+    let o_user = verify_provider_credential(&providers, &provider, json.into_inner()).await?;
+
+    let provider = provider.to_string();
+    match repos
+        .identities
+        .get_identity_user(&provider, &o_user.sub)
+        .await?
+    {
+        Some(linked) if linked.user_id == user.user_id => return Ok(HttpResponse::Ok().finish()),
+        Some(_) => return Err(ApiError::Conflict(LINKED_ELSEWHERE.to_string())),
+        None => {}
+    }
+    repos
+        .identities
+        .create_identity(user.user_id, &provider, &o_user)
+        .await
+        .map_err(conflict_on_unique(LINKED_ELSEWHERE))?;
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Unlink a provider account, as long as the user can still sign in some other way
+pub async fn unlink_identity(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (provider, subject) = path.into_inner();
+    let (identities, password, passkeys) = tokio::try_join!(
+        repos.identities.get_identities(user.user_id),
+        repos.passwords.get_password_hash(user.user_id),
+        repos.passkeys.get_passkey_credential_ids(user.user_id),
+    )?;
+    let not_found = || ApiError::NotFound("Identity not found".to_string());
+    if !identities
+        .iter()
+        .any(|identity| identity.provider == provider && identity.subject == subject)
+    {
+        return Err(not_found());
+    }
+    if identities.len() == 1 && password.is_none() && passkeys.is_empty() {
+        return Err(ApiError::Conflict(
+            "Cannot unlink the only way to sign in".to_string(),
+        ));
+    }
+
+    if !repos
+        .identities
+        .delete_identity(user.user_id, &provider, &subject)
+        .await?
+    {
+        return Err(not_found());
+    }
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,112 @@
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    Rng,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::ApiError,
+    handlers::{find_or_create_user, hash_token_secret, login_response, normalize_email},
+    keys::KeyRing,
+    mail::{Email, Mailer},
+    metrics::Metrics,
+    models::{DeviceInfo, EmailRequest, LoginCodeRequest, User, UserPlan},
+    repo::Repos,
+};
+
+const LOGIN_CODE_LIFETIME: Duration = Duration::minutes(10);
+/// Wrong guesses before a code is locked, six digits leave no room for many
+const LOGIN_CODE_ATTEMPTS: i32 = 5;
+/// Codes sent to one email per hour, caps the guesses and the mail an attacker can cause
+const LOGIN_CODES_PER_HOUR: i64 = 5;
+
+/// Email a six digit login code and a login link, the account is created on first login
+/// Accepted whether or not the email has an account, so it can't be used to find out which do
+/// No new code while the current one is locked after too many wrong guesses
+pub async fn request_login_code(
+    repos: web::Data<Repos>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<Config>,
+    json: web::Json<EmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let email = normalize_email(&json.email).ok_or_else(|| {
+        ApiError::Validation("Invalid email".to_string()).with_details(json!({ "field": "email" }))
+    })?;
+
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    let link_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let stored = repos
+        .login_codes
+        .replace_login_code(
+            &email,
+            hash_token_secret(&code),
+            hash_token_secret(&link_token),
+            Utc::now() + LOGIN_CODE_LIFETIME,
+            LOGIN_CODE_ATTEMPTS,
+            LOGIN_CODES_PER_HOUR,
+        )
+        .await?;
+    if !stored {
+        return Err(ApiError::TooManyRequests(
+            "Too many login codes requested, try again later".to_string(),
+        ));
+    }
+
+    let message = Email {
+        to: email,
+        subject: "Your Kairos login code".to_string(),
+        body: format!(
+            "Your login code is {}\n\nOr log in at {}/magic-login?token={}\n\n\
+             The code expires in 10 minutes. If you didn't ask for it, ignore this email.",
+            code, config.app.public_url, link_token
+        ),
+    };
+    if let Err(e) = mailer.send(message).await {
+        tracing::error!(error = %e, "Failed to send the login code");
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Exchange a login code (with its email) or the link token for the usual login response
+pub async fn verify_login_code(
+    repos: web::Data<Repos>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    device: DeviceInfo,
+    json: web::Json<LoginCodeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let email = match (request.token, request.email, request.code) {
+        (Some(token), _, _) => {
+            repos
+                .login_codes
+                .use_login_link(&hash_token_secret(&token))
+                .await?
+        }
+        (None, Some(email), Some(code)) => {
+            let email = normalize_email(&email).unwrap_or_default();
+            let code_hash = hash_token_secret(code.trim());
+            repos
+                .login_codes
+                .use_login_code(&email, &code_hash, LOGIN_CODE_ATTEMPTS)
+                .await?
+                .then_some(email)
+        }
+        _ => return Err(ApiError::Validation("Missing code or token".to_string())),
+    };
+    let email =
+        email.ok_or_else(|| ApiError::Unauthorized("Invalid or expired code".to_string()))?;
+
+    let name = email.split('@').next().unwrap_or_default().to_string();
+    let user = User::new(Uuid::new_v4(), name, email, None, None, UserPlan::free);
+    let user = find_or_create_user(&repos, user).await?;
+    repos.passwords.mark_email_verified(user.user_id).await?;
+
+    metrics.login("login_code");
+    login_response(&repos, &keys, &config.tokens, user, device).await
+}
@@ -0,0 +1,48 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use sqlx::PgPool;
+
+use crate::metrics::Metrics;
+
+/// Count requests and time them per route pattern, unmatched paths and non-standard methods share
+/// one label each so scanners can't blow up the number of series
+pub async fn metrics_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req
+        .app_data::<web::Data<Metrics>>()
+        .expect("metrics are registered as app data")
+        .clone();
+    let method = match req.method().as_str() {
+        method @ ("GET" | "POST" | "PUT" | "PATCH" | "DELETE" | "HEAD" | "OPTIONS" | "CONNECT"
+        | "TRACE") => method.to_string(),
+        _ => "other".to_string(),
+    };
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started = Instant::now();
+    let result = next.call(req).await;
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics.observe_request(&method, &route, status.as_u16(), started.elapsed());
+    result
+}
+
+/// Prometheus scrape endpoint, doesn't need a token
+pub async fn get_metrics(pool: web::Data<PgPool>, metrics: web::Data<Metrics>) -> HttpResponse {
+    let body = metrics.render(&pool).await;
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
@@ -1,35 +1,24 @@
 use actix_web::{web, HttpResponse, Responder};
-use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::{error::ApiError, handlers::AuthUser, repo::Repos};
+
+/// Only checks the token, probes should use `/livez` and `/readyz`
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().finish()
 }
 
 /// Get today's focused duration
 pub async fn get_todays_focus_time(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repos>,
+    user: AuthUser,
     user_id: web::Path<Uuid>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
+    user.check_owns(&user_id)?;
     let today = chrono::Utc::now().date_naive();
-    let rows = sqlx::query!(
-        "SELECT session_id, user_id, started_at, ended_at, duration
-         FROM sessions
-         WHERE user_id = $1 AND DATE(started_at) = $2",
-        user_id.into_inner(),
-        today
-    )
-    .fetch_all(&**pool)
-    .await;
-
-    match rows {
-        Ok(sessions) => {
-            let mut total_duration = 0;
-            sessions
-                .iter()
-                .for_each(|ses| total_duration += ses.duration);
-            HttpResponse::Ok().json(total_duration)
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+    let total_duration = repos
+        .sessions
+        .get_focus_time_on(user.user_id, today)
+        .await?;
+    Ok(HttpResponse::Ok().json(total_duration))
 }
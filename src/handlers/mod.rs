@@ -1,11 +1,35 @@
+pub mod access_token;
 pub mod auth;
+pub mod device;
+pub mod guest;
+pub mod health;
+pub mod identity;
+pub mod login_code;
+pub mod metrics;
 pub mod misc;
+pub mod passkey;
+pub mod password;
 pub mod project;
+pub mod request_id;
 pub mod session;
+pub mod settings;
+pub mod two_factor;
 pub mod user;
 
+pub use access_token::*;
 pub use auth::*;
+pub use device::*;
+pub use guest::*;
+pub use health::*;
+pub use identity::*;
+pub use login_code::*;
+pub use metrics::*;
 pub use misc::*;
+pub use passkey::*;
+pub use password::*;
 pub use project::*;
+pub use request_id::*;
 pub use session::*;
+pub use settings::*;
+pub use two_factor::*;
 pub use user::*;
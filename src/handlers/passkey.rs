@@ -0,0 +1,280 @@
+use actix_web::{web, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{conflict_on_unique, ApiError},
+    handlers::{complete_login, login_response, normalize_email, AuthUser},
+    keys::KeyRing,
+    metrics::Metrics,
+    models::{
+        ChallengePurpose, DeviceInfo, FinishPasskeyLogin, FinishPasskeyRegistration, Passkey,
+        StartPasskeyLogin,
+    },
+    repo::{RepoError, Repos},
+    webauthn::{new_challenge, RelyingParty, EDDSA, ES256},
+};
+
+const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+
+/// Options for `navigator.credentials.create()`, answered with [`finish_passkey_registration`]
+pub async fn start_passkey_registration(
+    repos: web::Data<Repos>,
+    rp: web::Data<RelyingParty>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let user = repos.users.get_user(user.user_id).await?;
+    let existing = repos
+        .passkeys
+        .get_passkey_credential_ids(user.user_id)
+        .await?;
+
+    let challenge_id = Uuid::new_v4();
+    let challenge = new_challenge();
+    repos
+        .passkeys
+        .store_webauthn_challenge(
+            challenge_id,
+            Some(user.user_id),
+            &challenge,
+            ChallengePurpose::Register,
+            Utc::now() + CHALLENGE_LIFETIME,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "challenge_id": challenge_id,
+        "publicKey": {
+            "rp": { "id": rp.id, "name": rp.name },
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user.user_id.as_bytes()),
+                "name": user.email,
+                "displayName": user.name,
+            },
+            "challenge": challenge,
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": ES256 },
+                { "type": "public-key", "alg": EDDSA },
+            ],
+            "timeout": CHALLENGE_LIFETIME.num_milliseconds(),
+            "attestation": "none",
+            "authenticatorSelection": {
+                "residentKey": "preferred",
+                "userVerification": "preferred",
+            },
+            "excludeCredentials": existing
+                .into_iter()
+                .map(|id| json!({ "type": "public-key", "id": id }))
+                .collect::<Vec<_>>(),
+        },
+    })))
+}
+
+fn invalid_encoding() -> ApiError {
+    ApiError::Validation("Invalid encoding".to_string())
+}
+
+/// Store the passkey the authenticator created
+pub async fn finish_passkey_registration(
+    repos: web::Data<Repos>,
+    rp: web::Data<RelyingParty>,
+    user: AuthUser,
+    json: web::Json<FinishPasskeyRegistration>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let challenge = repos
+        .passkeys
+        .take_webauthn_challenge(request.challenge_id, ChallengePurpose::Register)
+        .await?
+        .filter(|challenge| challenge.user_id == Some(user.user_id))
+        .ok_or_else(|| ApiError::Validation("Invalid or expired challenge".to_string()))?;
+    let name = request
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "Passkey".to_string());
+    if name.len() > 255 {
+        return Err(ApiError::Validation(
+            "Name must be at most 255 characters".to_string(),
+        ));
+    }
+
+    let response = request.credential.response;
+    let client_data_json = URL_SAFE_NO_PAD
+        .decode(response.client_data_json)
+        .map_err(|_| invalid_encoding())?;
+    let attestation_object = URL_SAFE_NO_PAD
+        .decode(response.attestation_object)
+        .map_err(|_| invalid_encoding())?;
+    let credential = rp
+        .verify_registration(&challenge.challenge, &client_data_json, &attestation_object)
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    let credential_id = URL_SAFE_NO_PAD.encode(&credential.credential_id);
+    if credential_id != request.credential.id {
+        return Err(ApiError::Validation("Credential id mismatch".to_string()));
+    }
+
+    let passkey = Passkey {
+        passkey_id: Uuid::new_v4(),
+        name,
+        created_at: Utc::now(),
+        last_used_at: None,
+    };
+    repos
+        .passkeys
+        .create_passkey(
+            user.user_id,
+            passkey.passkey_id,
+            credential_id,
+            &credential,
+            &passkey.name,
+        )
+        .await
+        .map_err(conflict_on_unique("Passkey already registered"))?;
+    Ok(HttpResponse::Created().json(passkey))
+}
+
+pub async fn get_passkeys(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let passkeys = repos.passkeys.get_passkeys(user.user_id).await?;
+    Ok(HttpResponse::Ok().json(passkeys))
+}
+
+pub async fn delete_passkey(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    passkey_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    if !repos
+        .passkeys
+        .delete_passkey(user.user_id, passkey_id.into_inner())
+        .await?
+    {
+        return Err(ApiError::NotFound("Passkey not found".to_string()));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Options for `navigator.credentials.get()`, answered with [`finish_passkey_login`]
+/// An unknown email gets the same empty credential list as no email at all
+pub async fn start_passkey_login(
+    repos: web::Data<Repos>,
+    rp: web::Data<RelyingParty>,
+    json: web::Json<StartPasskeyLogin>,
+) -> Result<HttpResponse, ApiError> {
+    let mut allowed = Vec::new();
+    if let Some(email) = json.email.as_deref().and_then(normalize_email) {
+        match repos.users.get_user_by_email(&email).await {
+            Ok(user) => {
+                allowed = repos
+                    .passkeys
+                    .get_passkey_credential_ids(user.user_id)
+                    .await?
+            }
+            Err(RepoError::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let challenge_id = Uuid::new_v4();
+    let challenge = new_challenge();
+    repos
+        .passkeys
+        .store_webauthn_challenge(
+            challenge_id,
+            None,
+            &challenge,
+            ChallengePurpose::Login,
+            Utc::now() + CHALLENGE_LIFETIME,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "challenge_id": challenge_id,
+        "publicKey": {
+            "challenge": challenge,
+            "rpId": rp.id,
+            "timeout": CHALLENGE_LIFETIME.num_milliseconds(),
+            "userVerification": "preferred",
+            "allowCredentials": allowed
+                .into_iter()
+                .map(|id| json!({ "type": "public-key", "id": id }))
+                .collect::<Vec<_>>(),
+        },
+    })))
+}
+
+/// Log in with a passkey assertion
+/// With user verification the passkey counts as both factors, otherwise 2FA still applies
+#[allow(clippy::too_many_arguments)]
+pub async fn finish_passkey_login(
+    repos: web::Data<Repos>,
+    rp: web::Data<RelyingParty>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    device: DeviceInfo,
+    json: web::Json<FinishPasskeyLogin>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let challenge = repos
+        .passkeys
+        .take_webauthn_challenge(request.challenge_id, ChallengePurpose::Login)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("Invalid or expired challenge".to_string()))?;
+    let passkey = repos
+        .passkeys
+        .get_passkey_by_credential_id(&request.credential.id)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("Unknown passkey".to_string()))?;
+
+    let response = request.credential.response;
+    let client_data_json = URL_SAFE_NO_PAD
+        .decode(response.client_data_json)
+        .map_err(|_| invalid_encoding())?;
+    let authenticator_data = URL_SAFE_NO_PAD
+        .decode(response.authenticator_data)
+        .map_err(|_| invalid_encoding())?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(response.signature)
+        .map_err(|_| invalid_encoding())?;
+    let assertion = rp
+        .verify_assertion(
+            &challenge.challenge,
+            &passkey.public_key,
+            passkey.algorithm,
+            &client_data_json,
+            &authenticator_data,
+            &signature,
+        )
+        .map_err(|_| ApiError::Unauthorized("Invalid passkey assertion".to_string()))?;
+
+    // Authenticators that count must count up, anything else hints at a cloned key
+    let counter_error =
+        || ApiError::Unauthorized("Passkey sign counter did not increase".to_string());
+    if (assertion.sign_count != 0 || passkey.sign_count != 0)
+        && assertion.sign_count <= passkey.sign_count
+    {
+        return Err(counter_error());
+    }
+    if !repos
+        .passkeys
+        .use_passkey(passkey.passkey_id, passkey.sign_count, assertion.sign_count)
+        .await?
+    {
+        return Err(counter_error());
+    }
+
+    let user = repos.users.get_user(passkey.user_id).await?;
+    metrics.login("passkey");
+    if assertion.user_verified {
+        complete_login(&repos, &keys, &config.tokens, user, device).await
+    } else {
+        login_response(&repos, &keys, &config.tokens, user, device).await
+    }
+}
@@ -0,0 +1,325 @@
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
+use lazy_static::lazy_static;
+use rand::distributions::{Alphanumeric, DistString};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{conflict_on_unique, ApiError},
+    handlers::{create_default_project, hash_token_secret, login_response, AuthUser},
+    keys::KeyRing,
+    mail::{Email, Mailer},
+    metrics::Metrics,
+    models::{
+        ChangePasswordRequest, DeviceInfo, EmailRequest, EmailTokenPurpose, EmailTokenRequest,
+        PasswordLoginRequest, RegisterRequest, ResetPasswordRequest, User, UserPlan,
+    },
+    repo::{RepoError, Repos},
+};
+
+const VERIFY_EMAIL_LIFETIME: Duration = Duration::hours(24);
+const RESET_PASSWORD_LIFETIME: Duration = Duration::hours(1);
+/// Reset and verification emails to one address per hour, caps the mail an attacker can cause
+const PASSWORD_EMAILS_PER_HOUR: i64 = 5;
+
+lazy_static! {
+    /// Checked against when there is no password to check, so unknown emails take as long to
+    /// reject as wrong passwords
+    static ref DUMMY_PASSWORD_HASH: String =
+        bcrypt::hash("kairos-dummy-password", bcrypt::DEFAULT_COST).unwrap();
+}
+
+fn invalid_email() -> ApiError {
+    ApiError::Validation("Invalid email".to_string()).with_details(json!({ "field": "email" }))
+}
+
+/// Create an email and password account, it can log in once the email is verified
+pub async fn register(
+    repos: web::Data<Repos>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<Config>,
+    json: web::Json<RegisterRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let name = request.name.trim().to_string();
+    if name.is_empty() || name.len() > 255 {
+        return Err(
+            ApiError::Validation("Name must be 1 to 255 characters".to_string())
+                .with_details(json!({ "field": "name" })),
+        );
+    }
+    let email = normalize_email(&request.email).ok_or_else(invalid_email)?;
+    validate_password(&request.password, "password")?;
+
+    let password_hash = bcrypt::hash(&request.password, bcrypt::DEFAULT_COST)?;
+    let user = User::new(Uuid::new_v4(), name, email, None, None, UserPlan::free);
+    repos
+        .passwords
+        .create_password_user(user.clone(), password_hash)
+        .await
+        .map_err(conflict_on_unique("Email already registered"))?;
+    create_default_project(&repos, user.user_id).await?;
+
+    if let Err(e) = send_verification_email(&repos, &**mailer, &config, &user).await {
+        // The account exists either way, the email can be sent again
+        tracing::error!(error = %e, "Failed to send the verification email");
+    }
+    Ok(HttpResponse::Created().json(user))
+}
+
+/// Confirm the email with the token sent to it
+pub async fn verify_email(
+    repos: web::Data<Repos>,
+    json: web::Json<EmailTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let token_hash = hash_token_secret(&json.token);
+    let user_id = repos
+        .passwords
+        .consume_email_token(token_hash, EmailTokenPurpose::VerifyEmail)
+        .await?
+        .ok_or_else(invalid_token)?;
+    repos.passwords.mark_email_verified(user_id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn invalid_token() -> ApiError {
+    ApiError::Validation("Invalid or expired token".to_string())
+}
+
+/// Send the verification email again
+/// Accepted whether or not the email has an account, so it can't be used to find out which do
+pub async fn resend_verification(
+    repos: web::Data<Repos>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<Config>,
+    json: web::Json<EmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let email = normalize_email(&json.email).ok_or_else(invalid_email)?;
+    limit_password_emails(&repos, &email).await?;
+    match repos.passwords.get_password_credentials(&email).await? {
+        Some(credentials) if credentials.password_hash.is_some() && !credentials.email_verified => {
+            if let Err(e) =
+                send_verification_email(&repos, &**mailer, &config, &credentials.user).await
+            {
+                tracing::error!(error = %e, "Failed to send the verification email");
+            }
+        }
+        _ => {}
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Login with email and password
+/// Unknown emails, accounts without a password and wrong passwords fail the same way and take
+/// the same time
+pub async fn password_login(
+    repos: web::Data<Repos>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    device: DeviceInfo,
+    json: web::Json<PasswordLoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let email = normalize_email(&request.email).unwrap_or_default();
+    let credentials = repos.passwords.get_password_credentials(&email).await?;
+
+    let password_hash = credentials
+        .as_ref()
+        .and_then(|c| c.password_hash.as_deref())
+        .unwrap_or(&DUMMY_PASSWORD_HASH);
+    let valid = bcrypt::verify(&request.password, password_hash).unwrap_or(false);
+    let credentials = match credentials {
+        Some(credentials) if valid && credentials.password_hash.is_some() => credentials,
+        _ => {
+            return Err(ApiError::Unauthorized(
+                "Invalid email or password".to_string(),
+            ))
+        }
+    };
+    if !credentials.email_verified {
+        return Err(ApiError::Forbidden("Email not verified".to_string()));
+    }
+
+    metrics.login("password");
+    login_response(&repos, &keys, &config.tokens, credentials.user, device).await
+}
+
+/// Change the password of the logged in user
+pub async fn change_password(
+    repos: web::Data<Repos>,
+    config: web::Data<Config>,
+    user: AuthUser,
+    json: web::Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let password_hash = repos.passwords.get_password_hash(user.user_id).await?;
+    let valid = password_hash
+        .is_some_and(|hash| bcrypt::verify(&request.current_password, &hash).unwrap_or(false));
+    if !valid {
+        return Err(ApiError::Forbidden("Wrong password".to_string()));
+    }
+    validate_password(&request.new_password, "new_password")?;
+
+    let new_hash = bcrypt::hash(&request.new_password, bcrypt::DEFAULT_COST)?;
+    repos.passwords.set_password(user.user_id, new_hash).await?;
+    // Whoever knew the old password may still be signed in elsewhere
+    repos
+        .tokens
+        .revoke_user_tokens(
+            user.user_id,
+            user.family_id,
+            config.tokens.access_token_expiry(),
+        )
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Email a password reset token
+/// Accepted whether or not the email has an account, so it can't be used to find out which do
+pub async fn request_password_reset(
+    repos: web::Data<Repos>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<Config>,
+    json: web::Json<EmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let email = normalize_email(&json.email).ok_or_else(invalid_email)?;
+    limit_password_emails(&repos, &email).await?;
+    let Some(credentials) = repos.passwords.get_password_credentials(&email).await? else {
+        return Ok(HttpResponse::Accepted().finish());
+    };
+    let user = credentials.user;
+
+    let token = create_email_token(
+        &repos,
+        user.user_id,
+        EmailTokenPurpose::ResetPassword,
+        RESET_PASSWORD_LIFETIME,
+    )
+    .await?;
+    let email = Email {
+        to: user.email,
+        subject: "Reset your Kairos password".to_string(),
+        body: format!(
+            "Hi {},\n\nSet a new password at {}/reset-password?token={}\n\n\
+             The link expires in an hour. If you didn't ask for it, ignore this email.",
+            user.name, config.app.public_url, token
+        ),
+    };
+    if let Err(e) = mailer.send(email).await {
+        tracing::error!(error = %e, "Failed to send the password reset email");
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Set a new password with a reset token, signs the user out everywhere
+/// Getting the token proves the email, so it also counts as verification
+pub async fn reset_password(
+    repos: web::Data<Repos>,
+    config: web::Data<Config>,
+    json: web::Json<ResetPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    validate_password(&request.new_password, "new_password")?;
+
+    let token_hash = hash_token_secret(&request.token);
+    let user_id = repos
+        .passwords
+        .consume_email_token(token_hash, EmailTokenPurpose::ResetPassword)
+        .await?
+        .ok_or_else(invalid_token)?;
+
+    let password_hash = bcrypt::hash(&request.new_password, bcrypt::DEFAULT_COST)?;
+    repos.passwords.set_password(user_id, password_hash).await?;
+    repos.passwords.mark_email_verified(user_id).await?;
+    repos
+        .tokens
+        .revoke_user_tokens(user_id, None, config.tokens.access_token_expiry())
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn send_verification_email(
+    repos: &Repos,
+    mailer: &dyn Mailer,
+    config: &Config,
+    user: &User,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = create_email_token(
+        repos,
+        user.user_id,
+        EmailTokenPurpose::VerifyEmail,
+        VERIFY_EMAIL_LIFETIME,
+    )
+    .await?;
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Verify your Kairos email".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email at {}/verify-email?token={}\n\n\
+             The link expires in 24 hours.",
+            user.name, config.app.public_url, token
+        ),
+    };
+    mailer.send(email).await?;
+    Ok(())
+}
+
+/// Store a new single use email token, returns the token to send
+async fn create_email_token(
+    repos: &Repos,
+    user_id: Uuid,
+    purpose: EmailTokenPurpose,
+    lifetime: Duration,
+) -> Result<String, RepoError> {
+    let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    repos
+        .passwords
+        .store_email_token(
+            user_id,
+            hash_token_secret(&token),
+            purpose,
+            Utc::now() + lifetime,
+        )
+        .await?;
+    Ok(token)
+}
+
+/// Refused once the address had `PASSWORD_EMAILS_PER_HOUR` requests in the last hour
+/// Every request counts, with or without an account, so a refusal doesn't tell either
+async fn limit_password_emails(repos: &Repos, email: &str) -> Result<(), ApiError> {
+    let allowed = repos
+        .passwords
+        .record_password_email_request(email, PASSWORD_EMAILS_PER_HOUR)
+        .await?;
+    if !allowed {
+        return Err(ApiError::TooManyRequests(
+            "Too many emails requested, try again later".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Trimmed and lowercased, every email is stored and looked up in this form
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+    if local.is_empty() || domain.is_empty() || email.len() > 255 {
+        return None;
+    }
+    Some(email)
+}
+
+/// bcrypt only looks at the first 72 bytes
+fn validate_password(password: &str, field: &str) -> Result<(), ApiError> {
+    let message = if password.chars().count() < 8 {
+        "Password must be at least 8 characters"
+    } else if password.len() > 72 {
+        "Password must be at most 72 bytes"
+    } else {
+        return Ok(());
+    };
+    Err(ApiError::Validation(message.to_string()).with_details(json!({ "field": field })))
+}
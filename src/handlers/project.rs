@@ -1,105 +1,69 @@
-use actix_web::{web, HttpResponse, Responder};
-use sqlx::PgPool;
+use actix_web::{web, HttpResponse};
 use uuid::Uuid;
 
-use crate::{config::UNIQUE_VIOLATION, models::Project};
+use crate::{
+    error::{conflict_on_unique, ApiError},
+    handlers::AuthUser,
+    models::Project,
+    repo::Repos,
+};
 
 /// Add project for the user
-pub async fn add_project(pool: web::Data<PgPool>, json: web::Json<Project>) -> impl Responder {
+pub async fn add_project(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<Project>,
+) -> Result<HttpResponse, ApiError> {
     let project = json.into_inner();
-    let result = sqlx::query!(
-        "INSERT INTO projects (project_id, user_id, project_name, colour, deadline, priority)
-         VALUES ($1, $2, $3, $4, $5, $6)",
-        project.project_id,
-        project.user_id,
-        project.project_name,
-        project.colour,
-        project.deadline,
-        project.priority,
-    )
-    .execute(&**pool)
-    .await;
+    user.check_owns(&project.user_id)?;
+    repos
+        .projects
+        .add_project(project)
+        .await
+        .map_err(conflict_on_unique("Project already exists"))?;
 
-    match result {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(e) => match e {
-            sqlx::Error::Database(err) => {
-                if err.code() == Some(UNIQUE_VIOLATION.into()) {
-                    HttpResponse::Conflict().finish()
-                } else {
-                    HttpResponse::InternalServerError().finish()
-                }
-            }
-            _ => HttpResponse::InternalServerError().finish(),
-        },
-    }
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Update project
-pub async fn update_project(pool: web::Data<PgPool>, json: web::Json<Project>) -> impl Responder {
+pub async fn update_project(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<Project>,
+) -> Result<HttpResponse, ApiError> {
     let project = json.into_inner();
-    let result = sqlx::query!(
-        "UPDATE projects SET project_name = $1, colour = $2, deadline = $3, priority = $4
-         WHERE user_id = $5 AND project_id = $6",
-        project.project_name,
-        project.colour,
-        project.deadline,
-        project.priority,
-        project.user_id,
-        project.project_id
-    )
-    .execute(&**pool)
-    .await;
-
-    match result {
-        Ok(rows_affected) if rows_affected.rows_affected() > 0 => HttpResponse::Ok().finish(),
-        _ => HttpResponse::NotFound().finish(),
+    user.check_owns(&project.user_id)?;
+    if !repos.projects.update_project(project).await? {
+        return Err(ApiError::NotFound("Project not found".to_string()));
     }
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Delete the project
-pub async fn delete_project(pool: web::Data<PgPool>, json: web::Json<Project>) -> impl Responder {
+pub async fn delete_project(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<Project>,
+) -> Result<HttpResponse, ApiError> {
     let project = json.into_inner();
-    let result = sqlx::query!(
-        "DELETE FROM projects WHERE project_id = $1 AND user_id = $2",
-        project.project_id,
-        project.user_id
-    )
-    .execute(&**pool)
-    .await;
-
-    match result {
-        Ok(rows_affected) if rows_affected.rows_affected() > 0 => HttpResponse::Ok().finish(),
-        _ => HttpResponse::NotFound().finish(),
+    user.check_owns(&project.user_id)?;
+    if !repos
+        .projects
+        .delete_project(user.user_id, project.project_id)
+        .await?
+    {
+        return Err(ApiError::NotFound("Project not found".to_string()));
     }
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Get all user projects
-pub async fn get_projects(pool: web::Data<PgPool>, user_id: web::Path<Uuid>) -> impl Responder {
-    let rows = sqlx::query!(
-        "SELECT project_id, user_id, project_name, colour, deadline, priority
-         FROM projects
-         WHERE user_id = $1",
-        user_id.into_inner()
-    )
-    .fetch_all(&**pool)
-    .await;
-
-    match rows {
-        Ok(rows) => {
-            let projects: Vec<Project> = rows
-                .into_iter()
-                .map(|row| Project {
-                    project_id: row.project_id,
-                    user_id: row.user_id,
-                    project_name: row.project_name,
-                    colour: row.colour,
-                    deadline: row.deadline,
-                    priority: row.priority,
-                })
-                .collect();
-            HttpResponse::Ok().json(projects)
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+pub async fn get_projects(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    user.check_owns(&user_id)?;
+    let projects = repos.projects.get_projects(user.user_id).await?;
+    Ok(HttpResponse::Ok().json(projects))
 }
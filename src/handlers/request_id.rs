@@ -0,0 +1,65 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    Error,
+};
+use tracing::{field::Empty, Instrument};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Run the request in a span carrying its id, route, user, status and latency, log it when it
+/// finishes and echo the id back in `X-Request-Id`
+/// The id comes from the `X-Request-Id` a proxy set, or is generated
+/// Wraps `jwt_middleware`, which answers rejected requests itself so they get an id too
+pub async fn request_id_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string()),
+        user_id = Empty,
+        status = Empty,
+        latency_ms = Empty,
+    );
+
+    let started = Instant::now();
+    let result = next.call(req).instrument(span.clone()).await;
+
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    span.record("status", status.as_u16());
+    span.record("latency_ms", started.elapsed().as_millis() as u64);
+    span.in_scope(|| tracing::info!("request finished"));
+
+    let mut res = result?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
+
+/// Ids from outside are logged and echoed, keep them short and printable
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
@@ -1,29 +1,34 @@
-use actix_web::{web, HttpResponse, Responder};
-use sqlx::PgPool;
+use actix_web::{web, HttpResponse};
 use uuid::Uuid;
 
-use crate::models::Session;
+use crate::{
+    error::{conflict_on_unique, ApiError},
+    handlers::AuthUser,
+    models::Session,
+    repo::Repos,
+};
 
 /// Add session for the user
-pub async fn add_session(pool: web::Data<PgPool>, json: web::Json<Session>) -> impl Responder {
+/// The session can only be logged against one of the user's own projects
+pub async fn add_session(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<Session>,
+) -> Result<HttpResponse, ApiError> {
     let session = json.into_inner();
-    let result = sqlx::query!(
-        "INSERT INTO sessions (session_id, user_id, project_id, started_at, ended_at, duration)
-         VALUES ($1, $2, $3, $4, $5, $6)",
-        session.session_id,
-        session.user_id,
-        session.project_id,
-        session.started_at,
-        session.ended_at,
-        session.duration
-    )
-    .execute(&**pool)
-    .await;
+    user.check_owns(&session.user_id)?;
+    let added = repos
+        .sessions
+        .add_session(session)
+        .await
+        .map_err(conflict_on_unique("Session already exists"))?;
 
-    match result {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    if !added {
+        return Err(ApiError::Forbidden(
+            "Project belongs to another user".to_string(),
+        ));
     }
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Update the session
@@ -33,23 +38,17 @@ pub async fn add_session(pool: web::Data<PgPool>, json: web::Json<Session>) -> i
 /// Max duration for any running session is set by the user.. by default 4 hours, can be set upto 6
 /// hours
 /// Max duration to update any past session is 4 hours
-pub async fn update_session(pool: web::Data<PgPool>, json: web::Json<Session>) -> impl Responder {
+pub async fn update_session(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<Session>,
+) -> Result<HttpResponse, ApiError> {
     let session = json.into_inner();
-    let result = sqlx::query!(
-        "UPDATE sessions SET ended_at = $1, duration = $2
-         WHERE user_id = $3 AND session_id = $4",
-        session.ended_at,
-        session.duration,
-        session.user_id,
-        session.session_id
-    )
-    .execute(&**pool)
-    .await;
-
-    match result {
-        Ok(rows_affected) if rows_affected.rows_affected() > 0 => HttpResponse::Ok().finish(),
-        _ => HttpResponse::NotFound().finish(),
+    user.check_owns(&session.user_id)?;
+    if !repos.sessions.update_session(session).await? {
+        return Err(ApiError::NotFound("Session not found".to_string()));
     }
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Check if the active session is already running on another device
@@ -58,61 +57,26 @@ pub async fn update_session(pool: web::Data<PgPool>, json: web::Json<Session>) -
 /// that connection should trigger only if start session or stop session was requested for that
 /// user
 pub async fn check_active_session(
-    pool: web::Data<PgPool>,
+    repos: web::Data<Repos>,
+    user: AuthUser,
     user_id: web::Path<Uuid>,
-) -> impl Responder {
-    let row = sqlx::query!(
-        "SELECT session_id, user_id, project_id, started_at, ended_at, duration
-         FROM sessions
-         WHERE user_id = $1 AND ended_at IS NULL
-         LIMIT 1",
-        user_id.into_inner()
-    )
-    .fetch_optional(&**pool)
-    .await;
-
-    match row {
-        Ok(Some(row)) => {
-            let active_session = Session::new(
-                row.session_id,
-                row.user_id,
-                row.project_id,
-                row.started_at,
-                row.ended_at,
-                row.duration,
-            );
-            HttpResponse::Ok().json(active_session)
-        }
-        _ => HttpResponse::NotFound().finish(),
-    }
+) -> Result<HttpResponse, ApiError> {
+    user.check_owns(&user_id)?;
+    let active_session = repos
+        .sessions
+        .get_active_session(user.user_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("No active session".to_string()))?;
+    Ok(HttpResponse::Ok().json(active_session))
 }
 
 /// Get all user sessions
-pub async fn get_sessions(pool: web::Data<PgPool>, user_id: web::Path<Uuid>) -> impl Responder {
-    let rows = sqlx::query!(
-        "SELECT session_id, user_id, project_id, started_at, ended_at, duration
-         FROM sessions
-         WHERE user_id = $1",
-        user_id.into_inner()
-    )
-    .fetch_all(&**pool)
-    .await;
-
-    match rows {
-        Ok(rows) => {
-            let sessions: Vec<Session> = rows
-                .into_iter()
-                .map(|row| Session {
-                    session_id: row.session_id,
-                    user_id: row.user_id,
-                    project_id: row.project_id,
-                    started_at: row.started_at,
-                    ended_at: row.ended_at,
-                    duration: row.duration,
-                })
-                .collect();
-            HttpResponse::Ok().json(sessions)
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+pub async fn get_sessions(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    user.check_owns(&user_id)?;
+    let sessions = repos.sessions.get_sessions(user.user_id).await?;
+    Ok(HttpResponse::Ok().json(sessions))
 }
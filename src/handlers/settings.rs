@@ -0,0 +1,26 @@
+use actix_web::{web, HttpResponse};
+
+use crate::{error::ApiError, handlers::AuthUser, models::UserSettings, repo::Repos};
+
+pub async fn get_settings(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    let settings = repos.users.get_settings(user.user_id).await?;
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Replace the settings of the signed in user
+/// The weekly digest only goes out once the email is verified
+pub async fn update_settings(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<UserSettings>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = json.into_inner();
+    repos
+        .users
+        .update_settings(user.user_id, settings.clone())
+        .await?;
+    Ok(HttpResponse::Ok().json(settings))
+}
@@ -0,0 +1,336 @@
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    Rng,
+};
+use totp_rs::{Secret, TOTP};
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, TokenConfig},
+    error::ApiError,
+    handlers::{complete_login, hash_token_secret, AuthUser},
+    keys::KeyRing,
+    models::{
+        ChallengeClaims, DeviceInfo, DisableTotpRequest, RecoveryCodes, TotpCodeRequest,
+        TotpEnrollment, TwoFactorChallenge, TwoFactorLoginRequest,
+    },
+    repo::{RepoError, Repos},
+};
+
+const TOTP_ISSUER: &str = "Kairos";
+const CHALLENGE_AUDIENCE: &str = "kairos:2fa";
+const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+/// Wrong codes in a row before the second step is locked for a while
+const TOTP_ATTEMPTS: i32 = 5;
+const TOTP_LOCKOUT: Duration = Duration::minutes(15);
+const RECOVERY_CODE_COUNT: usize = 10;
+
+enum SecondFactor {
+    Valid,
+    Invalid,
+    Locked,
+}
+
+impl SecondFactor {
+    /// `invalid` is the error for a wrong code
+    fn require(self, invalid: ApiError) -> Result<(), ApiError> {
+        match self {
+            SecondFactor::Valid => Ok(()),
+            SecondFactor::Invalid => Err(invalid),
+            SecondFactor::Locked => Err(ApiError::TooManyRequests("Too many attempts".to_string())),
+        }
+    }
+}
+
+fn already_enabled() -> ApiError {
+    ApiError::Conflict("Two-factor authentication is already enabled".to_string())
+}
+
+/// Start TOTP enrolment, 2FA is only enabled once a code is confirmed
+pub async fn enroll_totp(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+) -> Result<HttpResponse, ApiError> {
+    if repos
+        .two_factor
+        .get_totp(user.user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled)
+    {
+        return Err(already_enabled());
+    }
+    let user = repos.users.get_user(user.user_id).await?;
+
+    let secret: [u8; 20] = rand::thread_rng().gen();
+    let totp =
+        build_totp(secret.to_vec(), user.email).map_err(|e| ApiError::Internal(e.to_string()))?;
+    let enrollment = TotpEnrollment {
+        secret: totp.get_secret_base32(),
+        otpauth_uri: totp.get_url(),
+    };
+    repos
+        .two_factor
+        .set_pending_totp(user.user_id, enrollment.secret.clone())
+        .await?;
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+/// Enable 2FA with the first code from the authenticator, returns the recovery codes once
+pub async fn confirm_totp(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<TotpCodeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let totp = match repos.two_factor.get_totp(user.user_id).await? {
+        Some(totp) if totp.enabled => return Err(already_enabled()),
+        Some(totp) => totp,
+        None => return Err(ApiError::NotFound("No TOTP enrolment".to_string())),
+    };
+
+    let accepted = match matching_step(&totp.secret, &json.code) {
+        Some(step) => repos.two_factor.record_totp_use(user.user_id, step).await?,
+        None => false,
+    };
+    if !accepted {
+        return Err(ApiError::Validation("Invalid code".to_string()));
+    }
+
+    let (codes, hashes) = generate_recovery_codes();
+    repos.two_factor.enable_totp(user.user_id, hashes).await?;
+    Ok(HttpResponse::Ok().json(RecoveryCodes {
+        recovery_codes: codes,
+    }))
+}
+
+/// Second login step, exchanges the challenge token and a TOTP or recovery code for the tokens
+pub async fn verify_two_factor(
+    repos: web::Data<Repos>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    device: DeviceInfo,
+    json: web::Json<TwoFactorLoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    let (user_id, guest_id) = decode_challenge_token(&config.tokens, &request.challenge_token)
+        .ok_or_else(|| ApiError::Unauthorized("Invalid challenge".to_string()))?;
+
+    check_second_factor(
+        &repos,
+        user_id,
+        request.code.as_deref(),
+        request.recovery_code.as_deref(),
+    )
+    .await?
+    .require(ApiError::Unauthorized("Invalid code".to_string()))?;
+
+    if let Some(guest_id) = guest_id {
+        match repos.users.get_user(guest_id).await {
+            Ok(guest) if guest.guest => {
+                // The guest is deleted with the merge, its logins must not outlive it
+                repos
+                    .tokens
+                    .revoke_user_tokens(guest_id, None, config.tokens.access_token_expiry())
+                    .await?;
+                repos.guests.merge_guest(guest_id, user_id).await?;
+            }
+            // Already merged by an earlier use of the challenge or collected since
+            Ok(_) | Err(RepoError::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let user = repos.users.get_user(user_id).await?;
+    complete_login(&repos, &keys, &config.tokens, user, device).await
+}
+
+/// Turn 2FA off, needs a code and the password (if the account has one) again
+pub async fn disable_totp(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<DisableTotpRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = json.into_inner();
+    if !repos
+        .two_factor
+        .get_totp(user.user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled)
+    {
+        return Err(ApiError::NotFound(
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+
+    if let Some(hash) = repos.passwords.get_password_hash(user.user_id).await? {
+        let password = request.password.as_deref().unwrap_or_default();
+        if !bcrypt::verify(password, &hash).unwrap_or(false) {
+            return Err(ApiError::Forbidden("Wrong password".to_string()));
+        }
+    }
+
+    check_second_factor(
+        &repos,
+        user.user_id,
+        request.code.as_deref(),
+        request.recovery_code.as_deref(),
+    )
+    .await?
+    .require(ApiError::Forbidden("Invalid code".to_string()))?;
+
+    repos.two_factor.disable_totp(user.user_id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Replace the recovery codes, the old ones stop working
+pub async fn regenerate_recovery_codes(
+    repos: web::Data<Repos>,
+    user: AuthUser,
+    json: web::Json<TotpCodeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    check_second_factor(&repos, user.user_id, Some(&json.code), None)
+        .await?
+        .require(ApiError::Forbidden("Invalid code".to_string()))?;
+
+    let (codes, hashes) = generate_recovery_codes();
+    repos
+        .two_factor
+        .replace_recovery_codes(user.user_id, hashes)
+        .await?;
+    Ok(HttpResponse::Ok().json(RecoveryCodes {
+        recovery_codes: codes,
+    }))
+}
+
+/// Answer of the first login step for an account with 2FA
+/// `guest` is merged into the account only once the second step passes
+pub fn two_factor_challenge(
+    tokens: &TokenConfig,
+    user_id: Uuid,
+    guest: Option<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(TwoFactorChallenge {
+        two_factor_required: true,
+        challenge_token: create_challenge_token(tokens, user_id, guest)?,
+    }))
+}
+
+/// Short lived token standing in for the first login step
+pub fn create_challenge_token(
+    tokens: &TokenConfig,
+    user_id: Uuid,
+    guest: Option<Uuid>,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = ChallengeClaims {
+        sub: user_id.to_string(),
+        exp: (Utc::now() + CHALLENGE_LIFETIME).timestamp() as usize,
+        aud: CHALLENGE_AUDIENCE.to_string(),
+        guest: guest.map(|guest| guest.to_string()),
+    };
+    jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(tokens.refresh_secret.as_bytes()),
+    )
+}
+
+/// The user and the guest to merge into it, if any
+fn decode_challenge_token(tokens: &TokenConfig, token: &str) -> Option<(Uuid, Option<Uuid>)> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[CHALLENGE_AUDIENCE]);
+    let decoded = jsonwebtoken::decode::<ChallengeClaims>(
+        token,
+        &DecodingKey::from_secret(tokens.refresh_secret.as_bytes()),
+        &validation,
+    )
+    .ok()?;
+    let guest = match decoded.claims.guest {
+        Some(guest) => Some(Uuid::parse_str(&guest).ok()?),
+        None => None,
+    };
+    Some((Uuid::parse_str(&decoded.claims.sub).ok()?, guest))
+}
+
+/// Check a TOTP or recovery code of a user with 2FA enabled, wrong codes count towards the
+/// lockout
+async fn check_second_factor(
+    repos: &Repos,
+    user_id: Uuid,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<SecondFactor, RepoError> {
+    let two_factor = &repos.two_factor;
+    let totp = match two_factor.get_totp(user_id).await? {
+        Some(totp) if totp.enabled => totp,
+        _ => return Ok(SecondFactor::Invalid),
+    };
+    if totp.locked_until.is_some_and(|until| until > Utc::now()) {
+        return Ok(SecondFactor::Locked);
+    }
+
+    let valid = match (code, recovery_code) {
+        (Some(code), _) => match matching_step(&totp.secret, code) {
+            Some(step) => two_factor.record_totp_use(user_id, step).await?,
+            None => false,
+        },
+        (None, Some(recovery_code)) => {
+            let hash = hash_token_secret(&normalize_recovery_code(recovery_code));
+            two_factor.use_recovery_code(user_id, hash).await?
+        }
+        (None, None) => false,
+    };
+    if !valid {
+        two_factor
+            .record_totp_failure(user_id, TOTP_ATTEMPTS, Utc::now() + TOTP_LOCKOUT)
+            .await?;
+        return Ok(SecondFactor::Invalid);
+    }
+    Ok(SecondFactor::Valid)
+}
+
+/// SHA-1, six digits, 30 second steps, what every authenticator app supports
+fn build_totp(secret: Vec<u8>, account_name: String) -> Result<TOTP, totp_rs::TotpUrlError> {
+    TOTP::new(
+        totp_rs::Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        account_name,
+    )
+}
+
+/// Time step the code belongs to, one step of clock drift either way is accepted
+fn matching_step(secret: &str, code: &str) -> Option<i64> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    let totp = build_totp(secret, String::new()).ok()?;
+    let now = Utc::now().timestamp();
+    let code = code.trim();
+    (-1..=1)
+        .map(|offset| now + offset * totp.step as i64)
+        .find(|time| totp.generate(*time as u64) == code)
+        .map(|time| time / totp.step as i64)
+}
+
+/// Recovery codes as shown to the user and the hashes to store
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 10)
+                .to_lowercase();
+            let hash = hash_token_secret(&code);
+            (format!("{}-{}", &code[..5], &code[5..]), hash)
+        })
+        .unzip()
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
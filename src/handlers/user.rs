@@ -1,114 +1,234 @@
-use actix_web::{web, HttpResponse, Responder};
-use sqlx::PgPool;
+use actix_web::{web, HttpResponse};
 use uuid::Uuid;
 
 use crate::{
-    config::UNIQUE_VIOLATION,
-    db,
-    handlers::create_jwt_tokens,
-    models::{LoginResponse, OauthProvider, OauthUser, Project, User, UserPlan},
+    config::{Config, TokenConfig},
+    error::ApiError,
+    handlers::{issue_tokens, normalize_email, two_factor_challenge},
+    keys::KeyRing,
+    metrics::Metrics,
+    models::{
+        AuthorizeQuery, DeviceInfo, LoginRequest, LoginResponse, OauthProvider, OauthUser, Project,
+        User, UserPlan,
+    },
+    oauth::Providers,
+    repo::{RepoError, Repos},
 };
 
 /// Login user, create user if needed
+/// The user is derived from the verified provider credential, never from client supplied details
+#[allow(clippy::too_many_arguments)]
 pub async fn login_user(
-    pool: web::Data<PgPool>,
-    provider: web::Path<OauthProvider>,
-    json: web::Json<OauthUser>,
-) -> impl Responder {
-    let g_user = json.into_inner();
+    repos: web::Data<Repos>,
+    providers: web::Data<Providers>,
+    keys: web::Data<KeyRing>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    provider: web::Path<String>,
+    device: DeviceInfo,
+    json: web::Json<LoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let Ok(provider) = provider.parse::<OauthProvider>();
+    let o_user = verify_provider_credential(&providers, &provider, json.into_inner()).await?;
+    metrics.login(&provider.to_string());
+    let user = find_or_create_identity_user(&repos, provider, o_user).await?;
+    login_response(&repos, &keys, &config.tokens, user, device).await
+}
 
-    // Support more later
-    match provider.into_inner() {
+/// Check the credential with the provider and return the account it belongs to
+/// The email is normalized like the ones typed in, so both find the same user
+pub async fn verify_provider_credential(
+    providers: &Providers,
+    provider: &OauthProvider,
+    credential: LoginRequest,
+) -> Result<OauthUser, ApiError> {
+    let missing = |field: &str| {
+        ApiError::Validation(format!("Missing {}", field))
+            .with_details(serde_json::json!({ "field": field }))
+    };
+    let mut o_user = match provider {
         OauthProvider::google => {
-            // Creating user if not exist
-            let user = User::new(
-                Uuid::new_v4(),
-                g_user.name.clone(),
-                g_user.email.clone(),
-                Some(OauthProvider::google),
-                Some(g_user.picture),
-                UserPlan::free,
-            );
+            let id_token = credential.id_token.ok_or_else(|| missing("id_token"))?;
+            providers.google.verify(&id_token).await?
+        }
+        OauthProvider::github => {
+            let code = credential.code.ok_or_else(|| missing("code"))?;
+            providers
+                .github
+                .verify(&code, credential.redirect_uri.as_deref())
+                .await?
+        }
+        OauthProvider::oidc(name) => {
+            let oidc = providers
+                .oidc
+                .get(name)
+                .ok_or_else(|| ApiError::NotFound("Unknown provider".to_string()))?;
+            let code = credential.code.ok_or_else(|| missing("code"))?;
+            oidc.verify(
+                &code,
+                credential.redirect_uri.as_deref(),
+                credential.code_verifier.as_deref(),
+            )
+            .await?
+        }
+    };
+    o_user.email = normalize_email(&o_user.email).ok_or_else(|| {
+        ApiError::Unauthorized("The provider account has no usable email".to_string())
+    })?;
+    Ok(o_user)
+}
 
-            let res = db::create_user(pool.clone(), user.clone()).await;
+/// User linked to the provider account
+/// A provider account seen for the first time is linked to the user with its email, or to a new
+/// user, so accounts from before identities existed keep working
+/// Users that have or unlinked an identity of the provider link new ones themselves
+pub async fn find_or_create_identity_user(
+    repos: &Repos,
+    provider: OauthProvider,
+    o_user: OauthUser,
+) -> Result<User, ApiError> {
+    let provider_name = provider.to_string();
+    if let Some(user) = repos
+        .identities
+        .get_identity_user(&provider_name, &o_user.sub)
+        .await?
+    {
+        return Ok(user);
+    }
+    match repos.users.get_user_by_email(&o_user.email).await {
+        Ok(existing) => {
+            if repos
+                .identities
+                .had_identity(existing.user_id, &provider_name)
+                .await?
+            {
+                return Err(ApiError::Conflict(
+                    "Sign in to link this identity to the account with its email".to_string(),
+                ));
+            }
+        }
+        Err(RepoError::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
 
-            // Default project called "Unset"
-            let default_project = Project::new(
-                user.user_id,
-                Uuid::new_v4(),
-                "Unset".to_string(),
-                "grey".to_string(),
-                None,
-                None,
-            );
+    let user = User::new(
+        Uuid::new_v4(),
+        o_user.name.clone(),
+        o_user.email.clone(),
+        Some(provider),
+        o_user.picture.clone(),
+        UserPlan::free,
+    );
+    let user = find_or_create_user(repos, user).await?;
+    match repos
+        .identities
+        .create_identity(user.user_id, &provider_name, &o_user)
+        .await
+    {
+        Ok(()) => Ok(user),
+        // Linked by a concurrent login
+        Err(RepoError::Conflict) => repos
+            .identities
+            .get_identity_user(&provider_name, &o_user.sub)
+            .await?
+            .ok_or_else(|| RepoError::NotFound.into()),
+        Err(e) => Err(e.into()),
+    }
+}
 
-            // if user not exist, create user and add default project
-            if res.is_ok() {
-                if (db::add_project(pool.clone(), default_project).await).is_ok() {
-                    if let Ok(token) = create_jwt_tokens(&user.user_id.clone()) {
-                        let login_response = LoginResponse {
-                            user: user.clone(),
-                            access_token: token.access_token,
-                            refresh_token: token.refresh_token.clone(),
-                        };
-                        let token_id = Uuid::new_v4();
-                        let r_token_hash = bcrypt::hash(token.refresh_token, 10);
-                        if let Ok(hash) = r_token_hash {
-                            let r = db::store_refresh_token(
-                                pool,
-                                user.user_id,
-                                token_id,
-                                hash,
-                                token.expiry,
-                            )
-                            .await;
-                            if r.is_ok() {
-                                return HttpResponse::Ok().json(login_response);
-                            } else {
-                                return HttpResponse::InternalServerError().finish();
-                            }
-                        } else {
-                            return HttpResponse::InternalServerError().finish();
-                        }
-                    } else {
-                        return HttpResponse::InternalServerError().finish();
-                    }
-                } else {
-                    return HttpResponse::InternalServerError().finish();
-                }
-            } else if let Err(e) = res {
-                match e {
-                    sqlx::Error::Database(err) => {
-                        if err.code() == Some(UNIQUE_VIOLATION.into()) {
-                            // User already exist
-                            let user = db::get_user(pool, user.email).await;
-                            if let Ok(u) = user {
-                                if let Ok(token) = create_jwt_tokens(&u.user_id) {
-                                    let login_response = LoginResponse {
-                                        user: u,
-                                        access_token: token.access_token,
-                                        refresh_token: token.refresh_token,
-                                    };
-                                    return HttpResponse::Ok().json(login_response);
-                                } else {
-                                    return HttpResponse::InternalServerError().finish();
-                                }
-                            } else {
-                                return HttpResponse::NotFound().finish();
-                            }
-                        } else {
-                            return HttpResponse::InternalServerError().finish();
-                        }
-                    }
-                    _ => return HttpResponse::InternalServerError().finish(),
-                }
-            }
-            return HttpResponse::InternalServerError().finish();
+/// Authorization url of a configured OpenID Connect provider, so the apps don't need to know the
+/// provider's endpoints or our scopes
+pub async fn oidc_authorize(
+    providers: web::Data<Providers>,
+    provider: web::Path<String>,
+    query: web::Query<AuthorizeQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let oidc = providers
+        .oidc
+        .get(provider.as_str())
+        .ok_or_else(|| ApiError::NotFound("Unknown provider".to_string()))?;
+    let code_challenge = query.code_challenge.as_deref();
+    // S256 challenges are 32 bytes of base64url without padding
+    if code_challenge.is_some_and(|challenge| {
+        challenge.len() != 43
+            || !challenge
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    }) {
+        return Err(ApiError::Validation("Invalid code_challenge".to_string())
+            .with_details(serde_json::json!({ "field": "code_challenge" })));
+    }
+    let url = oidc
+        .authorization_url(&query.redirect_uri, query.state.as_deref(), code_challenge)
+        .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "authorization_url": url })))
+}
+
+/// Create the user together with the default project on first login, otherwise return the
+/// existing user with that email
+/// Only for logins that proved the email (providers, emailed codes)
+pub async fn find_or_create_user(repos: &Repos, user: User) -> Result<User, RepoError> {
+    match repos.users.create_user(user.clone()).await {
+        Ok(()) => {
+            create_default_project(repos, user.user_id).await?;
+            Ok(user)
         }
-        _ => HttpResponse::Unauthorized().body("Invalid provider"),
+        // User already exist
+        Err(RepoError::Conflict) => {
+            let user = repos.users.get_user_by_email(&user.email).await?;
+            repos
+                .passwords
+                .drop_unverified_password(user.user_id)
+                .await?;
+            Ok(user)
+        }
+        Err(e) => Err(e),
     }
 }
 
+/// Default project called "Unset", every new account starts with it
+pub async fn create_default_project(repos: &Repos, user_id: Uuid) -> Result<(), RepoError> {
+    let default_project = Project::new(
+        user_id,
+        Uuid::new_v4(),
+        "Unset".to_string(),
+        "grey".to_string(),
+        None,
+        None,
+    );
+    repos.projects.add_project(default_project).await
+}
+
+/// Finish the first login step, with the tokens or the second step challenge if the user has 2FA
+pub async fn login_response(
+    repos: &Repos,
+    keys: &KeyRing,
+    tokens: &TokenConfig,
+    user: User,
+    device: DeviceInfo,
+) -> Result<HttpResponse, ApiError> {
+    match repos.two_factor.get_totp(user.user_id).await? {
+        Some(totp) if totp.enabled => two_factor_challenge(tokens, user.user_id, None),
+        _ => complete_login(repos, keys, tokens, user, device).await,
+    }
+}
+
+/// Issue the tokens for a fresh login, every login starts a new refresh token family
+pub async fn complete_login(
+    repos: &Repos,
+    keys: &KeyRing,
+    tokens: &TokenConfig,
+    user: User,
+    device: DeviceInfo,
+) -> Result<HttpResponse, ApiError> {
+    let token = issue_tokens(repos, keys, tokens, user.user_id, Uuid::new_v4(), device).await?;
+    Ok(HttpResponse::Ok().json(LoginResponse {
+        user,
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+    }))
+}
+
 // Delete the user and the sessions linked to the user
 // pub async fn delete_user(pool: web::Data<PgPool>, user_id: web::Path<String>) -> impl Responder {
 //     let user_table_result = sqlx::query!("DELETE FROM users WHERE user_id = $1", user_id.clone())
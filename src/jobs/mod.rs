@@ -0,0 +1,244 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use actix_web::web;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::{sync::watch, task::JoinSet};
+
+use crate::{config::JobsConfig, db, mail::Mailer, models::JobStatus};
+
+pub mod schedule;
+pub mod tasks;
+
+pub use schedule::*;
+pub use tasks::*;
+
+/// First key of the advisory locks taken for jobs, keeps them apart from other locks on the
+/// database
+const LOCK_NAMESPACE: i32 = 0x6a6f6273;
+
+/// What the jobs get to work with
+#[derive(Clone)]
+pub struct JobContext {
+    pub pool: PgPool,
+    pub mailer: Arc<dyn Mailer>,
+}
+
+#[derive(Debug)]
+pub struct JobError(pub String);
+
+impl Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl From<sqlx::Error> for JobError {
+    fn from(e: sqlx::Error) -> Self {
+        JobError(e.to_string())
+    }
+}
+
+/// Periodic work, every replica schedules it but only one runs each scheduled time
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// Identifies the job in the run history and the advisory lock, keep it stable
+    fn name(&self) -> &'static str;
+
+    /// Do the work, returns a summary for the run history
+    async fn run(&self, ctx: &JobContext) -> Result<String, JobError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    Succeeded(String),
+    Failed(String),
+    /// Another replica is running the job or already ran it for this time
+    Skipped,
+}
+
+/// Run `job` for the time it was scheduled for, unless another replica holds its lock or
+/// already recorded a run for that time
+pub async fn run_job(
+    ctx: &JobContext,
+    job: Arc<dyn Job>,
+    scheduled_for: DateTime<Utc>,
+) -> Result<RunOutcome, sqlx::Error> {
+    // The lock gets a connection of its own instead of holding one of the app's for the whole
+    // run, it goes away with the connection even if this task is aborted
+    let mut lock = PgConnection::connect_with(&ctx.pool.connect_options()).await?;
+    let outcome = run_locked(ctx, &mut lock, job, scheduled_for).await;
+    // Closing releases the lock on every path, errors included
+    if let Err(e) = lock.close().await {
+        tracing::warn!(error = %e, "Failed to close the job lock connection");
+    }
+    outcome
+}
+
+async fn run_locked(
+    ctx: &JobContext,
+    lock: &mut PgConnection,
+    job: Arc<dyn Job>,
+    scheduled_for: DateTime<Utc>,
+) -> Result<RunOutcome, sqlx::Error> {
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_lock($1, hashtext($2)) AS "locked!""#,
+        LOCK_NAMESPACE,
+        job.name()
+    )
+    .fetch_one(&mut *lock)
+    .await?;
+    if !locked {
+        return Ok(RunOutcome::Skipped);
+    }
+    let pool = web::Data::new(ctx.pool.clone());
+    let Some(run_id) = db::claim_job_run(pool.clone(), job.name(), scheduled_for).await? else {
+        return Ok(RunOutcome::Skipped);
+    };
+
+    let started = std::time::Instant::now();
+    // Own task so a panicking job is recorded as a failure
+    let result = {
+        let ctx = ctx.clone();
+        let job = job.clone();
+        tokio::spawn(async move { job.run(&ctx).await }).await
+    };
+    let outcome = match result {
+        Ok(Ok(summary)) => RunOutcome::Succeeded(summary),
+        Ok(Err(e)) => RunOutcome::Failed(e.to_string()),
+        Err(e) => RunOutcome::Failed(format!("panicked: {}", e)),
+    };
+
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    let (status, detail) = match &outcome {
+        RunOutcome::Succeeded(summary) => {
+            tracing::info!(job = job.name(), %run_id, elapsed_ms, summary, "Job finished");
+            (JobStatus::Succeeded, summary)
+        }
+        RunOutcome::Failed(error) => {
+            tracing::error!(job = job.name(), %run_id, elapsed_ms, error, "Job failed");
+            (JobStatus::Failed, error)
+        }
+        RunOutcome::Skipped => unreachable!("the run was claimed"),
+    };
+    db::finish_job_run(pool, run_id, status, detail).await?;
+    Ok(outcome)
+}
+
+struct Scheduled {
+    schedule: Schedule,
+    job: Arc<dyn Job>,
+    next: Option<DateTime<Utc>>,
+}
+
+/// Runs jobs on their schedules until told to stop
+pub struct Scheduler {
+    ctx: JobContext,
+    jobs: Vec<Scheduled>,
+}
+
+impl Scheduler {
+    pub fn new(ctx: JobContext) -> Self {
+        Scheduler {
+            ctx,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// The built-in jobs with the configured schedules
+    pub fn from_config(config: &JobsConfig, ctx: JobContext) -> Self {
+        let scheduler = Scheduler::new(ctx)
+            .add(
+                config.purge_expired_tokens.clone(),
+                PurgeExpiredTokens {
+                    keep_runs_days: config.keep_runs_days,
+                },
+            )
+            .add(
+                config.stop_runaway_sessions.clone(),
+                StopRunawaySessions {
+                    max_hours: config.max_session_hours,
+                },
+            )
+            .add(
+                config.collect_guests.clone(),
+                CollectGuests {
+                    retention_days: config.guest_retention_days,
+                },
+            );
+        match &config.send_digests {
+            Some(schedule) => scheduler.add(schedule.clone(), SendDigests),
+            None => scheduler,
+        }
+    }
+
+    pub fn add(mut self, schedule: Schedule, job: impl Job + 'static) -> Self {
+        self.jobs.push(Scheduled {
+            schedule,
+            job: Arc::new(job),
+            next: None,
+        });
+        self
+    }
+
+    /// Start jobs as they come due until `shutdown` turns true or its sender goes away, then wait
+    /// up to `drain` for the running ones before abandoning them
+    pub async fn run(mut self, mut shutdown: watch::Receiver<bool>, drain: Duration) {
+        let now = Utc::now();
+        for scheduled in &mut self.jobs {
+            scheduled.next = scheduled.schedule.next_after(now);
+        }
+        let mut running = JoinSet::new();
+
+        while let Some(due) = self
+            .jobs
+            .iter()
+            .filter_map(|scheduled| scheduled.next)
+            .min()
+        {
+            let wait = (due - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let now = Utc::now();
+            for scheduled in &mut self.jobs {
+                let Some(next) = scheduled.next.filter(|next| *next <= now) else {
+                    continue;
+                };
+                // Times missed while the process was stalled are skipped, not caught up
+                scheduled.next = scheduled.schedule.next_after(now);
+                let ctx = self.ctx.clone();
+                let job = scheduled.job.clone();
+                running.spawn(async move {
+                    if let Err(e) = run_job(&ctx, job.clone(), next).await {
+                        tracing::error!(job = job.name(), error = %e, "Failed to run job");
+                    }
+                });
+            }
+            while running.try_join_next().is_some() {}
+        }
+
+        if running.is_empty() {
+            return;
+        }
+        tracing::info!(jobs = running.len(), "Waiting for running jobs");
+        let drained = tokio::time::timeout(drain, async {
+            while running.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            tracing::warn!(jobs = running.len(), "Abandoning jobs still running");
+            running.abort_all();
+        }
+    }
+}
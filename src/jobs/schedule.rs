@@ -0,0 +1,157 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, TimeZone, Timelike, Utc};
+use serde::Deserialize;
+
+/// When a job runs, a cron expression evaluated in UTC: `minute hour day-of-month month
+/// day-of-week` with `*`, lists, ranges and steps (`*/15`, `1-5`, `0,30`), or one of `@hourly`,
+/// `@daily`, `@weekly` and `@monthly`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    /// Sunday is 0 (and 7 when parsing)
+    days_of_week: u64,
+    /// As in cron, a day matches either field when both are restricted
+    any_day: bool,
+}
+
+impl Schedule {
+    /// First time strictly after `after` the schedule fires, whole minutes only
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.duration_trunc(Duration::minutes(1)).ok()? + Duration::minutes(1);
+        // Every month with a matching day shows up within a few years
+        let give_up = time + Duration::days(5 * 366);
+        while time < give_up {
+            if !contains(self.months, time.month()) {
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.matches_day(time) {
+                time = time.duration_trunc(Duration::days(1)).ok()? + Duration::days(1);
+            } else if !contains(self.hours, time.hour()) {
+                time = time.duration_trunc(Duration::hours(1)).ok()? + Duration::hours(1);
+            } else if !contains(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = contains(self.days_of_month, time.day());
+        let day_of_week = contains(self.days_of_week, time.weekday().num_days_from_sunday());
+        if self.any_day {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+}
+
+fn contains(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expression = s.trim();
+        let expanded = match expression {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(format!(
+                "{:?} is not a cron expression with 5 fields",
+                expression
+            ));
+        };
+
+        let mut days_of_week_set = parse_field(days_of_week, 0, 7, "day of week")?;
+        // 7 is another Sunday
+        if contains(days_of_week_set, 7) {
+            days_of_week_set = (days_of_week_set & !(1 << 7)) | 1;
+        }
+        let schedule = Schedule {
+            expression: expression.to_string(),
+            minutes: parse_field(minutes, 0, 59, "minute")?,
+            hours: parse_field(hours, 0, 23, "hour")?,
+            days_of_month: parse_field(days_of_month, 1, 31, "day of month")?,
+            months: parse_field(months, 1, 12, "month")?,
+            days_of_week: days_of_week_set,
+            any_day: !days_of_month.starts_with('*') && !days_of_week.starts_with('*'),
+        };
+        if schedule.next_after(Utc::now()).is_none() {
+            return Err(format!("{:?} never fires", expression));
+        }
+        Ok(schedule)
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// Bit set of the values a field matches, e.g. `*/15` or `1-5,10`
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, String> {
+    let invalid = || format!("{:?} is not a valid {} ({}-{})", field, name, min, max);
+    let number = |value: &str| -> Result<u32, String> {
+        value
+            .parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(invalid)
+    };
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/10` means from 5 to the end
+                None if part.contains('/') => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
@@ -0,0 +1,122 @@
+use actix_web::web;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+
+use crate::{
+    db,
+    jobs::{Job, JobContext, JobError},
+    mail::Email,
+    models::ProjectFocus,
+};
+
+/// Delete expired refresh tokens and revocations, and forget old job runs
+pub struct PurgeExpiredTokens {
+    pub keep_runs_days: i32,
+}
+
+#[async_trait]
+impl Job for PurgeExpiredTokens {
+    fn name(&self) -> &'static str {
+        "purge_expired_tokens"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, JobError> {
+        let pool = web::Data::new(ctx.pool.clone());
+        let tokens = db::delete_expired_tokens(pool.clone()).await?;
+        let runs = db::delete_old_job_runs(pool, self.keep_runs_days).await?;
+        Ok(format!("deleted {} tokens and {} job runs", tokens, runs))
+    }
+}
+
+/// End sessions a client forgot to stop, e.g. because it crashed mid-session
+pub struct StopRunawaySessions {
+    pub max_hours: i32,
+}
+
+#[async_trait]
+impl Job for StopRunawaySessions {
+    fn name(&self) -> &'static str {
+        "stop_runaway_sessions"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, JobError> {
+        let pool = web::Data::new(ctx.pool.clone());
+        let stopped = db::stop_runaway_sessions(pool, self.max_hours).await?;
+        Ok(format!("stopped {} sessions", stopped))
+    }
+}
+
+/// Delete guest accounts unused for `retention_days` days
+pub struct CollectGuests {
+    pub retention_days: i32,
+}
+
+#[async_trait]
+impl Job for CollectGuests {
+    fn name(&self) -> &'static str {
+        "collect_guests"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, JobError> {
+        let pool = web::Data::new(ctx.pool.clone());
+        let deleted = db::delete_inactive_guests(pool, self.retention_days).await?;
+        Ok(format!("deleted {} guests", deleted))
+    }
+}
+
+/// Email the users who asked for it and focused in the past week their time per project
+pub struct SendDigests;
+
+#[async_trait]
+impl Job for SendDigests {
+    fn name(&self) -> &'static str {
+        "send_digests"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<String, JobError> {
+        let pool = web::Data::new(ctx.pool.clone());
+        let focus = db::get_focus_since(pool, Utc::now() - Duration::days(7)).await?;
+
+        let (mut sent, mut failed) = (0, Vec::new());
+        for user in focus.chunk_by(|a, b| a.user_id == b.user_id) {
+            match ctx.mailer.send(digest(user)).await {
+                Ok(()) => sent += 1,
+                Err(e) => failed.push(format!("{}: {}", user[0].email, e)),
+            }
+        }
+        if failed.is_empty() {
+            Ok(format!("sent {} digests", sent))
+        } else {
+            Err(JobError(format!(
+                "sent {} digests, {} failed: {}",
+                sent,
+                failed.len(),
+                failed.join("; ")
+            )))
+        }
+    }
+}
+
+/// The digest of one user, `projects` are the rows of that user
+fn digest(projects: &[ProjectFocus]) -> Email {
+    let user = &projects[0];
+    let total: i64 = projects.iter().map(|project| project.seconds).sum();
+    let lines: Vec<String> = projects
+        .iter()
+        .map(|project| format!("  {}: {}", project.project_name, hours(project.seconds)))
+        .collect();
+    Email {
+        to: user.email.clone(),
+        subject: "Your week in Kairos".to_string(),
+        body: format!(
+            "Hi {},\n\nYou focused for {} in the past 7 days:\n\n{}\n",
+            user.name,
+            hours(total),
+            lines.join("\n")
+        ),
+    }
+}
+
+fn hours(seconds: i64) -> String {
+    format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60)
+}
@@ -0,0 +1,370 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::{pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::models::Claims;
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo, the raw 32 byte key follows it
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Key access tokens are signed and verified with
+/// With an asymmetric key the public half is published as JWKS, so other services can verify
+/// our tokens without being able to mint them
+pub struct AccessTokenKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    /// Missing for keys only kept around to verify tokens issued before a rotation
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    jwk: Option<Value>,
+}
+
+#[derive(Debug)]
+pub struct KeyError(String);
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid signing key: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl AccessTokenKey {
+    /// Shared secret (HS256), tokens can't be verified by anyone else
+    pub fn from_secret(kid: Option<String>, secret: &[u8]) -> Self {
+        Self {
+            kid,
+            algorithm: Algorithm::HS256,
+            encoding: Some(EncodingKey::from_secret(secret)),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// RS256 or EdDSA (Ed25519) key pair from PEM encoded PKCS#8 private and SPKI public keys
+    /// Without the private key it can only verify. `kid` defaults to the RFC 7638 thumbprint
+    pub fn from_pem(
+        algorithm: Algorithm,
+        kid: Option<String>,
+        private_pem: Option<&[u8]>,
+        public_pem: &[u8],
+    ) -> Result<Self, KeyError> {
+        let err = |e: jsonwebtoken::errors::Error| KeyError(e.to_string());
+        let (encoding, decoding, jwk) = match algorithm {
+            Algorithm::RS256 => {
+                let public = std::str::from_utf8(public_pem)
+                    .ok()
+                    .and_then(|pem| RsaPublicKey::from_public_key_pem(pem).ok())
+                    .ok_or_else(|| KeyError("public key is not an RSA SPKI PEM".to_string()))?;
+                (
+                    private_pem
+                        .map(EncodingKey::from_rsa_pem)
+                        .transpose()
+                        .map_err(err)?,
+                    DecodingKey::from_rsa_pem(public_pem).map_err(err)?,
+                    // Members in lexicographic order as the thumbprint needs them
+                    json!({
+                        "e": URL_SAFE_NO_PAD.encode(public.e().to_bytes_be()),
+                        "kty": "RSA",
+                        "n": URL_SAFE_NO_PAD.encode(public.n().to_bytes_be()),
+                    }),
+                )
+            }
+            Algorithm::EdDSA => {
+                let der = pem_body(public_pem)?;
+                let raw = der
+                    .strip_prefix(&ED25519_SPKI_PREFIX)
+                    .filter(|raw| raw.len() == 32)
+                    .ok_or_else(|| KeyError("public key is not an Ed25519 SPKI PEM".to_string()))?;
+                (
+                    private_pem
+                        .map(EncodingKey::from_ed_pem)
+                        .transpose()
+                        .map_err(err)?,
+                    DecodingKey::from_ed_pem(public_pem).map_err(err)?,
+                    json!({
+                        "crv": "Ed25519",
+                        "kty": "OKP",
+                        "x": URL_SAFE_NO_PAD.encode(raw),
+                    }),
+                )
+            }
+            _ => return Err(KeyError(format!("unsupported algorithm {:?}", algorithm))),
+        };
+
+        // Refuse a key pair that doesn't belong together, it would only fail on the first login
+        if let Some(encoding) = &encoding {
+            let probe = Claims {
+                sub: String::new(),
+                exp: usize::MAX,
+                jti: String::new(),
+                fam: String::new(),
+            };
+            let signed =
+                jsonwebtoken::encode(&Header::new(algorithm), &probe, encoding).map_err(err)?;
+            jsonwebtoken::decode::<Claims>(&signed, &decoding, &Validation::new(algorithm))
+                .map_err(|_| KeyError("private and public key don't match".to_string()))?;
+        }
+
+        let kid = kid.unwrap_or_else(|| thumbprint(&jwk));
+        let mut jwk = jwk;
+        jwk["kid"] = json!(kid);
+        jwk["use"] = json!("sig");
+        jwk["alg"] = json!(format!("{:?}", algorithm));
+
+        Ok(Self {
+            kid: Some(kid),
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+
+    pub fn sign(&self, claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+        let encoding = self.encoding.as_ref().ok_or(ErrorKind::InvalidKeyFormat)?;
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.kid.clone();
+        jsonwebtoken::encode(&header, claims, encoding)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        let validation = Validation::new(self.algorithm);
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation).map(|data| data.claims)
+    }
+}
+
+/// Every key access tokens may be verified with, plus the one new tokens are signed with
+/// Keys are looked up by the `kid` in the token header. Rotating means adding a new key, making
+/// it active and giving the previous one a retirement time past the last token it signed
+pub struct KeyRing {
+    /// Manifest the keys are (re)loaded from, `None` for a fixed key
+    manifest: Option<PathBuf>,
+    keys: RwLock<RingKeys>,
+}
+
+struct RingKeys {
+    active: usize,
+    keys: Vec<RingKey>,
+}
+
+struct RingKey {
+    key: AccessTokenKey,
+    retires_at: Option<DateTime<Utc>>,
+}
+
+impl RingKey {
+    fn retired(&self) -> bool {
+        self.retires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+/// Key ring manifest, a JSON file listing the keys
+#[derive(Deserialize)]
+struct Manifest {
+    active: String,
+    keys: Vec<ManifestKey>,
+}
+
+#[derive(Deserialize)]
+struct ManifestKey {
+    kid: String,
+    algorithm: String,
+    /// HS256 only
+    secret: Option<String>,
+    /// Key paths, relative to the manifest
+    private_key: Option<PathBuf>,
+    public_key: Option<PathBuf>,
+    retires_at: Option<DateTime<Utc>>,
+}
+
+impl KeyRing {
+    /// A single key that both signs and verifies, it can't be rotated without a restart
+    pub fn single(key: AccessTokenKey) -> Self {
+        Self {
+            manifest: None,
+            keys: RwLock::new(RingKeys {
+                active: 0,
+                keys: vec![RingKey {
+                    key,
+                    retires_at: None,
+                }],
+            }),
+        }
+    }
+
+    /// Load the keys listed in a manifest, see [`KeyRing::reload`] to pick up changes to it
+    pub fn load(manifest: impl Into<PathBuf>) -> Result<Self, KeyError> {
+        let manifest = manifest.into();
+        let keys = read_manifest(&manifest)?;
+        Ok(Self {
+            manifest: Some(manifest),
+            keys: RwLock::new(keys),
+        })
+    }
+
+    /// Re-read the manifest, on any error the current keys stay in use
+    pub fn reload(&self) -> Result<(), KeyError> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        let keys = read_manifest(manifest)?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    pub fn sign(&self, claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+        let keys = self.keys.read().unwrap();
+        keys.keys[keys.active].key.sign(claims)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .keys
+            .iter()
+            .find(|k| k.key.kid == header.kid && !k.retired())
+            .ok_or(ErrorKind::InvalidSignature)?;
+        key.key.verify(token)
+    }
+
+    /// Public keys that aren't retired as JSON Web Key Set, shared secrets are left out
+    pub fn jwks(&self) -> Value {
+        let keys = self.keys.read().unwrap();
+        let jwks: Vec<&Value> = keys
+            .keys
+            .iter()
+            .filter(|k| !k.retired())
+            .filter_map(|k| k.key.jwk.as_ref())
+            .collect();
+        json!({ "keys": jwks })
+    }
+}
+
+/// Reload the key ring whenever the process receives SIGHUP
+#[cfg(unix)]
+pub async fn reload_on_hangup(keys: Arc<KeyRing>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+    while hangup.recv().await.is_some() {
+        match keys.reload() {
+            Ok(()) => tracing::info!("Reloaded JWT keys"),
+            Err(e) => tracing::warn!(error = %e, "Keeping the current JWT keys"),
+        }
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<RingKeys, KeyError> {
+    let contents = fs::read(path).map_err(|e| KeyError(format!("{}: {}", path.display(), e)))?;
+    let manifest: Manifest = serde_json::from_slice(&contents)
+        .map_err(|e| KeyError(format!("{}: {}", path.display(), e)))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut keys: Vec<RingKey> = Vec::with_capacity(manifest.keys.len());
+    for entry in manifest.keys {
+        if keys.iter().any(|k| k.key.kid.as_ref() == Some(&entry.kid)) {
+            return Err(KeyError(format!("duplicate kid {}", entry.kid)));
+        }
+        let key = match entry.algorithm.as_str() {
+            "HS256" => {
+                let secret = entry
+                    .secret
+                    .ok_or_else(|| KeyError(format!("key {} needs a secret", entry.kid)))?;
+                AccessTokenKey::from_secret(Some(entry.kid), secret.as_bytes())
+            }
+            algorithm => {
+                let algorithm = parse_algorithm(algorithm)?;
+                let public_key = entry
+                    .public_key
+                    .ok_or_else(|| KeyError(format!("key {} needs a public_key", entry.kid)))?;
+                load_pem_files(
+                    algorithm,
+                    Some(entry.kid),
+                    entry.private_key.map(|p| dir.join(p)).as_deref(),
+                    &dir.join(public_key),
+                )?
+            }
+        };
+        keys.push(RingKey {
+            key,
+            retires_at: entry.retires_at,
+        });
+    }
+
+    let active = keys
+        .iter()
+        .position(|k| k.key.kid.as_ref() == Some(&manifest.active))
+        .ok_or_else(|| KeyError(format!("active key {} isn't listed", manifest.active)))?;
+    if keys[active].key.encoding.is_none() {
+        return Err(KeyError(format!(
+            "active key {} has no private_key",
+            manifest.active
+        )));
+    }
+    if keys[active].retires_at.is_some() {
+        return Err(KeyError(format!(
+            "active key {} can't have a retirement time",
+            manifest.active
+        )));
+    }
+
+    Ok(RingKeys { active, keys })
+}
+
+/// `JWT_ALGORITHM` names as used in the JWS `alg` header
+pub fn parse_algorithm(name: &str) -> Result<Algorithm, KeyError> {
+    match Algorithm::from_str(name) {
+        Ok(algorithm @ (Algorithm::RS256 | Algorithm::EdDSA)) => Ok(algorithm),
+        _ => Err(KeyError(format!(
+            "unsupported algorithm {}, use RS256 or EdDSA",
+            name
+        ))),
+    }
+}
+
+/// Load the key pair from PEM files
+pub fn load_pem_files(
+    algorithm: Algorithm,
+    kid: Option<String>,
+    private_path: Option<&Path>,
+    public_path: &Path,
+) -> Result<AccessTokenKey, KeyError> {
+    let read =
+        |path: &Path| fs::read(path).map_err(|e| KeyError(format!("{}: {}", path.display(), e)));
+    let private_pem = private_path.map(read).transpose()?;
+    AccessTokenKey::from_pem(algorithm, kid, private_pem.as_deref(), &read(public_path)?)
+}
+
+fn pem_body(pem: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let pem = std::str::from_utf8(pem).map_err(|e| KeyError(e.to_string()))?;
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(body)
+        .map_err(|e| KeyError(e.to_string()))
+}
+
+/// https://www.rfc-editor.org/rfc/rfc7638
+fn thumbprint(jwk: &Value) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.to_string().as_bytes()))
+}
@@ -1,39 +1,210 @@
-use std::net::TcpListener;
+use std::{net::TcpListener, sync::Arc, time::Duration};
 
 use actix_cors::Cors;
-use actix_web::{middleware::from_fn, web, App, HttpServer};
-use dotenv::dotenv;
-use handlers::jwt_middleware;
+use actix_web::{
+    dev::Server, http::Method, middleware::from_fn, web, App, HttpResponse, HttpServer,
+};
+use error::{extractor_error, ApiError};
+use handlers::{jwt_middleware, metrics_middleware, request_id_middleware, REQUEST_ID_HEADER};
+use jobs::{JobContext, Scheduler};
+use keys::KeyRing;
+use mail::Mailer;
+use metrics::Metrics;
+use migrate::MigrateCommand;
+use oauth::{GithubClient, GoogleVerifier, KeySource, OidcProvider, Providers, GOOGLE_JWKS_URL};
+use repo::Repos;
 use routes::configure_routes;
+use sqlx::{migrate::MigrateError, PgPool};
+use tokio::sync::watch;
 
 mod config;
 mod db;
+mod error;
 mod handlers;
+pub mod jobs;
+pub mod keys;
+pub mod mail;
+mod metrics;
+pub mod migrate;
 mod models;
+pub mod oauth;
+pub mod repo;
 mod routes;
+pub mod telemetry;
+pub mod webauthn;
 
-pub async fn run(listener: TcpListener) -> Result<(), std::io::Error> {
-    dotenv().ok();
+pub use config::{Config, ConfigError, JobsConfig, LogFormat, LoggingConfig, MailTransport};
+pub use db::create_pool;
 
-    let pool = db::create_pool().await;
+pub async fn run(listener: TcpListener, config: Config) -> Result<(), std::io::Error> {
+    let pool = db::create_pool(&config.database)
+        .await
+        .map_err(std::io::Error::other)?;
+    if config.database.run_migrations {
+        migrate::up(&pool).await.map_err(std::io::Error::other)?;
+    }
+    migrate::check_schema(&pool)
+        .await
+        .map_err(std::io::Error::other)?;
+
+    let providers = Providers {
+        google: GoogleVerifier::new(
+            config.google.client_ids.clone(),
+            KeySource::Remote(GOOGLE_JWKS_URL.to_string()),
+        ),
+        github: GithubClient::new(
+            config.github.client_id.clone(),
+            config.github.client_secret.clone(),
+            config.github.token_url.clone(),
+            config.github.api_url.clone(),
+        ),
+        oidc: config
+            .oidc
+            .iter()
+            .map(|(name, p)| {
+                let provider = OidcProvider::new(
+                    p.issuer.clone(),
+                    p.client_id.clone(),
+                    p.client_secret.clone(),
+                    p.scopes.clone(),
+                );
+                (name.clone(), provider)
+            })
+            .collect(),
+    };
+
+    let keys = config
+        .tokens
+        .access_token_keys()
+        .map_err(std::io::Error::other)?;
+    let keys = Arc::new(keys);
+    #[cfg(unix)]
+    tokio::spawn(keys::reload_on_hangup(keys.clone()));
+
+    let mailer = config.mail.mailer().map_err(std::io::Error::other)?;
+    let drain = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let (shutdown, shutdown_requested) = watch::channel(false);
+    let jobs = config.jobs.enabled.then(|| {
+        let ctx = JobContext {
+            pool: pool.clone(),
+            mailer: mailer.clone(),
+        };
+        tokio::spawn(Scheduler::from_config(&config.jobs, ctx).run(shutdown_requested, drain))
+    });
+
+    let repos = Repos::postgres(pool.clone());
+    let server = serve(listener, config, pool, repos, providers, keys, mailer)?;
+    let handle = server.handle();
+    let stop = tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down, draining requests and jobs");
+        shutdown.send_replace(true);
+        handle.stop(true).await;
+    });
+
+    let served = server.await;
+    // Also stops the scheduler if the server ended on its own, by dropping the sender
+    stop.abort();
+    if let Some(jobs) = jobs {
+        jobs.await.map_err(std::io::Error::other)?;
+    }
+    served
+}
+
+/// SIGTERM (what orchestrators send) or Ctrl+C
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl+C");
+}
+
+/// Apply the pending migrations or print the status of each
+pub async fn run_migrate(config: Config, command: MigrateCommand) -> Result<(), MigrateError> {
+    let pool = db::create_pool(&config.database).await?;
+    if command == MigrateCommand::Up {
+        migrate::up(&pool).await?;
+    }
+    for migration in migrate::status(&pool).await? {
+        println!(
+            "{} {:<8} {}",
+            migration.version, migration.state, migration.description
+        );
+    }
+    Ok(())
+}
+
+/// Build the server around already created dependencies, lets the tests swap them out
+pub fn serve(
+    listener: TcpListener,
+    config: Config,
+    pool: PgPool,
+    repos: Repos,
+    providers: Providers,
+    keys: Arc<KeyRing>,
+    mailer: Arc<dyn Mailer>,
+) -> Result<Server, std::io::Error> {
+    let shutdown_timeout = config.server.shutdown_timeout_secs;
+    let repos = web::Data::new(repos);
+    let providers = web::Data::new(providers);
+    let keys = web::Data::from(keys);
+    let mailer: web::Data<dyn Mailer> = web::Data::from(mailer);
+    let relying_party = web::Data::new(config.relying_party());
+    let config = web::Data::new(config);
+    let metrics = web::Data::new(Metrics::new());
+
+    let server = HttpServer::new(move || {
+        let cors =
+            config
+                .cors
+                .allowed_origins
+                .iter()
+                .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+                .allowed_methods(config.cors.allowed_methods.iter().map(|method| {
+                    Method::from_bytes(method.as_bytes()).expect("validated in Config")
+                }))
+                .allowed_headers(vec![
+                    actix_web::http::header::CONTENT_TYPE,
+                    actix_web::http::header::ACCEPT,
+                ])
+                .expose_headers(vec![REQUEST_ID_HEADER])
+                .supports_credentials();
 
-    HttpServer::new(move || {
         App::new()
             .wrap(from_fn(jwt_middleware))
-            .wrap(
-                Cors::default()
-                    .allowed_origin("http://localhost:6080")
-                    .allowed_methods(vec!["GET", "POST"])
-                    .allowed_headers(vec![
-                        actix_web::http::header::CONTENT_TYPE,
-                        actix_web::http::header::ACCEPT,
-                    ])
-                    .supports_credentials(),
-            )
+            .wrap(from_fn(metrics_middleware))
+            .wrap(cors)
+            .wrap(from_fn(request_id_middleware))
+            .app_data(config.clone())
+            .app_data(metrics.clone())
             .app_data(web::Data::new(pool.clone()))
+            .app_data(repos.clone())
+            .app_data(providers.clone())
+            .app_data(keys.clone())
+            .app_data(relying_party.clone())
+            .app_data(mailer.clone())
+            .app_data(web::JsonConfig::default().error_handler(extractor_error))
+            .app_data(web::PathConfig::default().error_handler(extractor_error))
+            .app_data(web::QueryConfig::default().error_handler(extractor_error))
             .configure(configure_routes)
+            .default_service(web::to(|| async {
+                Err::<HttpResponse, _>(ApiError::NotFound("No such route".to_string()))
+            }))
     })
     .listen(listener)?
-    .run()
-    .await
+    // `run` handles the signals so jobs drain alongside the requests
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
+    .run();
+    Ok(server)
 }
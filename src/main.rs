@@ -1,13 +1,41 @@
-use std::net::TcpListener;
+use std::{env, net::TcpListener, process};
 
-use kairos_server::run;
+use dotenv::dotenv;
+use kairos_server::{migrate::MigrateCommand, run, run_migrate, telemetry::init_tracing, Config};
+
+const USAGE: &str = "Usage: kairos-server [serve | migrate [up|status]]";
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let listener = TcpListener::bind("0.0.0.0:33333").expect("Failed to bind random port");
-    println!(
-        "Listening on port {}",
-        listener.local_addr().unwrap().port()
-    );
-    run(listener).await
+async fn main() {
+    dotenv().ok();
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    init_tracing(&config.logging);
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] | ["serve"] => serve(config).await,
+        ["migrate"] | ["migrate", "up"] => run_migrate(config, MigrateCommand::Up)
+            .await
+            .map_err(std::io::Error::other),
+        ["migrate", "status"] => run_migrate(config, MigrateCommand::Status)
+            .await
+            .map_err(std::io::Error::other),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        tracing::error!("{}", e);
+        process::exit(1);
+    }
+}
+
+async fn serve(config: Config) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(&config.server.bind_address)?;
+    tracing::info!(address = %listener.local_addr()?, "Listening");
+    run(listener, config).await
 }
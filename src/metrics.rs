@@ -0,0 +1,216 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Target of the events sqlx emits when a connection is handed out
+pub const POOL_ACQUIRE_TARGET: &str = "sqlx::pool::acquire";
+
+/// Scrapes within this long of the last count of running sessions reuse it
+const RUNNING_SESSIONS_MAX_AGE: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    /// Fed from the log events of every pool in the process by `PoolAcquireLayer`
+    static ref POOL_ACQUIRE_SECONDS: Histogram = Histogram::with_opts(
+        HistogramOpts::new(
+            "db_pool_acquire_seconds",
+            "Time spent waiting for a database connection"
+        )
+        .buckets(vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0])
+    )
+    .expect("valid histogram");
+}
+
+/// Prometheus metrics of one server, rendered by `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    logins: IntCounterVec,
+    token_refreshes: IntCounterVec,
+    db_connections: IntGaugeVec,
+    db_max_connections: IntGauge,
+    running_sessions: IntGauge,
+    running_sessions_counted_at: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .expect("valid counter");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["method", "route"],
+        )
+        .expect("valid histogram");
+        let logins = IntCounterVec::new(
+            Opts::new("logins_total", "Successful logins by provider or method"),
+            &["provider"],
+        )
+        .expect("valid counter");
+        let token_refreshes = IntCounterVec::new(
+            Opts::new("token_refreshes_total", "Refresh token exchanges by result"),
+            &["result"],
+        )
+        .expect("valid counter");
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Open database connections by state"),
+            &["state"],
+        )
+        .expect("valid gauge");
+        let db_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Most connections the pool will open",
+        )
+        .expect("valid gauge");
+        let running_sessions = IntGauge::new(
+            "running_sessions",
+            "Focus sessions that have started but not ended",
+        )
+        .expect("valid gauge");
+
+        for collector in [
+            Box::new(http_requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http_request_duration.clone()),
+            Box::new(logins.clone()),
+            Box::new(token_refreshes.clone()),
+            Box::new(db_connections.clone()),
+            Box::new(db_max_connections.clone()),
+            Box::new(running_sessions.clone()),
+            Box::new(POOL_ACQUIRE_SECONDS.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            logins,
+            token_refreshes,
+            db_connections,
+            db_max_connections,
+            running_sessions,
+            running_sessions_counted_at: Mutex::new(None),
+        }
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// A user signed in with a provider (`google`, an OIDC provider name) or a method
+    /// (`password`, `passkey`, ...)
+    pub fn login(&self, provider: &str) {
+        self.logins.with_label_values(&[provider]).inc();
+    }
+
+    /// `rotated` or `reuse_detected`
+    pub fn token_refresh(&self, result: &str) {
+        self.token_refreshes.with_label_values(&[result]).inc();
+    }
+
+    /// Sample the pool and, when the last count is stale, the database, then encode everything in
+    /// the text format
+    /// A failed count keeps the previous value, so scrapes still work while the database is down
+    pub async fn render(&self, pool: &PgPool) -> String {
+        if self.running_sessions_stale() {
+            let running = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM sessions WHERE ended_at IS NULL"#
+            )
+            .fetch_one(pool)
+            .await;
+            match running {
+                Ok(running) => self.running_sessions.set(running),
+                Err(e) => tracing::warn!(error = %e, "Failed to count the running sessions"),
+            }
+        }
+
+        let idle = pool.num_idle() as i64;
+        self.db_connections.with_label_values(&["idle"]).set(idle);
+        self.db_connections
+            .with_label_values(&["in_use"])
+            .set(pool.size() as i64 - idle);
+        self.db_max_connections
+            .set(pool.options().get_max_connections() as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode as text");
+        String::from_utf8(buffer).expect("the text format is UTF-8")
+    }
+
+    /// Whether this scrape counts the running sessions, concurrent scrapes leave it to the first
+    fn running_sessions_stale(&self) -> bool {
+        let mut counted_at = self.running_sessions_counted_at.lock().unwrap();
+        match *counted_at {
+            Some(at) if at.elapsed() < RUNNING_SESSIONS_MAX_AGE => false,
+            _ => {
+                *counted_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Records how long connections took to acquire, from the events sqlx logs on every acquire
+pub struct PoolAcquireLayer;
+
+impl<S: Subscriber> Layer<S> for PoolAcquireLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if event.metadata().target() != POOL_ACQUIRE_TARGET {
+            return;
+        }
+        let mut visitor = AcquiredAfter(None);
+        event.record(&mut visitor);
+        if let Some(seconds) = visitor.0 {
+            POOL_ACQUIRE_SECONDS.observe(seconds);
+        }
+    }
+}
+
+struct AcquiredAfter(Option<f64>);
+
+impl Visit for AcquiredAfter {
+    // sqlx spells it that way
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "aquired_after_secs" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fmt::Display};
+
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migrator},
+    PgPool,
+};
+
+/// The `migrations/` directory, compiled into the binary
+/// Versions applied by a newer binary are tolerated so older replicas keep booting during a
+/// rolling deploy
+pub static MIGRATOR: Migrator = Migrator {
+    ignore_missing: true,
+    ..sqlx::migrate!()
+};
+
+/// `kairos-server migrate [up|status]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateCommand {
+    Up,
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the file changed since
+    Modified,
+    /// Started but didn't finish
+    Failed,
+    /// Applied by a newer binary, not embedded in this one
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The database schema is older than this binary expects
+    Behind(Vec<MigrationStatus>),
+    Migrate(MigrateError),
+}
+
+impl Display for MigrationState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "modified",
+            MigrationState::Failed => "failed",
+            MigrationState::Unknown => "unknown",
+        };
+        f.pad(state)
+    }
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Behind(migrations) => {
+                write!(
+                    f,
+                    "Database schema is behind, run `kairos-server migrate up`:"
+                )?;
+                for migration in migrations {
+                    write!(
+                        f,
+                        "\n  {} {} {}",
+                        migration.version, migration.state, migration.description
+                    )?;
+                }
+                Ok(())
+            }
+            SchemaError::Migrate(e) => write!(f, "Can't read the migration status: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl From<MigrateError> for SchemaError {
+    fn from(e: MigrateError) -> Self {
+        SchemaError::Migrate(e)
+    }
+}
+
+/// Apply every pending migration, holding an advisory lock so replicas don't race
+pub async fn up(pool: &PgPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(pool).await
+}
+
+/// Every embedded migration and every version in the database, ordered by version
+/// Doesn't create the migrations table, a fresh database just has everything pending
+pub async fn status(pool: &PgPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    let has_table: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    let (applied, failed) = if has_table {
+        (
+            conn.list_applied_migrations().await?,
+            conn.dirty_version().await?,
+        )
+    } else {
+        (Vec::new(), None)
+    };
+    let mut applied: HashMap<i64, _> = applied
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect();
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let state = match applied.remove(&migration.version) {
+                _ if failed == Some(migration.version) => MigrationState::Failed,
+                None => MigrationState::Pending,
+                Some(checksum) if checksum != migration.checksum => MigrationState::Modified,
+                Some(_) => MigrationState::Applied,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    statuses.extend(applied.into_keys().map(|version| MigrationStatus {
+        version,
+        description: String::new(),
+        state: if failed == Some(version) {
+            MigrationState::Failed
+        } else {
+            MigrationState::Unknown
+        },
+    }));
+    statuses.sort_by_key(|migration| migration.version);
+    Ok(statuses)
+}
+
+/// Refuse to run against a schema that is missing migrations this binary relies on
+pub async fn check_schema(pool: &PgPool) -> Result<(), SchemaError> {
+    let behind: Vec<MigrationStatus> = status(pool)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.state != MigrationState::Applied)
+        .filter(|migration| migration.state != MigrationState::Unknown)
+        .collect();
+    if behind.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError::Behind(behind))
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What a personal access token is allowed to do, JWTs from a login can do everything
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "projects:read")]
+    ProjectsRead,
+    #[serde(rename = "projects:write")]
+    ProjectsWrite,
+    #[serde(rename = "sessions:read")]
+    SessionsRead,
+    #[serde(rename = "sessions:write")]
+    SessionsWrite,
+    #[serde(rename = "stats:read")]
+    StatsRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ProjectsRead => "projects:read",
+            Scope::ProjectsWrite => "projects:write",
+            Scope::SessionsRead => "sessions:read",
+            Scope::SessionsWrite => "sessions:write",
+            Scope::StatsRead => "stats:read",
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "projects:read" => Ok(Scope::ProjectsRead),
+            "projects:write" => Ok(Scope::ProjectsWrite),
+            "sessions:read" => Ok(Scope::SessionsRead),
+            "sessions:write" => Ok(Scope::SessionsWrite),
+            "stats:read" => Ok(Scope::StatsRead),
+            _ => Err(format!("unknown scope {}", s)),
+        }
+    }
+}
+
+/// Personal access token as shown to its owner, the secret is only ever returned on creation
+#[derive(Serialize, Debug, Clone)]
+pub struct PersonalAccessToken {
+    #[serde(rename = "tokenId")]
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateAccessToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CreatedAccessToken {
+    #[serde(flatten)]
+    pub access_token: PersonalAccessToken,
+    /// `kairos_pat_...`, can't be retrieved again
+    pub token: String,
+}
+
+/// Stored token used to authenticate a request
+pub struct StoredAccessToken {
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,69 @@
+use serde::Deserialize;
+
+use crate::models::User;
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    pub name: String,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordLoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailTokenRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// What a token sent by email can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify_email",
+            EmailTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+}
+
+/// User with the password login details
+pub struct PasswordCredentials {
+    pub user: User,
+    pub password_hash: Option<String>,
+    pub email_verified: bool,
+}
+
+/// Either the six digit code together with the email, or the token from the link
+#[derive(Debug, Deserialize)]
+pub struct LoginCodeRequest {
+    pub email: Option<String>,
+    pub code: Option<String>,
+    pub token: Option<String>,
+}
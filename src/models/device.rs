@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Client details captured at login and on every token refresh
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    pub app_version: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Fill the details the client didn't send this time from the previous token of the login
+    pub fn or(self, previous: DeviceInfo) -> Self {
+        Self {
+            device_name: self.device_name.or(previous.device_name),
+            platform: self.platform.or(previous.platform),
+            app_version: self.app_version.or(previous.app_version),
+            ip_address: self.ip_address.or(previous.ip_address),
+        }
+    }
+}
+
+/// A signed in device, one per active refresh token family
+#[derive(serde::Serialize, Debug)]
+pub struct Device {
+    #[serde(rename = "deviceId")]
+    pub device_id: Uuid,
+    #[serde(rename = "deviceName")]
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    #[serde(rename = "appVersion")]
+    pub app_version: Option<String>,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    #[serde(rename = "signedInAt")]
+    pub signed_in_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: DateTime<Utc>,
+    /// Whether this is the device making the request
+    pub current: bool,
+}
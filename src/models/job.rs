@@ -0,0 +1,31 @@
+use std::fmt::Display;
+
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        };
+        f.write_str(status)
+    }
+}
+
+/// Focus time of a user on one project, a line of the weekly digest
+#[derive(Debug, Clone)]
+pub struct ProjectFocus {
+    pub user_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub project_name: String,
+    pub seconds: i64,
+}
@@ -1,9 +1,21 @@
+pub mod access_token;
+pub mod account;
+pub mod device;
+pub mod job;
+pub mod passkey;
 pub mod project;
 pub mod session;
 pub mod token;
+pub mod two_factor;
 pub mod user;
 
+pub use access_token::*;
+pub use account::*;
+pub use device::*;
+pub use job::*;
+pub use passkey::*;
 pub use project::*;
 pub use session::*;
 pub use token::*;
+pub use two_factor::*;
 pub use user::*;
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Passkey {
+    #[serde(rename = "passkeyId")]
+    pub passkey_id: Uuid,
+    pub name: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+pub struct StoredPasskey {
+    pub passkey_id: Uuid,
+    pub user_id: Uuid,
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+}
+
+/// What a WebAuthn challenge was issued for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengePurpose {
+    Register,
+    Login,
+}
+
+impl ChallengePurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChallengePurpose::Register => "register",
+            ChallengePurpose::Login => "login",
+        }
+    }
+}
+
+pub struct WebauthnChallenge {
+    pub user_id: Option<Uuid>,
+    pub challenge: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartPasskeyLogin {
+    /// Without an email the authenticator offers its discoverable credentials
+    pub email: Option<String>,
+}
+
+/// Registration response, `PublicKeyCredential.toJSON()` of `navigator.credentials.create()`
+#[derive(Debug, Deserialize)]
+pub struct FinishPasskeyRegistration {
+    pub challenge_id: Uuid,
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// Login response, `PublicKeyCredential.toJSON()` of `navigator.credentials.get()`
+#[derive(Debug, Deserialize)]
+pub struct FinishPasskeyLogin {
+    pub challenge_id: Uuid,
+    pub credential: AssertionCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
@@ -1,15 +1,40 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::DeviceInfo;
 
 #[derive(Serialize)]
 pub struct TokenResponse {
     pub access_token: String,
     pub refresh_token: String,
     pub expiry: DateTime<Utc>,
+    #[serde(skip)]
+    pub token_id: Uuid,
+    #[serde(skip)]
+    pub family_id: Uuid,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Id of the refresh token row this token pair belongs to
+    pub jti: String,
+    /// Every token rotated out of the same login shares the family id
+    pub fam: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Stored refresh token, `refresh_token` is the bcrypt hash of the issued token
+pub struct RefreshToken {
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub refresh_token: String,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub device: DeviceInfo,
 }
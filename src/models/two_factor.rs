@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Secret of a TOTP enrolment, the otpauth uri is what the QR code shows
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+/// Second login step, either a TOTP code or a recovery code
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+/// Disabling proves both factors again, the password only if the account has one
+#[derive(Debug, Deserialize)]
+pub struct DisableTotpRequest {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Login response when the account has 2FA, the challenge token is exchanged for the real
+/// tokens together with a code
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+}
+
+/// Claims of the challenge token, the audience keeps it from passing as any other token
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: String,
+    pub exp: usize,
+    pub aud: String,
+    /// Guest that moves into the account once the second step passes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest: Option<String>,
+}
+
+pub struct StoredTotp {
+    pub secret: String,
+    pub enabled: bool,
+    pub locked_until: Option<DateTime<Utc>>,
+}
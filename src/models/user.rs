@@ -1,5 +1,6 @@
-use std::fmt::Display;
+use std::{convert::Infallible, fmt::Display, str::FromStr};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -13,6 +14,9 @@ pub struct User {
     pub oauth_provider: Option<OauthProvider>,
     pub picture: Option<String>,
     pub u_type: UserPlan,
+    /// Anonymous account, upgraded by signing in with a provider
+    #[serde(default)]
+    pub guest: bool,
 }
 
 impl User {
@@ -31,8 +35,32 @@ impl User {
             oauth_provider,
             picture,
             u_type,
+            guest: false,
         }
     }
+
+    /// Anonymous user, the email is a placeholder no one can receive mail at
+    pub fn guest(user_id: Uuid) -> Self {
+        Self {
+            guest: true,
+            ..Self::new(
+                user_id,
+                "Guest".to_string(),
+                format!("guest-{}@guest.invalid", user_id),
+                None,
+                None,
+                UserPlan::free,
+            )
+        }
+    }
+}
+
+/// Account settings the user changes themselves
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct UserSettings {
+    /// Weekly email with the focus time per project, off until the user turns it on
+    #[serde(rename = "weeklyDigest")]
+    pub weekly_digest: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -40,7 +68,38 @@ pub struct OauthUser {
     pub sub: String,
     pub name: String,
     pub email: String,
-    pub picture: String,
+    pub picture: Option<String>,
+}
+
+/// Credential issued by the provider, verified server-side before logging in
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    /// ID token (google)
+    pub id_token: Option<String>,
+    /// Authorization code, exchanged by the server (github, oidc)
+    pub code: Option<String>,
+    /// Redirect uri the code was requested with, if the app sent one
+    pub redirect_uri: Option<String>,
+    /// PKCE verifier, if the app started the flow with a code challenge (oidc)
+    pub code_verifier: Option<String>,
+}
+
+/// Provider account linked to a user
+#[derive(Debug, Serialize, Clone)]
+pub struct Identity {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeQuery {
+    pub redirect_uri: String,
+    pub state: Option<String>,
+    /// PKCE challenge, the base64url SHA-256 (S256) of the `code_verifier` sent with the code
+    pub code_challenge: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
@@ -49,11 +108,30 @@ pub enum OauthProvider {
     google,
     #[allow(non_camel_case_types)]
     github,
+    /// OpenID Connect provider, by its configured name
+    #[allow(non_camel_case_types)]
+    #[serde(untagged)]
+    oidc(String),
 }
 
 impl Display for OauthProvider {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            OauthProvider::oidc(name) => write!(f, "{}", name),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl FromStr for OauthProvider {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "google" => Ok(OauthProvider::google),
+            "github" => Ok(OauthProvider::github),
+            _ => Ok(OauthProvider::oidc(s.to_string())),
+        }
     }
 }
 
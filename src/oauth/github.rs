@@ -0,0 +1,146 @@
+use serde::Deserialize;
+
+use super::{http_client, OauthError};
+use crate::models::OauthUser;
+
+pub const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Logs users in through GitHub's OAuth web flow
+/// https://docs.github.com/en/apps/oauth-apps/building-oauth-apps/authorizing-oauth-apps
+pub struct GithubClient {
+    client_id: String,
+    client_secret: String,
+    token_url: String,
+    api_url: String,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+impl GithubClient {
+    /// `token_url` and `api_url` are only overridden to point the tests at a mock server
+    pub fn new(
+        client_id: String,
+        client_secret: String,
+        token_url: String,
+        api_url: String,
+    ) -> Self {
+        Self {
+            client_id,
+            client_secret,
+            token_url,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            http: http_client(),
+        }
+    }
+
+    /// Exchange the authorization code the app received and return the GitHub user it belongs to
+    pub async fn verify(
+        &self,
+        code: &str,
+        redirect_uri: Option<&str>,
+    ) -> Result<OauthUser, OauthError> {
+        let access_token = self.exchange_code(code, redirect_uri).await?;
+        self.user_info(&access_token).await
+    }
+
+    async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: Option<&str>,
+    ) -> Result<String, OauthError> {
+        let mut form = vec![
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("code", code),
+        ];
+        if let Some(redirect_uri) = redirect_uri {
+            form.push(("redirect_uri", redirect_uri));
+        }
+
+        let response: TokenResponse = self
+            .http
+            .post(&self.token_url)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(OauthError::from)?
+            .json()
+            .await
+            .map_err(OauthError::from)?;
+
+        // GitHub answers 200 with an `error` field for bad or expired codes
+        match (response.access_token, response.error) {
+            (Some(token), None) => Ok(token),
+            (_, error) => Err(OauthError::InvalidToken(
+                error.unwrap_or_else(|| "missing access token".to_string()),
+            )),
+        }
+    }
+
+    async fn user_info(&self, access_token: &str) -> Result<OauthUser, OauthError> {
+        let user: GithubUser = self.get(access_token, "/user").await?;
+        // The profile email is optional and may be unverified, so ask for the primary one
+        let emails: Vec<GithubEmail> = self.get(access_token, "/user/emails").await?;
+        let email = emails
+            .into_iter()
+            .find(|email| email.primary && email.verified)
+            .ok_or_else(|| OauthError::InvalidToken("no verified primary email".to_string()))?;
+
+        Ok(OauthUser {
+            sub: user.id.to_string(),
+            name: user.name.unwrap_or(user.login),
+            email: email.email,
+            picture: user.avatar_url,
+        })
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+    ) -> Result<T, OauthError> {
+        let response = self
+            .http
+            .get(format!("{}{}", self.api_url, path))
+            .bearer_auth(access_token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header(reqwest::header::USER_AGENT, "kairos-server")
+            .send()
+            .await
+            .map_err(OauthError::from)?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(OauthError::InvalidToken(
+                "access token rejected".to_string(),
+            ));
+        }
+        response
+            .error_for_status()
+            .map_err(OauthError::from)?
+            .json()
+            .await
+            .map_err(OauthError::from)
+    }
+}
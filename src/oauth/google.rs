@@ -0,0 +1,74 @@
+use jsonwebtoken::{Algorithm, Validation};
+use serde::Deserialize;
+
+use super::{JwksCache, KeySource, OauthError};
+use crate::models::OauthUser;
+
+pub const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+const GOOGLE_ISSUERS: [&str; 2] = ["accounts.google.com", "https://accounts.google.com"];
+
+/// Verifies Google ID tokens locally against Google's published signing keys
+/// https://developers.google.com/identity/sign-in/android/backend-auth
+pub struct GoogleVerifier {
+    client_ids: Vec<String>,
+    keys: JwksCache,
+}
+
+#[derive(Deserialize)]
+struct GoogleClaims {
+    sub: String, // unique identifier of the google account
+    email: String,
+    #[serde(default)]
+    email_verified: bool,
+    name: String,
+    picture: Option<String>,
+}
+
+impl GoogleVerifier {
+    /// `client_ids` are the OAuth client ids of our apps, tokens issued to any other app are rejected
+    pub fn new(client_ids: Vec<String>, source: KeySource) -> Self {
+        Self {
+            client_ids,
+            keys: JwksCache::new(source),
+        }
+    }
+
+    /// Check signature, issuer, audience and expiry of the ID token and return the user it was
+    /// issued for
+    pub async fn verify(&self, id_token: &str) -> Result<OauthUser, OauthError> {
+        if self.client_ids.is_empty() {
+            return Err(OauthError::Provider(
+                "no google client ids configured".to_string(),
+            ));
+        }
+
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|e| OauthError::InvalidToken(e.to_string()))?;
+        if header.alg != Algorithm::RS256 {
+            return Err(OauthError::InvalidToken("unexpected algorithm".to_string()));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| OauthError::InvalidToken("missing key id".to_string()))?;
+        let key = self.keys.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&self.client_ids);
+        validation.set_issuer(&GOOGLE_ISSUERS);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken::decode::<GoogleClaims>(id_token, &key, &validation)
+            .map_err(|e| OauthError::InvalidToken(e.to_string()))?
+            .claims;
+        if !claims.email_verified {
+            return Err(OauthError::InvalidToken("email not verified".to_string()));
+        }
+
+        Ok(OauthUser {
+            sub: claims.sub,
+            name: claims.name,
+            email: claims.email,
+            picture: claims.picture,
+        })
+    }
+}
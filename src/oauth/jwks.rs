@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::{jwk::JwkSet, DecodingKey};
+use tokio::sync::RwLock;
+
+use super::{http_client, OauthError};
+
+/// How long a fetched key set is trusted before fetching it again
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+/// Minimum time between fetches triggered by an unknown `kid`
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(60);
+
+/// Where the signing keys of a provider come from
+pub enum KeySource {
+    /// Fetched from the provider's JWKS endpoint and cached
+    Remote(String),
+    /// Fixed key set, used by the tests so no network is needed
+    Static(JwkSet),
+}
+
+pub struct JwksCache {
+    source: KeySource,
+    http: reqwest::Client,
+    cached: RwLock<Option<(JwkSet, Instant)>>,
+}
+
+impl JwksCache {
+    pub fn new(source: KeySource) -> Self {
+        Self {
+            source,
+            http: http_client(),
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Key used to sign a token with the given `kid`
+    /// Unknown ids trigger a refetch since providers rotate their keys regularly
+    pub async fn decoding_key(&self, kid: &str) -> Result<DecodingKey, OauthError> {
+        let url = match &self.source {
+            KeySource::Static(set) => return find_key(set, kid),
+            KeySource::Remote(url) => url,
+        };
+
+        if let Some((set, fetched_at)) = &*self.cached.read().await {
+            let fresh = fetched_at.elapsed() < JWKS_TTL;
+            if fresh && set.find(kid).is_some() {
+                return find_key(set, kid);
+            }
+            if fresh && fetched_at.elapsed() < JWKS_MIN_REFETCH {
+                return Err(OauthError::InvalidToken(format!("unknown key id {}", kid)));
+            }
+        }
+
+        let set = fetch_jwks(&self.http, url).await?;
+        let key = find_key(&set, kid);
+        *self.cached.write().await = Some((set, Instant::now()));
+        key
+    }
+}
+
+fn find_key(set: &JwkSet, kid: &str) -> Result<DecodingKey, OauthError> {
+    let jwk = set
+        .find(kid)
+        .ok_or_else(|| OauthError::InvalidToken(format!("unknown key id {}", kid)))?;
+    DecodingKey::from_jwk(jwk).map_err(|e| OauthError::Provider(e.to_string()))
+}
+
+async fn fetch_jwks(http: &reqwest::Client, url: &str) -> Result<JwkSet, OauthError> {
+    let response = http
+        .get(url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())?;
+    Ok(response.json::<JwkSet>().await?)
+}
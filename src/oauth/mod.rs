@@ -0,0 +1,65 @@
+pub mod github;
+pub mod google;
+pub mod jwks;
+pub mod oidc;
+
+pub use github::*;
+pub use google::*;
+pub use jwks::*;
+pub use oidc::*;
+
+use std::{collections::HashMap, fmt::Display, time::Duration};
+
+/// Longest a call to a provider may take before it is given up
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest connecting to a provider may take
+const PROVIDER_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Identity providers the server can verify logins against
+pub struct Providers {
+    pub google: GoogleVerifier,
+    pub github: GithubClient,
+    /// OpenID Connect providers by the name used in `/login/{provider}`
+    pub oidc: HashMap<String, OidcProvider>,
+}
+
+#[derive(Debug)]
+pub enum OauthError {
+    /// The credential sent by the client is not acceptable
+    InvalidToken(String),
+    /// The provider could not be reached or answered with garbage
+    Provider(String),
+    /// The provider did not answer in time
+    Unavailable(String),
+}
+
+impl Display for OauthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OauthError::InvalidToken(reason) => write!(f, "invalid token: {}", reason),
+            OauthError::Provider(reason) => write!(f, "provider error: {}", reason),
+            OauthError::Unavailable(reason) => write!(f, "provider unavailable: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OauthError {}
+
+impl From<reqwest::Error> for OauthError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            OauthError::Unavailable(e.to_string())
+        } else {
+            OauthError::Provider(e.to_string())
+        }
+    }
+}
+
+/// Client for calls to providers, so a hanging provider can't hold a worker forever
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(PROVIDER_TIMEOUT)
+        .connect_timeout(PROVIDER_CONNECT_TIMEOUT)
+        .build()
+        .expect("Failed to build the provider HTTP client")
+}
@@ -0,0 +1,228 @@
+use jsonwebtoken::{Algorithm, Validation};
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+
+use super::{http_client, JwksCache, KeySource, OauthError};
+use crate::models::OauthUser;
+
+/// Signing algorithms accepted for ID tokens, symmetric ones would need the client secret as key
+const ID_TOKEN_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Any OpenID Connect provider (Keycloak, Authentik, ...), configured by its issuer url
+/// Endpoints come from the discovery document, fetched on first use
+/// `issuer` has to be exactly what the provider puts in `iss`, trailing slash included
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    discovery: OnceCell<Discovery>,
+    http: reqwest::Client,
+}
+
+/// https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    keys: JwksCache,
+}
+
+#[derive(Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OidcClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+    preferred_username: Option<String>,
+    picture: Option<String>,
+}
+
+impl OidcProvider {
+    pub fn new(
+        issuer: String,
+        client_id: String,
+        client_secret: String,
+        scopes: Vec<String>,
+    ) -> Self {
+        Self {
+            issuer,
+            client_id,
+            client_secret,
+            scopes,
+            discovery: OnceCell::new(),
+            http: http_client(),
+        }
+    }
+
+    /// Url the app sends the user to, the provider redirects back with the code for `verify`
+    /// `code_challenge` is an S256 PKCE challenge, its verifier then goes along with the code
+    pub async fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        state: Option<&str>,
+        code_challenge: Option<&str>,
+    ) -> Result<String, OauthError> {
+        let discovery = self.discovery().await?;
+        let scope = self.scopes.join(" ");
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", scope.as_str()),
+        ];
+        if let Some(state) = state {
+            params.push(("state", state));
+        }
+        if let Some(code_challenge) = code_challenge {
+            params.push(("code_challenge", code_challenge));
+            params.push(("code_challenge_method", "S256"));
+        }
+        reqwest::Url::parse_with_params(&discovery.authorization_endpoint, &params)
+            .map(String::from)
+            .map_err(|e| OauthError::Provider(e.to_string()))
+    }
+
+    /// Exchange the authorization code for an ID token and return the user it was issued for
+    pub async fn verify(
+        &self,
+        code: &str,
+        redirect_uri: Option<&str>,
+        code_verifier: Option<&str>,
+    ) -> Result<OauthUser, OauthError> {
+        let discovery = self.discovery().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        if let Some(redirect_uri) = redirect_uri {
+            form.push(("redirect_uri", redirect_uri));
+        }
+        if let Some(code_verifier) = code_verifier {
+            form.push(("code_verifier", code_verifier));
+        }
+
+        let response = self
+            .http
+            .post(&discovery.token_endpoint)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await
+            .map_err(OauthError::from)?;
+        // invalid_grant and friends come back as 400
+        if response.status().is_client_error() {
+            return Err(OauthError::InvalidToken(format!(
+                "code rejected with {}",
+                response.status()
+            )));
+        }
+        let token: TokenResponse = response
+            .error_for_status()
+            .map_err(OauthError::from)?
+            .json()
+            .await
+            .map_err(OauthError::from)?;
+        let id_token = token.id_token.ok_or_else(|| {
+            OauthError::Provider("missing id_token, is openid in the scopes?".to_string())
+        })?;
+
+        self.verify_id_token(discovery, &id_token).await
+    }
+
+    async fn verify_id_token(
+        &self,
+        discovery: &Discovery,
+        id_token: &str,
+    ) -> Result<OauthUser, OauthError> {
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|e| OauthError::InvalidToken(e.to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(OauthError::InvalidToken("unexpected algorithm".to_string()));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| OauthError::InvalidToken("missing key id".to_string()))?;
+        let key = discovery.keys.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken::decode::<OidcClaims>(id_token, &key, &validation)
+            .map_err(|e| OauthError::InvalidToken(e.to_string()))?
+            .claims;
+        let email = match claims.email {
+            Some(email) if claims.email_verified => email,
+            _ => return Err(OauthError::InvalidToken("email not verified".to_string())),
+        };
+
+        Ok(OauthUser {
+            sub: claims.sub,
+            name: claims
+                .name
+                .or(claims.preferred_username)
+                .unwrap_or_else(|| email.clone()),
+            email,
+            picture: claims.picture,
+        })
+    }
+
+    async fn discovery(&self) -> Result<&Discovery, OauthError> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.issuer.trim_end_matches('/')
+                );
+                let document: DiscoveryDocument = self
+                    .http
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(OauthError::from)?
+                    .json()
+                    .await
+                    .map_err(OauthError::from)?;
+                // Has to match exactly, protects against a document served for another issuer
+                if document.issuer != self.issuer {
+                    return Err(OauthError::Provider(format!(
+                        "discovery issuer {} does not match {}",
+                        document.issuer, self.issuer
+                    )));
+                }
+                Ok(Discovery {
+                    authorization_endpoint: document.authorization_endpoint,
+                    token_endpoint: document.token_endpoint,
+                    keys: JwksCache::new(KeySource::Remote(document.jwks_uri)),
+                })
+            })
+            .await
+    }
+}
@@ -0,0 +1,1090 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        ChallengePurpose, Device, DeviceInfo, EmailTokenPurpose, Identity, OauthProvider,
+        OauthUser, Passkey, PasswordCredentials, PersonalAccessToken, Project, RefreshToken,
+        Session, StoredAccessToken, StoredPasskey, StoredTotp, User, UserSettings,
+        WebauthnChallenge,
+    },
+    repo::{
+        AccessTokenRepo, GuestRepo, IdentityRepo, LoginCodeRepo, PasskeyRepo, PasswordRepo,
+        ProjectRepo, RepoError, SessionRepo, TokenRepo, TwoFactorRepo, UserRepo,
+    },
+    webauthn::RegisteredCredential,
+};
+
+/// The repositories in a map per table, keeping the keys and ownership rules of the database
+#[derive(Default)]
+pub struct MemoryRepo {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    users: HashMap<Uuid, User>,
+    settings: HashMap<Uuid, UserSettings>,
+    projects: HashMap<Uuid, Project>,
+    sessions: HashMap<Uuid, Session>,
+    refresh_tokens: HashMap<Uuid, StoredToken>,
+    revoked_families: HashSet<Uuid>,
+    /// Oldest first, like the database orders them
+    identities: Vec<(Uuid, Identity)>,
+    /// Users and the providers they unlinked an identity of
+    identity_unlinks: HashSet<(Uuid, String)>,
+    password_hashes: HashMap<Uuid, String>,
+    verified_emails: HashSet<Uuid>,
+    email_tokens: HashMap<String, StoredEmailToken>,
+    /// By email
+    login_codes: HashMap<String, StoredLoginCode>,
+    /// When login codes were sent, by email
+    login_code_requests: HashMap<String, Vec<DateTime<Utc>>>,
+    password_email_requests: HashMap<String, Vec<DateTime<Utc>>>,
+    totp: HashMap<Uuid, MemoryTotp>,
+    recovery_codes: HashMap<Uuid, HashSet<String>>,
+    webauthn_challenges: HashMap<Uuid, StoredChallenge>,
+    passkeys: HashMap<Uuid, MemoryPasskey>,
+    access_tokens: HashMap<Uuid, MemoryAccessToken>,
+}
+
+struct StoredToken {
+    user_id: Uuid,
+    family_id: Uuid,
+    refresh_token: String,
+    expires_at: DateTime<Utc>,
+    rotated_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    device: DeviceInfo,
+}
+
+struct StoredEmailToken {
+    user_id: Uuid,
+    purpose: EmailTokenPurpose,
+    expires_at: DateTime<Utc>,
+}
+
+struct StoredLoginCode {
+    code_hash: String,
+    link_hash: String,
+    expires_at: DateTime<Utc>,
+    attempts: i32,
+}
+
+struct MemoryTotp {
+    secret: String,
+    enabled: bool,
+    last_used_step: Option<i64>,
+    failed_attempts: i32,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+struct StoredChallenge {
+    user_id: Option<Uuid>,
+    challenge: String,
+    purpose: ChallengePurpose,
+    expires_at: DateTime<Utc>,
+}
+
+struct MemoryPasskey {
+    user_id: Uuid,
+    credential_id: String,
+    public_key: Vec<u8>,
+    algorithm: i64,
+    sign_count: u32,
+    passkey: Passkey,
+}
+
+struct MemoryAccessToken {
+    user_id: Uuid,
+    token_hash: String,
+    token: PersonalAccessToken,
+}
+
+impl MemoryRepo {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl UserRepo for MemoryRepo {
+    async fn create_user(&self, user: User) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.users.contains_key(&user.user_id)
+            || state.users.values().any(|u| u.email == user.email)
+        {
+            return Err(RepoError::Conflict);
+        }
+        state.users.insert(user.user_id, user);
+        Ok(())
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<User, RepoError> {
+        self.state()
+            .users
+            .get(&user_id)
+            .cloned()
+            .ok_or(RepoError::NotFound)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<User, RepoError> {
+        self.state()
+            .users
+            .values()
+            .find(|user| user.email == email)
+            .cloned()
+            .ok_or(RepoError::NotFound)
+    }
+
+    async fn get_settings(&self, user_id: Uuid) -> Result<UserSettings, RepoError> {
+        let state = self.state();
+        if !state.users.contains_key(&user_id) {
+            return Err(RepoError::NotFound);
+        }
+        Ok(state.settings.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    async fn update_settings(
+        &self,
+        user_id: Uuid,
+        settings: UserSettings,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if !state.users.contains_key(&user_id) {
+            return Err(RepoError::NotFound);
+        }
+        state.settings.insert(user_id, settings);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProjectRepo for MemoryRepo {
+    async fn add_project(&self, project: Project) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.projects.contains_key(&project.project_id) {
+            return Err(RepoError::Conflict);
+        }
+        state.projects.insert(project.project_id, project);
+        Ok(())
+    }
+
+    async fn update_project(&self, project: Project) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.projects.get_mut(&project.project_id) {
+            Some(stored) if stored.user_id == project.user_id => {
+                *stored = project;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_project(&self, user_id: Uuid, project_id: Uuid) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.projects.get(&project_id) {
+            Some(project) if project.user_id == user_id => {
+                state.projects.remove(&project_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get_projects(&self, user_id: Uuid) -> Result<Vec<Project>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .projects
+            .values()
+            .filter(|project| project.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl SessionRepo for MemoryRepo {
+    async fn add_session(&self, session: Session) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        if state.sessions.contains_key(&session.session_id) {
+            return Err(RepoError::Conflict);
+        }
+        match state.projects.get(&session.project_id) {
+            Some(project) if project.user_id == session.user_id => {
+                state.sessions.insert(session.session_id, session);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn update_session(&self, session: Session) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.sessions.get_mut(&session.session_id) {
+            Some(stored) if stored.user_id == session.user_id => {
+                stored.ended_at = session.ended_at;
+                stored.duration = session.duration;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get_active_session(&self, user_id: Uuid) -> Result<Option<Session>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .sessions
+            .values()
+            .find(|session| session.user_id == user_id && session.ended_at.is_none())
+            .cloned())
+    }
+
+    async fn get_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .sessions
+            .values()
+            .filter(|session| session.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_focus_time_on(&self, user_id: Uuid, day: NaiveDate) -> Result<i64, RepoError> {
+        let state = self.state();
+        Ok(state
+            .sessions
+            .values()
+            .filter(|session| session.user_id == user_id && session.started_at.date_naive() == day)
+            .map(|session| i64::from(session.duration))
+            .sum())
+    }
+}
+
+#[async_trait]
+impl TokenRepo for MemoryRepo {
+    async fn store_refresh_token(
+        &self,
+        user_id: Uuid,
+        token_id: Uuid,
+        family_id: Uuid,
+        refresh_token: String,
+        expires_at: DateTime<Utc>,
+        device: DeviceInfo,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.refresh_tokens.contains_key(&token_id) {
+            return Err(RepoError::Conflict);
+        }
+        let token = StoredToken {
+            user_id,
+            family_id,
+            refresh_token,
+            expires_at,
+            rotated_at: None,
+            created_at: Utc::now(),
+            device,
+        };
+        state.refresh_tokens.insert(token_id, token);
+        Ok(())
+    }
+
+    async fn get_refresh_token(&self, token_id: Uuid) -> Result<Option<RefreshToken>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .refresh_tokens
+            .get(&token_id)
+            .map(|token| RefreshToken {
+                user_id: token.user_id,
+                family_id: token.family_id,
+                refresh_token: token.refresh_token.clone(),
+                rotated_at: token.rotated_at,
+                device: token.device.clone(),
+            }))
+    }
+
+    async fn rotate_refresh_token(&self, token_id: Uuid) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.refresh_tokens.get_mut(&token_id) {
+            Some(token) if token.rotated_at.is_none() => {
+                token.rotated_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Revocations are kept for good, there are no access tokens that outlive a test
+    async fn revoke_token_family(
+        &self,
+        family_id: Uuid,
+        _access_valid_until: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        state.revoked_families.insert(family_id);
+        state
+            .refresh_tokens
+            .retain(|_, token| token.family_id != family_id);
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+        keep_family: Option<Uuid>,
+        _access_valid_until: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        let revoked =
+            |token: &StoredToken| token.user_id == user_id && Some(token.family_id) != keep_family;
+        let families: Vec<Uuid> = state
+            .refresh_tokens
+            .values()
+            .filter(|token| revoked(token))
+            .map(|token| token.family_id)
+            .collect();
+        state.revoked_families.extend(families);
+        state.refresh_tokens.retain(|_, token| !revoked(token));
+        Ok(())
+    }
+
+    async fn is_token_family_revoked(&self, family_id: Uuid) -> Result<bool, RepoError> {
+        Ok(self.state().revoked_families.contains(&family_id))
+    }
+
+    async fn owns_token_family(&self, user_id: Uuid, family_id: Uuid) -> Result<bool, RepoError> {
+        let state = self.state();
+        Ok(state
+            .refresh_tokens
+            .values()
+            .any(|token| token.family_id == family_id && token.user_id == user_id))
+    }
+
+    async fn get_devices(
+        &self,
+        user_id: Uuid,
+        current_family: Option<Uuid>,
+    ) -> Result<Vec<Device>, RepoError> {
+        let state = self.state();
+        let now = Utc::now();
+        let signed_in_at = |family_id: Uuid| {
+            state
+                .refresh_tokens
+                .values()
+                .filter(|token| token.family_id == family_id)
+                .map(|token| token.created_at)
+                .min()
+        };
+        let mut devices: Vec<Device> = state
+            .refresh_tokens
+            .values()
+            .filter(|token| {
+                token.user_id == user_id && token.rotated_at.is_none() && token.expires_at > now
+            })
+            .map(|token| Device {
+                device_id: token.family_id,
+                device_name: token.device.device_name.clone(),
+                platform: token.device.platform.clone(),
+                app_version: token.device.app_version.clone(),
+                ip_address: token.device.ip_address.clone(),
+                signed_in_at: signed_in_at(token.family_id),
+                // Tokens are replaced on every use, so the live one was last used when created
+                last_used_at: token.created_at,
+                current: Some(token.family_id) == current_family,
+            })
+            .collect();
+        devices.sort_by_key(|device| Reverse(device.last_used_at));
+        Ok(devices)
+    }
+}
+
+#[async_trait]
+impl IdentityRepo for MemoryRepo {
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .identities
+            .iter()
+            .find(|(_, identity)| identity.provider == provider && identity.subject == subject)
+            .and_then(|(user_id, _)| state.users.get(user_id))
+            .cloned())
+    }
+
+    async fn create_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        o_user: &OauthUser,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if !state.users.contains_key(&user_id) {
+            return Err(RepoError::NotFound);
+        }
+        if state
+            .identities
+            .iter()
+            .any(|(_, identity)| identity.provider == provider && identity.subject == o_user.sub)
+        {
+            return Err(RepoError::Conflict);
+        }
+        let identity = Identity {
+            provider: provider.to_string(),
+            subject: o_user.sub.clone(),
+            email: o_user.email.clone(),
+            created_at: Utc::now(),
+        };
+        state.identities.push((user_id, identity));
+        Ok(())
+    }
+
+    async fn get_identities(&self, user_id: Uuid) -> Result<Vec<Identity>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .identities
+            .iter()
+            .filter(|(owner, _)| *owner == user_id)
+            .map(|(_, identity)| identity.clone())
+            .collect())
+    }
+
+    async fn delete_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+    ) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        let before = state.identities.len();
+        state.identities.retain(|(owner, identity)| {
+            !(*owner == user_id && identity.provider == provider && identity.subject == subject)
+        });
+        if state.identities.len() == before {
+            return Ok(false);
+        }
+        state
+            .identity_unlinks
+            .insert((user_id, provider.to_string()));
+        Ok(true)
+    }
+
+    async fn had_identity(&self, user_id: Uuid, provider: &str) -> Result<bool, RepoError> {
+        let state = self.state();
+        Ok(state
+            .identities
+            .iter()
+            .any(|(owner, identity)| *owner == user_id && identity.provider == provider)
+            || state
+                .identity_unlinks
+                .contains(&(user_id, provider.to_string())))
+    }
+}
+
+#[async_trait]
+impl GuestRepo for MemoryRepo {
+    async fn upgrade_guest(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        o_user: &OauthUser,
+    ) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        if !state.users.get(&user_id).is_some_and(|user| user.guest) {
+            return Ok(false);
+        }
+        let email_taken = state
+            .users
+            .values()
+            .any(|user| user.email == o_user.email && user.user_id != user_id);
+        let linked = state
+            .identities
+            .iter()
+            .any(|(_, identity)| identity.provider == provider && identity.subject == o_user.sub);
+        if email_taken || linked {
+            return Err(RepoError::Conflict);
+        }
+
+        let user = state.users.get_mut(&user_id).unwrap();
+        user.guest = false;
+        user.name = o_user.name.clone();
+        user.email = o_user.email.clone();
+        user.picture = o_user.picture.clone();
+        let Ok(provider_kind) = provider.parse::<OauthProvider>();
+        user.oauth_provider = Some(provider_kind);
+        let identity = Identity {
+            provider: provider.to_string(),
+            subject: o_user.sub.clone(),
+            email: o_user.email.clone(),
+            created_at: Utc::now(),
+        };
+        state.identities.push((user_id, identity));
+        Ok(true)
+    }
+
+    async fn merge_guest(&self, guest_id: Uuid, user_id: Uuid) -> Result<(), RepoError> {
+        let mut state = self.state();
+        let default_project = |state: &State, owner: Uuid| {
+            state
+                .projects
+                .values()
+                .find(|project| project.user_id == owner && project.project_name == "Unset")
+                .map(|project| project.project_id)
+        };
+        let guest_default = default_project(&state, guest_id);
+        let target_default = default_project(&state, user_id);
+        if let (Some(guest_default), Some(target_default)) = (guest_default, target_default) {
+            for session in state.sessions.values_mut() {
+                if session.project_id == guest_default {
+                    session.project_id = target_default;
+                }
+            }
+            state.projects.remove(&guest_default);
+        }
+        for project in state.projects.values_mut() {
+            if project.user_id == guest_id {
+                project.user_id = user_id;
+            }
+        }
+        for session in state.sessions.values_mut() {
+            if session.user_id == guest_id {
+                session.user_id = user_id;
+            }
+        }
+        if state.users.get(&guest_id).is_some_and(|user| user.guest) {
+            // Everything else of the guest goes with it, like the foreign keys cascade
+            state.users.remove(&guest_id);
+            state.settings.remove(&guest_id);
+            state
+                .refresh_tokens
+                .retain(|_, token| token.user_id != guest_id);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PasswordRepo for MemoryRepo {
+    async fn create_password_user(
+        &self,
+        user: User,
+        password_hash: String,
+    ) -> Result<(), RepoError> {
+        let user_id = user.user_id;
+        self.create_user(user).await?;
+        self.state().password_hashes.insert(user_id, password_hash);
+        Ok(())
+    }
+
+    async fn get_password_credentials(
+        &self,
+        email: &str,
+    ) -> Result<Option<PasswordCredentials>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .users
+            .values()
+            .find(|user| user.email == email)
+            .map(|user| PasswordCredentials {
+                user: user.clone(),
+                password_hash: state.password_hashes.get(&user.user_id).cloned(),
+                email_verified: state.verified_emails.contains(&user.user_id),
+            }))
+    }
+
+    async fn get_password_hash(&self, user_id: Uuid) -> Result<Option<String>, RepoError> {
+        Ok(self.state().password_hashes.get(&user_id).cloned())
+    }
+
+    async fn set_password(&self, user_id: Uuid, password_hash: String) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.users.contains_key(&user_id) {
+            state.password_hashes.insert(user_id, password_hash);
+        }
+        Ok(())
+    }
+
+    async fn mark_email_verified(&self, user_id: Uuid) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.users.contains_key(&user_id) {
+            state.verified_emails.insert(user_id);
+        }
+        Ok(())
+    }
+
+    async fn drop_unverified_password(&self, user_id: Uuid) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if !state.verified_emails.contains(&user_id) {
+            state.password_hashes.remove(&user_id);
+        }
+        Ok(())
+    }
+
+    async fn store_email_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        purpose: EmailTokenPurpose,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.email_tokens.contains_key(&token_hash) {
+            return Err(RepoError::Conflict);
+        }
+        let token = StoredEmailToken {
+            user_id,
+            purpose,
+            expires_at,
+        };
+        state.email_tokens.insert(token_hash, token);
+        Ok(())
+    }
+
+    async fn consume_email_token(
+        &self,
+        token_hash: String,
+        purpose: EmailTokenPurpose,
+    ) -> Result<Option<Uuid>, RepoError> {
+        let mut state = self.state();
+        let user_id = match state.email_tokens.get(&token_hash) {
+            Some(token) if token.purpose == purpose && token.expires_at > Utc::now() => {
+                token.user_id
+            }
+            _ => return Ok(None),
+        };
+        state
+            .email_tokens
+            .retain(|_, token| token.user_id != user_id || token.purpose != purpose);
+        Ok(Some(user_id))
+    }
+
+    async fn record_password_email_request(
+        &self,
+        email: &str,
+        per_hour: i64,
+    ) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        let now = Utc::now();
+        let requests = state
+            .password_email_requests
+            .entry(email.to_string())
+            .or_default();
+        requests.retain(|requested_at| *requested_at > now - Duration::hours(1));
+        if requests.len() as i64 >= per_hour {
+            return Ok(false);
+        }
+        requests.push(now);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl LoginCodeRepo for MemoryRepo {
+    async fn replace_login_code(
+        &self,
+        email: &str,
+        code_hash: String,
+        link_hash: String,
+        expires_at: DateTime<Utc>,
+        max_attempts: i32,
+        per_hour: i64,
+    ) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        let now = Utc::now();
+        let locked = state
+            .login_codes
+            .get(email)
+            .is_some_and(|code| code.expires_at > now && code.attempts >= max_attempts);
+        let requests = state
+            .login_code_requests
+            .entry(email.to_string())
+            .or_default();
+        requests.retain(|requested_at| *requested_at > now - Duration::hours(1));
+        if locked || requests.len() as i64 >= per_hour {
+            return Ok(false);
+        }
+
+        requests.push(now);
+        let code = StoredLoginCode {
+            code_hash,
+            link_hash,
+            expires_at,
+            attempts: 0,
+        };
+        state.login_codes.insert(email.to_string(), code);
+        Ok(true)
+    }
+
+    async fn use_login_code(
+        &self,
+        email: &str,
+        code_hash: &str,
+        max_attempts: i32,
+    ) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        let Some(code) = state
+            .login_codes
+            .get_mut(email)
+            .filter(|code| code.expires_at > Utc::now() && code.attempts < max_attempts)
+        else {
+            return Ok(false);
+        };
+
+        let matches = code.code_hash == code_hash;
+        if matches {
+            state.login_codes.remove(email);
+        } else {
+            code.attempts += 1;
+        }
+        Ok(matches)
+    }
+
+    async fn use_login_link(&self, link_hash: &str) -> Result<Option<String>, RepoError> {
+        let mut state = self.state();
+        let now = Utc::now();
+        let Some(email) = state
+            .login_codes
+            .iter()
+            .find(|(_, code)| code.link_hash == link_hash && code.expires_at > now)
+            .map(|(email, _)| email.clone())
+        else {
+            return Ok(None);
+        };
+        state.login_codes.remove(&email);
+        Ok(Some(email))
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepo for MemoryRepo {
+    async fn get_totp(&self, user_id: Uuid) -> Result<Option<StoredTotp>, RepoError> {
+        Ok(self.state().totp.get(&user_id).map(|totp| StoredTotp {
+            secret: totp.secret.clone(),
+            enabled: totp.enabled,
+            locked_until: totp.locked_until,
+        }))
+    }
+
+    async fn set_pending_totp(&self, user_id: Uuid, secret: String) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if !state.users.contains_key(&user_id) {
+            return Err(RepoError::NotFound);
+        }
+        match state.totp.get_mut(&user_id) {
+            Some(totp) if totp.enabled => {}
+            Some(totp) => {
+                totp.secret = secret;
+                totp.last_used_step = None;
+                totp.failed_attempts = 0;
+            }
+            None => {
+                let totp = MemoryTotp {
+                    secret,
+                    enabled: false,
+                    last_used_step: None,
+                    failed_attempts: 0,
+                    locked_until: None,
+                };
+                state.totp.insert(user_id, totp);
+            }
+        }
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if let Some(totp) = state.totp.get_mut(&user_id) {
+            totp.enabled = true;
+        }
+        state
+            .recovery_codes
+            .insert(user_id, recovery_code_hashes.into_iter().collect());
+        Ok(())
+    }
+
+    async fn disable_totp(&self, user_id: Uuid) -> Result<(), RepoError> {
+        let mut state = self.state();
+        state.totp.remove(&user_id);
+        state.recovery_codes.remove(&user_id);
+        Ok(())
+    }
+
+    async fn record_totp_use(&self, user_id: Uuid, step: i64) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.totp.get_mut(&user_id) {
+            Some(totp) if totp.last_used_step.is_none_or(|last| last < step) => {
+                totp.last_used_step = Some(step);
+                totp.failed_attempts = 0;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn record_totp_failure(
+        &self,
+        user_id: Uuid,
+        max_attempts: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if let Some(totp) = state.totp.get_mut(&user_id) {
+            if totp.failed_attempts + 1 >= max_attempts {
+                totp.failed_attempts = 0;
+                totp.locked_until = Some(lock_until);
+            } else {
+                totp.failed_attempts += 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: String) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        Ok(state
+            .recovery_codes
+            .get_mut(&user_id)
+            .is_some_and(|codes| codes.remove(&code_hash)))
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepoError> {
+        self.state()
+            .recovery_codes
+            .insert(user_id, recovery_code_hashes.into_iter().collect());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PasskeyRepo for MemoryRepo {
+    async fn store_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        user_id: Option<Uuid>,
+        challenge: &str,
+        purpose: ChallengePurpose,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.webauthn_challenges.contains_key(&challenge_id) {
+            return Err(RepoError::Conflict);
+        }
+        let challenge = StoredChallenge {
+            user_id,
+            challenge: challenge.to_string(),
+            purpose,
+            expires_at,
+        };
+        state.webauthn_challenges.insert(challenge_id, challenge);
+        Ok(())
+    }
+
+    async fn take_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        purpose: ChallengePurpose,
+    ) -> Result<Option<WebauthnChallenge>, RepoError> {
+        let mut state = self.state();
+        match state.webauthn_challenges.get(&challenge_id) {
+            Some(stored) if stored.purpose == purpose && stored.expires_at > Utc::now() => {}
+            _ => return Ok(None),
+        }
+        Ok(state
+            .webauthn_challenges
+            .remove(&challenge_id)
+            .map(|stored| WebauthnChallenge {
+                user_id: stored.user_id,
+                challenge: stored.challenge,
+            }))
+    }
+
+    async fn create_passkey(
+        &self,
+        user_id: Uuid,
+        passkey_id: Uuid,
+        credential_id: String,
+        credential: &RegisteredCredential,
+        name: &str,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.passkeys.contains_key(&passkey_id)
+            || state
+                .passkeys
+                .values()
+                .any(|passkey| passkey.credential_id == credential_id)
+        {
+            return Err(RepoError::Conflict);
+        }
+        let passkey = MemoryPasskey {
+            user_id,
+            credential_id,
+            public_key: credential.public_key.clone(),
+            algorithm: credential.algorithm,
+            sign_count: credential.sign_count,
+            passkey: Passkey {
+                passkey_id,
+                name: name.to_string(),
+                created_at: Utc::now(),
+                last_used_at: None,
+            },
+        };
+        state.passkeys.insert(passkey_id, passkey);
+        Ok(())
+    }
+
+    async fn get_passkeys(&self, user_id: Uuid) -> Result<Vec<Passkey>, RepoError> {
+        let state = self.state();
+        let mut passkeys: Vec<Passkey> = state
+            .passkeys
+            .values()
+            .filter(|passkey| passkey.user_id == user_id)
+            .map(|passkey| passkey.passkey.clone())
+            .collect();
+        passkeys.sort_by_key(|passkey| passkey.created_at);
+        Ok(passkeys)
+    }
+
+    async fn get_passkey_credential_ids(&self, user_id: Uuid) -> Result<Vec<String>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .passkeys
+            .values()
+            .filter(|passkey| passkey.user_id == user_id)
+            .map(|passkey| passkey.credential_id.clone())
+            .collect())
+    }
+
+    async fn get_passkey_by_credential_id(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<StoredPasskey>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .passkeys
+            .values()
+            .find(|passkey| passkey.credential_id == credential_id)
+            .map(|passkey| StoredPasskey {
+                passkey_id: passkey.passkey.passkey_id,
+                user_id: passkey.user_id,
+                public_key: passkey.public_key.clone(),
+                algorithm: passkey.algorithm,
+                sign_count: passkey.sign_count,
+            }))
+    }
+
+    async fn use_passkey(
+        &self,
+        passkey_id: Uuid,
+        old_sign_count: u32,
+        new_sign_count: u32,
+    ) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.passkeys.get_mut(&passkey_id) {
+            Some(passkey) if passkey.sign_count == old_sign_count => {
+                passkey.sign_count = new_sign_count;
+                passkey.passkey.last_used_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_passkey(&self, user_id: Uuid, passkey_id: Uuid) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.passkeys.get(&passkey_id) {
+            Some(passkey) if passkey.user_id == user_id => {
+                state.passkeys.remove(&passkey_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl AccessTokenRepo for MemoryRepo {
+    async fn create_access_token(
+        &self,
+        user_id: Uuid,
+        token: &PersonalAccessToken,
+        token_hash: String,
+    ) -> Result<(), RepoError> {
+        let mut state = self.state();
+        if state.access_tokens.contains_key(&token.token_id) {
+            return Err(RepoError::Conflict);
+        }
+        let stored = MemoryAccessToken {
+            user_id,
+            token_hash,
+            token: token.clone(),
+        };
+        state.access_tokens.insert(token.token_id, stored);
+        Ok(())
+    }
+
+    async fn get_access_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, RepoError> {
+        let state = self.state();
+        let mut tokens: Vec<PersonalAccessToken> = state
+            .access_tokens
+            .values()
+            .filter(|stored| stored.user_id == user_id)
+            .map(|stored| stored.token.clone())
+            .collect();
+        tokens.sort_by_key(|token| Reverse(token.created_at));
+        Ok(tokens)
+    }
+
+    async fn get_access_token(
+        &self,
+        token_id: Uuid,
+    ) -> Result<Option<StoredAccessToken>, RepoError> {
+        let state = self.state();
+        Ok(state
+            .access_tokens
+            .get(&token_id)
+            .map(|stored| StoredAccessToken {
+                user_id: stored.user_id,
+                token_hash: stored.token_hash.clone(),
+                scopes: stored.token.scopes.clone(),
+                expires_at: stored.token.expires_at,
+            }))
+    }
+
+    async fn record_access_token_use(&self, token_id: Uuid) -> Result<(), RepoError> {
+        if let Some(stored) = self.state().access_tokens.get_mut(&token_id) {
+            stored.token.last_used_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn delete_access_token(&self, user_id: Uuid, token_id: Uuid) -> Result<bool, RepoError> {
+        let mut state = self.state();
+        match state.access_tokens.get(&token_id) {
+            Some(stored) if stored.user_id == user_id => {
+                state.access_tokens.remove(&token_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::{fmt::Display, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::UNIQUE_VIOLATION,
+    models::{
+        ChallengePurpose, Device, DeviceInfo, EmailTokenPurpose, Identity, OauthUser, Passkey,
+        PasswordCredentials, PersonalAccessToken, Project, RefreshToken, Session,
+        StoredAccessToken, StoredPasskey, StoredTotp, User, UserSettings, WebauthnChallenge,
+    },
+    webauthn::RegisteredCredential,
+};
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::*;
+pub use postgres::*;
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// An id or other unique key (e.g. the email of a user) is already taken
+    Conflict,
+    NotFound,
+    Database(sqlx::Error),
+}
+
+impl Display for RepoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepoError::Conflict => f.write_str("already exists"),
+            RepoError::NotFound => f.write_str("not found"),
+            RepoError::Database(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
+
+impl From<sqlx::Error> for RepoError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => RepoError::NotFound,
+            sqlx::Error::Database(err) if err.code() == Some(UNIQUE_VIOLATION.into()) => {
+                RepoError::Conflict
+            }
+            e => RepoError::Database(e),
+        }
+    }
+}
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    /// Conflict if the id or the email is taken
+    async fn create_user(&self, user: User) -> Result<(), RepoError>;
+
+    async fn get_user(&self, user_id: Uuid) -> Result<User, RepoError>;
+
+    async fn get_user_by_email(&self, email: &str) -> Result<User, RepoError>;
+
+    async fn get_settings(&self, user_id: Uuid) -> Result<UserSettings, RepoError>;
+
+    /// NotFound if there is no such user
+    async fn update_settings(&self, user_id: Uuid, settings: UserSettings)
+        -> Result<(), RepoError>;
+}
+
+#[async_trait]
+pub trait ProjectRepo: Send + Sync {
+    async fn add_project(&self, project: Project) -> Result<(), RepoError>;
+
+    /// Only updates a project of `project.user_id`, false if there is none with that id
+    async fn update_project(&self, project: Project) -> Result<bool, RepoError>;
+
+    /// False if the user has no project with that id
+    async fn delete_project(&self, user_id: Uuid, project_id: Uuid) -> Result<bool, RepoError>;
+
+    async fn get_projects(&self, user_id: Uuid) -> Result<Vec<Project>, RepoError>;
+}
+
+#[async_trait]
+pub trait SessionRepo: Send + Sync {
+    /// False without adding it if the project isn't one of `session.user_id`'s
+    async fn add_session(&self, session: Session) -> Result<bool, RepoError>;
+
+    /// Stores the new end and duration of a session of `session.user_id`, false if there is none
+    /// with that id
+    async fn update_session(&self, session: Session) -> Result<bool, RepoError>;
+
+    /// A session of the user that hasn't ended yet
+    async fn get_active_session(&self, user_id: Uuid) -> Result<Option<Session>, RepoError>;
+
+    async fn get_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, RepoError>;
+
+    /// Seconds of the sessions the user started on `day` (UTC)
+    async fn get_focus_time_on(&self, user_id: Uuid, day: NaiveDate) -> Result<i64, RepoError>;
+}
+
+/// Refresh tokens and the revocation list of their families (logins)
+#[async_trait]
+pub trait TokenRepo: Send + Sync {
+    /// `refresh_token` is the bcrypt hash of the issued token
+    async fn store_refresh_token(
+        &self,
+        user_id: Uuid,
+        token_id: Uuid,
+        family_id: Uuid,
+        refresh_token: String,
+        expires_at: DateTime<Utc>,
+        device: DeviceInfo,
+    ) -> Result<(), RepoError>;
+
+    async fn get_refresh_token(&self, token_id: Uuid) -> Result<Option<RefreshToken>, RepoError>;
+
+    /// Marks the token as used, only true for the first caller
+    async fn rotate_refresh_token(&self, token_id: Uuid) -> Result<bool, RepoError>;
+
+    /// Deletes every refresh token of the family and keeps it on the revocation list until
+    /// `access_valid_until`, when the last access token issued from it expires
+    async fn revoke_token_family(
+        &self,
+        family_id: Uuid,
+        access_valid_until: DateTime<Utc>,
+    ) -> Result<(), RepoError>;
+
+    /// Revokes every family of the user but `keep_family`
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+        keep_family: Option<Uuid>,
+        access_valid_until: DateTime<Utc>,
+    ) -> Result<(), RepoError>;
+
+    async fn is_token_family_revoked(&self, family_id: Uuid) -> Result<bool, RepoError>;
+
+    async fn owns_token_family(&self, user_id: Uuid, family_id: Uuid) -> Result<bool, RepoError>;
+
+    /// Signed in devices of the user, the live token of every family, most recently used first
+    async fn get_devices(
+        &self,
+        user_id: Uuid,
+        current_family: Option<Uuid>,
+    ) -> Result<Vec<Device>, RepoError>;
+}
+
+/// Provider accounts linked to users
+#[async_trait]
+pub trait IdentityRepo: Send + Sync {
+    /// User the provider account is linked to
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, RepoError>;
+
+    /// Conflict if the provider account is linked already
+    async fn create_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        o_user: &OauthUser,
+    ) -> Result<(), RepoError>;
+
+    /// Oldest first
+    async fn get_identities(&self, user_id: Uuid) -> Result<Vec<Identity>, RepoError>;
+
+    /// Also remembers that the user unlinked one of the provider, false if there is no such
+    /// identity
+    async fn delete_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+    ) -> Result<bool, RepoError>;
+
+    /// Whether the user has, or had before unlinking it, an identity of the provider
+    async fn had_identity(&self, user_id: Uuid, provider: &str) -> Result<bool, RepoError>;
+}
+
+#[async_trait]
+pub trait GuestRepo: Send + Sync {
+    /// Turn the guest into a regular account of the provider user, false if it isn't a guest
+    /// Conflict if the email or the provider account belongs to another user
+    async fn upgrade_guest(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        o_user: &OauthUser,
+    ) -> Result<bool, RepoError>;
+
+    /// Move the guest's projects and sessions to an existing account and delete the guest
+    /// Sessions of the guest's default project go to the account's default project
+    async fn merge_guest(&self, guest_id: Uuid, user_id: Uuid) -> Result<(), RepoError>;
+}
+
+/// Passwords, email verification and the tokens sent by email
+#[async_trait]
+pub trait PasswordRepo: Send + Sync {
+    /// Conflict if the email is taken
+    async fn create_password_user(
+        &self,
+        user: User,
+        password_hash: String,
+    ) -> Result<(), RepoError>;
+
+    async fn get_password_credentials(
+        &self,
+        email: &str,
+    ) -> Result<Option<PasswordCredentials>, RepoError>;
+
+    async fn get_password_hash(&self, user_id: Uuid) -> Result<Option<String>, RepoError>;
+
+    async fn set_password(&self, user_id: Uuid, password_hash: String) -> Result<(), RepoError>;
+
+    /// Keeps the time of the first verification
+    async fn mark_email_verified(&self, user_id: Uuid) -> Result<(), RepoError>;
+
+    /// Drop the password of an account whose email was never verified
+    /// Whoever registered it didn't prove they own the email, the provider login just did
+    async fn drop_unverified_password(&self, user_id: Uuid) -> Result<(), RepoError>;
+
+    /// `token_hash` is the SHA-256 of the token sent
+    async fn store_email_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        purpose: EmailTokenPurpose,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepoError>;
+
+    /// Use up an unexpired email token, returns the user it was sent to
+    /// Every other token of the user for the same purpose goes with it
+    async fn consume_email_token(
+        &self,
+        token_hash: String,
+        purpose: EmailTokenPurpose,
+    ) -> Result<Option<Uuid>, RepoError>;
+
+    /// Count a request for a password reset or verification email to the address
+    /// False once `per_hour` were made in the last hour
+    async fn record_password_email_request(
+        &self,
+        email: &str,
+        per_hour: i64,
+    ) -> Result<bool, RepoError>;
+}
+
+/// Emailed login codes and links, by email since the account may not exist yet
+#[async_trait]
+pub trait LoginCodeRepo: Send + Sync {
+    /// Store a new login code for the email, any earlier one stops working
+    /// False when refused, the current code is locked after `max_attempts` misses or `per_hour`
+    /// codes were sent in the last hour
+    async fn replace_login_code(
+        &self,
+        email: &str,
+        code_hash: String,
+        link_hash: String,
+        expires_at: DateTime<Utc>,
+        max_attempts: i32,
+        per_hour: i64,
+    ) -> Result<bool, RepoError>;
+
+    /// Use up the login code of the email if it matches
+    /// Every miss counts, after `max_attempts` of them the code is locked until it expires
+    async fn use_login_code(
+        &self,
+        email: &str,
+        code_hash: &str,
+        max_attempts: i32,
+    ) -> Result<bool, RepoError>;
+
+    /// Use up the login code the link belongs to, returns its email
+    async fn use_login_link(&self, link_hash: &str) -> Result<Option<String>, RepoError>;
+}
+
+/// TOTP secrets, their lockout and the recovery codes
+#[async_trait]
+pub trait TwoFactorRepo: Send + Sync {
+    async fn get_totp(&self, user_id: Uuid) -> Result<Option<StoredTotp>, RepoError>;
+
+    /// Start (or restart) an enrolment, an enabled TOTP is left alone
+    async fn set_pending_totp(&self, user_id: Uuid, secret: String) -> Result<(), RepoError>;
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepoError>;
+
+    /// Deletes the secret and the recovery codes
+    async fn disable_totp(&self, user_id: Uuid) -> Result<(), RepoError>;
+
+    /// Accept a code of the given time step, false if that step or a later one was already used
+    async fn record_totp_use(&self, user_id: Uuid, step: i64) -> Result<bool, RepoError>;
+
+    /// Count a wrong code, after `max_attempts` in a row the second step is locked until
+    /// `lock_until`
+    async fn record_totp_failure(
+        &self,
+        user_id: Uuid,
+        max_attempts: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<(), RepoError>;
+
+    /// Use up a recovery code, false if the user has none with that hash
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: String) -> Result<bool, RepoError>;
+
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepoError>;
+}
+
+/// WebAuthn credentials and the challenges of the ceremonies in progress
+#[async_trait]
+pub trait PasskeyRepo: Send + Sync {
+    async fn store_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        user_id: Option<Uuid>,
+        challenge: &str,
+        purpose: ChallengePurpose,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepoError>;
+
+    /// Use up an unexpired challenge, each one can only be answered once
+    async fn take_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        purpose: ChallengePurpose,
+    ) -> Result<Option<WebauthnChallenge>, RepoError>;
+
+    /// Conflict if the credential is registered already
+    async fn create_passkey(
+        &self,
+        user_id: Uuid,
+        passkey_id: Uuid,
+        credential_id: String,
+        credential: &RegisteredCredential,
+        name: &str,
+    ) -> Result<(), RepoError>;
+
+    /// Oldest first
+    async fn get_passkeys(&self, user_id: Uuid) -> Result<Vec<Passkey>, RepoError>;
+
+    /// Credential ids of the user's passkeys, base64url
+    async fn get_passkey_credential_ids(&self, user_id: Uuid) -> Result<Vec<String>, RepoError>;
+
+    async fn get_passkey_by_credential_id(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<StoredPasskey>, RepoError>;
+
+    /// Store the new sign counter, false if another login got in first
+    async fn use_passkey(
+        &self,
+        passkey_id: Uuid,
+        old_sign_count: u32,
+        new_sign_count: u32,
+    ) -> Result<bool, RepoError>;
+
+    /// False if the user has no passkey with that id
+    async fn delete_passkey(&self, user_id: Uuid, passkey_id: Uuid) -> Result<bool, RepoError>;
+}
+
+#[async_trait]
+pub trait AccessTokenRepo: Send + Sync {
+    /// `token_hash` is the SHA-256 of the secret
+    async fn create_access_token(
+        &self,
+        user_id: Uuid,
+        token: &PersonalAccessToken,
+        token_hash: String,
+    ) -> Result<(), RepoError>;
+
+    /// Newest first
+    async fn get_access_tokens(&self, user_id: Uuid)
+        -> Result<Vec<PersonalAccessToken>, RepoError>;
+
+    /// Token a request authenticates with, the caller checks the secret and expiry
+    async fn get_access_token(
+        &self,
+        token_id: Uuid,
+    ) -> Result<Option<StoredAccessToken>, RepoError>;
+
+    /// Only called once the token was accepted
+    async fn record_access_token_use(&self, token_id: Uuid) -> Result<(), RepoError>;
+
+    /// False if the user has no token with that id
+    async fn delete_access_token(&self, user_id: Uuid, token_id: Uuid) -> Result<bool, RepoError>;
+}
+
+/// Storage the handlers work with, registered as app data
+#[derive(Clone)]
+pub struct Repos {
+    pub users: Arc<dyn UserRepo>,
+    pub projects: Arc<dyn ProjectRepo>,
+    pub sessions: Arc<dyn SessionRepo>,
+    pub tokens: Arc<dyn TokenRepo>,
+    pub identities: Arc<dyn IdentityRepo>,
+    pub guests: Arc<dyn GuestRepo>,
+    pub passwords: Arc<dyn PasswordRepo>,
+    pub login_codes: Arc<dyn LoginCodeRepo>,
+    pub two_factor: Arc<dyn TwoFactorRepo>,
+    pub passkeys: Arc<dyn PasskeyRepo>,
+    pub access_tokens: Arc<dyn AccessTokenRepo>,
+}
+
+impl Repos {
+    pub fn postgres(pool: PgPool) -> Self {
+        Repos::all(Arc::new(PgRepo::new(pool)))
+    }
+
+    /// Empty in-memory storage, for tests that don't need a database
+    pub fn in_memory() -> Self {
+        Repos::all(Arc::new(MemoryRepo::default()))
+    }
+
+    fn all<R>(repo: Arc<R>) -> Self
+    where
+        R: UserRepo
+            + ProjectRepo
+            + SessionRepo
+            + TokenRepo
+            + IdentityRepo
+            + GuestRepo
+            + PasswordRepo
+            + LoginCodeRepo
+            + TwoFactorRepo
+            + PasskeyRepo
+            + AccessTokenRepo
+            + 'static,
+    {
+        Repos {
+            users: repo.clone(),
+            projects: repo.clone(),
+            sessions: repo.clone(),
+            tokens: repo.clone(),
+            identities: repo.clone(),
+            guests: repo.clone(),
+            passwords: repo.clone(),
+            login_codes: repo.clone(),
+            two_factor: repo.clone(),
+            passkeys: repo.clone(),
+            access_tokens: repo,
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use actix_web::web;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db,
+    models::{
+        ChallengePurpose, Device, DeviceInfo, EmailTokenPurpose, Identity, OauthUser, Passkey,
+        PasswordCredentials, PersonalAccessToken, Project, RefreshToken, Session,
+        StoredAccessToken, StoredPasskey, StoredTotp, User, UserSettings, WebauthnChallenge,
+    },
+    repo::{
+        AccessTokenRepo, GuestRepo, IdentityRepo, LoginCodeRepo, PasskeyRepo, PasswordRepo,
+        ProjectRepo, RepoError, SessionRepo, TokenRepo, TwoFactorRepo, UserRepo,
+    },
+    webauthn::RegisteredCredential,
+};
+
+/// The repositories on Postgres, the queries live in `db`
+pub struct PgRepo {
+    pool: web::Data<PgPool>,
+}
+
+impl PgRepo {
+    pub fn new(pool: PgPool) -> Self {
+        PgRepo {
+            pool: web::Data::new(pool),
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepo for PgRepo {
+    async fn create_user(&self, user: User) -> Result<(), RepoError> {
+        db::create_user(self.pool.clone(), user).await?;
+        Ok(())
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<User, RepoError> {
+        Ok(db::get_user_by_id(self.pool.clone(), user_id).await?)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<User, RepoError> {
+        Ok(db::get_user(self.pool.clone(), email.to_string()).await?)
+    }
+
+    async fn get_settings(&self, user_id: Uuid) -> Result<UserSettings, RepoError> {
+        Ok(db::get_settings(self.pool.clone(), user_id).await?)
+    }
+
+    async fn update_settings(
+        &self,
+        user_id: Uuid,
+        settings: UserSettings,
+    ) -> Result<(), RepoError> {
+        let result = db::update_settings(self.pool.clone(), user_id, settings).await?;
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProjectRepo for PgRepo {
+    async fn add_project(&self, project: Project) -> Result<(), RepoError> {
+        db::add_project(self.pool.clone(), project).await?;
+        Ok(())
+    }
+
+    async fn update_project(&self, project: Project) -> Result<bool, RepoError> {
+        let result = db::update_project(self.pool.clone(), project).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_project(&self, user_id: Uuid, project_id: Uuid) -> Result<bool, RepoError> {
+        let result = db::delete_project(self.pool.clone(), user_id, project_id).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_projects(&self, user_id: Uuid) -> Result<Vec<Project>, RepoError> {
+        Ok(db::get_projects(self.pool.clone(), user_id).await?)
+    }
+}
+
+#[async_trait]
+impl SessionRepo for PgRepo {
+    async fn add_session(&self, session: Session) -> Result<bool, RepoError> {
+        let result = db::add_session(self.pool.clone(), session).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_session(&self, session: Session) -> Result<bool, RepoError> {
+        let result = db::update_session(self.pool.clone(), session).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_active_session(&self, user_id: Uuid) -> Result<Option<Session>, RepoError> {
+        Ok(db::get_active_session(self.pool.clone(), user_id).await?)
+    }
+
+    async fn get_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, RepoError> {
+        Ok(db::get_sessions(self.pool.clone(), user_id).await?)
+    }
+
+    async fn get_focus_time_on(&self, user_id: Uuid, day: NaiveDate) -> Result<i64, RepoError> {
+        Ok(db::get_focus_time_on(self.pool.clone(), user_id, day).await?)
+    }
+}
+
+#[async_trait]
+impl TokenRepo for PgRepo {
+    async fn store_refresh_token(
+        &self,
+        user_id: Uuid,
+        token_id: Uuid,
+        family_id: Uuid,
+        refresh_token: String,
+        expires_at: DateTime<Utc>,
+        device: DeviceInfo,
+    ) -> Result<(), RepoError> {
+        db::store_refresh_token(
+            self.pool.clone(),
+            user_id,
+            token_id,
+            family_id,
+            refresh_token,
+            expires_at,
+            device,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_refresh_token(&self, token_id: Uuid) -> Result<Option<RefreshToken>, RepoError> {
+        match db::get_refresh_token(self.pool.clone(), token_id).await {
+            Ok(token) => Ok(Some(token)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn rotate_refresh_token(&self, token_id: Uuid) -> Result<bool, RepoError> {
+        let result = db::rotate_refresh_token(self.pool.clone(), token_id).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_token_family(
+        &self,
+        family_id: Uuid,
+        access_valid_until: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        Ok(db::revoke_token_family(self.pool.clone(), family_id, access_valid_until).await?)
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+        keep_family: Option<Uuid>,
+        access_valid_until: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        Ok(
+            db::revoke_user_tokens(self.pool.clone(), user_id, keep_family, access_valid_until)
+                .await?,
+        )
+    }
+
+    async fn is_token_family_revoked(&self, family_id: Uuid) -> Result<bool, RepoError> {
+        Ok(db::is_token_family_revoked(&self.pool, family_id).await?)
+    }
+
+    async fn owns_token_family(&self, user_id: Uuid, family_id: Uuid) -> Result<bool, RepoError> {
+        Ok(db::owns_token_family(self.pool.clone(), user_id, family_id).await?)
+    }
+
+    async fn get_devices(
+        &self,
+        user_id: Uuid,
+        current_family: Option<Uuid>,
+    ) -> Result<Vec<Device>, RepoError> {
+        Ok(db::get_devices(self.pool.clone(), user_id, current_family).await?)
+    }
+}
+
+#[async_trait]
+impl IdentityRepo for PgRepo {
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, RepoError> {
+        Ok(db::get_identity_user(self.pool.clone(), provider, subject).await?)
+    }
+
+    async fn create_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        o_user: &OauthUser,
+    ) -> Result<(), RepoError> {
+        db::create_identity(self.pool.clone(), user_id, provider, o_user).await?;
+        Ok(())
+    }
+
+    async fn get_identities(&self, user_id: Uuid) -> Result<Vec<Identity>, RepoError> {
+        Ok(db::get_identities(self.pool.clone(), user_id).await?)
+    }
+
+    async fn delete_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+    ) -> Result<bool, RepoError> {
+        let result = db::delete_identity(self.pool.clone(), user_id, provider, subject).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn had_identity(&self, user_id: Uuid, provider: &str) -> Result<bool, RepoError> {
+        Ok(db::had_identity(self.pool.clone(), user_id, provider).await?)
+    }
+}
+
+#[async_trait]
+impl GuestRepo for PgRepo {
+    async fn upgrade_guest(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        o_user: &OauthUser,
+    ) -> Result<bool, RepoError> {
+        Ok(db::upgrade_guest(self.pool.clone(), user_id, provider, o_user).await?)
+    }
+
+    async fn merge_guest(&self, guest_id: Uuid, user_id: Uuid) -> Result<(), RepoError> {
+        Ok(db::merge_guest(self.pool.clone(), guest_id, user_id).await?)
+    }
+}
+
+#[async_trait]
+impl PasswordRepo for PgRepo {
+    async fn create_password_user(
+        &self,
+        user: User,
+        password_hash: String,
+    ) -> Result<(), RepoError> {
+        db::create_password_user(self.pool.clone(), user, password_hash).await?;
+        Ok(())
+    }
+
+    async fn get_password_credentials(
+        &self,
+        email: &str,
+    ) -> Result<Option<PasswordCredentials>, RepoError> {
+        Ok(db::get_password_credentials(self.pool.clone(), email).await?)
+    }
+
+    async fn get_password_hash(&self, user_id: Uuid) -> Result<Option<String>, RepoError> {
+        Ok(db::get_password_hash(self.pool.clone(), user_id).await?)
+    }
+
+    async fn set_password(&self, user_id: Uuid, password_hash: String) -> Result<(), RepoError> {
+        db::set_password(self.pool.clone(), user_id, password_hash).await?;
+        Ok(())
+    }
+
+    async fn mark_email_verified(&self, user_id: Uuid) -> Result<(), RepoError> {
+        db::mark_email_verified(self.pool.clone(), user_id).await?;
+        Ok(())
+    }
+
+    async fn drop_unverified_password(&self, user_id: Uuid) -> Result<(), RepoError> {
+        db::drop_unverified_password(self.pool.clone(), user_id).await?;
+        Ok(())
+    }
+
+    async fn store_email_token(
+        &self,
+        user_id: Uuid,
+        token_hash: String,
+        purpose: EmailTokenPurpose,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        db::store_email_token(self.pool.clone(), user_id, token_hash, purpose, expires_at).await?;
+        Ok(())
+    }
+
+    async fn consume_email_token(
+        &self,
+        token_hash: String,
+        purpose: EmailTokenPurpose,
+    ) -> Result<Option<Uuid>, RepoError> {
+        Ok(db::consume_email_token(self.pool.clone(), token_hash, purpose).await?)
+    }
+
+    async fn record_password_email_request(
+        &self,
+        email: &str,
+        per_hour: i64,
+    ) -> Result<bool, RepoError> {
+        Ok(db::record_password_email_request(self.pool.clone(), email, per_hour).await?)
+    }
+}
+
+#[async_trait]
+impl LoginCodeRepo for PgRepo {
+    async fn replace_login_code(
+        &self,
+        email: &str,
+        code_hash: String,
+        link_hash: String,
+        expires_at: DateTime<Utc>,
+        max_attempts: i32,
+        per_hour: i64,
+    ) -> Result<bool, RepoError> {
+        Ok(db::replace_login_code(
+            self.pool.clone(),
+            email,
+            code_hash,
+            link_hash,
+            expires_at,
+            max_attempts,
+            per_hour,
+        )
+        .await?)
+    }
+
+    async fn use_login_code(
+        &self,
+        email: &str,
+        code_hash: &str,
+        max_attempts: i32,
+    ) -> Result<bool, RepoError> {
+        Ok(db::use_login_code(self.pool.clone(), email, code_hash, max_attempts).await?)
+    }
+
+    async fn use_login_link(&self, link_hash: &str) -> Result<Option<String>, RepoError> {
+        Ok(db::use_login_link(self.pool.clone(), link_hash).await?)
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepo for PgRepo {
+    async fn get_totp(&self, user_id: Uuid) -> Result<Option<StoredTotp>, RepoError> {
+        Ok(db::get_totp(self.pool.clone(), user_id).await?)
+    }
+
+    async fn set_pending_totp(&self, user_id: Uuid, secret: String) -> Result<(), RepoError> {
+        db::set_pending_totp(self.pool.clone(), user_id, secret).await?;
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepoError> {
+        Ok(db::enable_totp(self.pool.clone(), user_id, recovery_code_hashes).await?)
+    }
+
+    async fn disable_totp(&self, user_id: Uuid) -> Result<(), RepoError> {
+        Ok(db::disable_totp(self.pool.clone(), user_id).await?)
+    }
+
+    async fn record_totp_use(&self, user_id: Uuid, step: i64) -> Result<bool, RepoError> {
+        Ok(db::record_totp_use(self.pool.clone(), user_id, step).await?)
+    }
+
+    async fn record_totp_failure(
+        &self,
+        user_id: Uuid,
+        max_attempts: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        db::record_totp_failure(self.pool.clone(), user_id, max_attempts, lock_until).await?;
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: String) -> Result<bool, RepoError> {
+        Ok(db::use_recovery_code(self.pool.clone(), user_id, code_hash).await?)
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), RepoError> {
+        Ok(db::replace_recovery_codes(self.pool.clone(), user_id, recovery_code_hashes).await?)
+    }
+}
+
+#[async_trait]
+impl PasskeyRepo for PgRepo {
+    async fn store_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        user_id: Option<Uuid>,
+        challenge: &str,
+        purpose: ChallengePurpose,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepoError> {
+        db::store_webauthn_challenge(
+            self.pool.clone(),
+            challenge_id,
+            user_id,
+            challenge,
+            purpose,
+            expires_at,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn take_webauthn_challenge(
+        &self,
+        challenge_id: Uuid,
+        purpose: ChallengePurpose,
+    ) -> Result<Option<WebauthnChallenge>, RepoError> {
+        Ok(db::take_webauthn_challenge(self.pool.clone(), challenge_id, purpose).await?)
+    }
+
+    async fn create_passkey(
+        &self,
+        user_id: Uuid,
+        passkey_id: Uuid,
+        credential_id: String,
+        credential: &RegisteredCredential,
+        name: &str,
+    ) -> Result<(), RepoError> {
+        db::create_passkey(
+            self.pool.clone(),
+            user_id,
+            passkey_id,
+            credential_id,
+            credential,
+            name,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_passkeys(&self, user_id: Uuid) -> Result<Vec<Passkey>, RepoError> {
+        Ok(db::get_passkeys(self.pool.clone(), user_id).await?)
+    }
+
+    async fn get_passkey_credential_ids(&self, user_id: Uuid) -> Result<Vec<String>, RepoError> {
+        Ok(db::get_passkey_credential_ids(self.pool.clone(), user_id).await?)
+    }
+
+    async fn get_passkey_by_credential_id(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<StoredPasskey>, RepoError> {
+        Ok(db::get_passkey_by_credential_id(self.pool.clone(), credential_id).await?)
+    }
+
+    async fn use_passkey(
+        &self,
+        passkey_id: Uuid,
+        old_sign_count: u32,
+        new_sign_count: u32,
+    ) -> Result<bool, RepoError> {
+        Ok(db::use_passkey(
+            self.pool.clone(),
+            passkey_id,
+            old_sign_count,
+            new_sign_count,
+        )
+        .await?)
+    }
+
+    async fn delete_passkey(&self, user_id: Uuid, passkey_id: Uuid) -> Result<bool, RepoError> {
+        let result = db::delete_passkey(self.pool.clone(), user_id, passkey_id).await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl AccessTokenRepo for PgRepo {
+    async fn create_access_token(
+        &self,
+        user_id: Uuid,
+        token: &PersonalAccessToken,
+        token_hash: String,
+    ) -> Result<(), RepoError> {
+        db::create_access_token(self.pool.clone(), user_id, token, token_hash).await?;
+        Ok(())
+    }
+
+    async fn get_access_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PersonalAccessToken>, RepoError> {
+        Ok(db::get_access_tokens(self.pool.clone(), user_id).await?)
+    }
+
+    async fn get_access_token(
+        &self,
+        token_id: Uuid,
+    ) -> Result<Option<StoredAccessToken>, RepoError> {
+        Ok(db::get_access_token(&self.pool, token_id).await?)
+    }
+
+    async fn record_access_token_use(&self, token_id: Uuid) -> Result<(), RepoError> {
+        Ok(db::record_access_token_use(&self.pool, token_id).await?)
+    }
+
+    async fn delete_access_token(&self, user_id: Uuid, token_id: Uuid) -> Result<bool, RepoError> {
+        let result = db::delete_access_token(self.pool.clone(), user_id, token_id).await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -1,14 +1,89 @@
 use actix_web::web;
 
 use crate::handlers::{
-    add_project, add_session, check_active_session, delete_project, get_projects, get_sessions,
-    get_todays_focus_time, health_check, login_user, update_project, update_session,
+    add_project, add_session, change_password, check_active_session, confirm_totp,
+    create_access_token, delete_passkey, delete_project, disable_totp, enroll_totp,
+    finish_passkey_login, finish_passkey_registration, get_access_tokens, get_devices,
+    get_identities, get_metrics, get_passkeys, get_projects, get_sessions, get_settings,
+    get_todays_focus_time, health_check, jwks, link_identity, livez, login_guest, login_user,
+    logout, logout_all, oidc_authorize, password_login, readyz, refresh_token,
+    regenerate_recovery_codes, register, request_login_code, request_password_reset,
+    resend_verification, reset_password, revoke_access_token, revoke_device, start_passkey_login,
+    start_passkey_registration, unlink_identity, update_project, update_session, update_settings,
+    upgrade_guest, verify_email, verify_login_code, verify_two_factor, version,
 };
+use crate::models::Scope;
 
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg
         // auth
+        .route("/login/guest", web::post().to(login_guest))
         .route("/login/{provider}", web::post().to(login_user))
+        .route("/login/{provider}/authorize", web::get().to(oidc_authorize))
+        .route("/token/refresh", web::post().to(refresh_token))
+        .route("/logout", web::post().to(logout))
+        .route("/logout_all", web::post().to(logout_all))
+        .route("/.well-known/jwks.json", web::get().to(jwks))
+        .route("/guest/upgrade/{provider}", web::post().to(upgrade_guest))
+        // linked provider accounts
+        .route("/identities", web::get().to(get_identities))
+        .route("/identities/{provider}", web::post().to(link_identity))
+        .route(
+            "/identities/{provider}/{subject}",
+            web::delete().to(unlink_identity),
+        )
+        // email and password accounts
+        .route("/register", web::post().to(register))
+        .route("/register/verify", web::post().to(verify_email))
+        .route("/register/resend", web::post().to(resend_verification))
+        .route("/password/login", web::post().to(password_login))
+        .route("/password/change", web::post().to(change_password))
+        .route(
+            "/password/reset/request",
+            web::post().to(request_password_reset),
+        )
+        .route("/password/reset", web::post().to(reset_password))
+        // two factor
+        .route("/2fa/totp/enroll", web::post().to(enroll_totp))
+        .route("/2fa/totp/confirm", web::post().to(confirm_totp))
+        .route("/2fa/totp/disable", web::post().to(disable_totp))
+        .route(
+            "/2fa/recovery_codes",
+            web::post().to(regenerate_recovery_codes),
+        )
+        .route("/2fa/verify", web::post().to(verify_two_factor))
+        // passkeys
+        .route("/passkeys", web::get().to(get_passkeys))
+        .route("/passkeys/{passkey_id}", web::delete().to(delete_passkey))
+        .route(
+            "/passkeys/register/start",
+            web::post().to(start_passkey_registration),
+        )
+        .route(
+            "/passkeys/register/finish",
+            web::post().to(finish_passkey_registration),
+        )
+        .route("/passkeys/login/start", web::post().to(start_passkey_login))
+        .route(
+            "/passkeys/login/finish",
+            web::post().to(finish_passkey_login),
+        )
+        // passwordless
+        .route("/login_code", web::post().to(request_login_code))
+        .route("/login_code/verify", web::post().to(verify_login_code))
+        // personal access tokens
+        .route("/access_tokens", web::get().to(get_access_tokens))
+        .route("/access_tokens", web::post().to(create_access_token))
+        .route(
+            "/access_tokens/{token_id}",
+            web::delete().to(revoke_access_token),
+        )
+        // account settings
+        .route("/settings", web::get().to(get_settings))
+        .route("/settings", web::post().to(update_settings))
+        // devices
+        .route("/devices", web::get().to(get_devices))
+        .route("/devices/{device_id}", web::delete().to(revoke_device))
         // project
         .route("/add_project", web::post().to(add_project))
         .route("/update_project", web::post().to(update_project))
@@ -24,8 +99,29 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
         .route("/get_sessions/{user_id}", web::get().to(get_sessions))
         // misc
         .route("/health_check", web::get().to(health_check))
+        .route("/livez", web::get().to(livez))
+        .route("/readyz", web::get().to(readyz))
+        .route("/version", web::get().to(version))
+        .route("/metrics", web::get().to(get_metrics))
         .route(
             "/get_todays_focus_time/{user_id}",
             web::get().to(get_todays_focus_time),
         );
 }
+
+/// Scopes a personal access token needs for the route (by its pattern)
+/// Routes not listed here can only be used with a login, this keeps tokens from managing tokens,
+/// devices and logins
+pub fn required_scopes(pattern: &str) -> Option<&'static [Scope]> {
+    match pattern {
+        "/add_project" | "/update_project" | "/delete_project" => Some(&[Scope::ProjectsWrite]),
+        "/get_projects/{user_id}" => Some(&[Scope::ProjectsRead]),
+        "/add_session" | "/update_session" => Some(&[Scope::SessionsWrite]),
+        "/check_active_session/{user_id}" | "/get_sessions/{user_id}" => {
+            Some(&[Scope::SessionsRead])
+        }
+        "/get_todays_focus_time/{user_id}" => Some(&[Scope::StatsRead]),
+        "/health_check" => Some(&[]),
+        _ => None,
+    }
+}
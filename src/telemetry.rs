@@ -0,0 +1,41 @@
+use tracing::Subscriber;
+use tracing_subscriber::{
+    filter::filter_fn, fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Layer, Registry,
+};
+
+use crate::{
+    config::{LogFormat, LoggingConfig},
+    metrics::{PoolAcquireLayer, POOL_ACQUIRE_TARGET},
+};
+
+/// Log subscriber writing to `writer` in the configured format, also feeds the pool acquire
+/// time metric
+pub fn subscriber<W>(config: &LoggingConfig, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    let logs = match config.format {
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+    let acquire_times =
+        PoolAcquireLayer.with_filter(filter_fn(|meta| meta.target() == POOL_ACQUIRE_TARGET));
+
+    Box::new(
+        Registry::default()
+            .with(logs.with_filter(EnvFilter::new(&config.filter)))
+            .with(acquire_times),
+    )
+}
+
+/// Send the logs of the whole process to stdout
+pub fn init_tracing(config: &LoggingConfig) {
+    tracing::subscriber::set_global_default(subscriber(config, std::io::stdout))
+        .expect("Failed to install the log subscriber");
+}
@@ -0,0 +1,264 @@
+use std::{fmt::Display, io::Cursor};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value as Cbor;
+use ring::{
+    agreement::{self, EphemeralPrivateKey, ECDH_P256},
+    rand::SystemRandom,
+    signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ED25519},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// COSE algorithm identifiers we accept
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+
+/// COSE key types and curves (RFC 9053), each algorithm only comes with one of each
+const KTY_OKP: i64 = 1;
+const KTY_EC2: i64 = 2;
+const CRV_P256: i64 = 1;
+const CRV_ED25519: i64 = 6;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The site passkeys are bound to, `id` is the domain, `origins` are the web app origins allowed
+/// to use them
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origins: Vec<String>,
+}
+
+/// Public key of a newly registered credential
+pub struct RegisteredCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+}
+
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+#[derive(Debug)]
+pub struct WebauthnError(String);
+
+impl Display for WebauthnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "webauthn: {}", self.0)
+    }
+}
+
+impl std::error::Error for WebauthnError {}
+
+fn error(message: &str) -> WebauthnError {
+    WebauthnError(message.to_string())
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ty: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions, registration only
+    rest: &'a [u8],
+}
+
+impl RelyingParty {
+    /// Check a `navigator.credentials.create()` response against the challenge we issued
+    /// Attestation statements aren't verified, we only ask for "none" and trust the key the
+    /// authenticator reports, like most relying parties that don't restrict authenticator models
+    pub fn verify_registration(
+        &self,
+        challenge: &str,
+        client_data_json: &[u8],
+        attestation_object: &[u8],
+    ) -> Result<RegisteredCredential, WebauthnError> {
+        self.check_client_data(client_data_json, "webauthn.create", challenge)?;
+
+        let attestation: Cbor = ciborium::from_reader(attestation_object)
+            .map_err(|_| error("attestation object is not CBOR"))?;
+        let auth_data = map_get(&attestation, |key| key.as_text() == Some("authData"))
+            .and_then(Cbor::as_bytes)
+            .ok_or_else(|| error("attestation object has no authData"))?;
+        let auth_data = self.check_authenticator_data(auth_data)?;
+        if auth_data.flags & FLAG_ATTESTED_CREDENTIAL == 0 {
+            return Err(error("no attested credential data"));
+        }
+
+        // aaguid (16), credential id length (2), credential id, COSE public key
+        let rest = auth_data.rest;
+        if rest.len() < 18 {
+            return Err(error("attested credential data is too short"));
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let credential_id = rest
+            .get(18..18 + id_len)
+            .ok_or_else(|| error("credential id is truncated"))?
+            .to_vec();
+        let cose_key: Cbor = ciborium::from_reader(Cursor::new(&rest[18 + id_len..]))
+            .map_err(|_| error("credential public key is not CBOR"))?;
+        let (algorithm, public_key) = parse_cose_key(&cose_key)?;
+
+        Ok(RegisteredCredential {
+            credential_id,
+            public_key,
+            algorithm,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Check a `navigator.credentials.get()` response against the challenge and the stored key
+    pub fn verify_assertion(
+        &self,
+        challenge: &str,
+        public_key: &[u8],
+        algorithm: i64,
+        client_data_json: &[u8],
+        authenticator_data: &[u8],
+        signature: &[u8],
+    ) -> Result<VerifiedAssertion, WebauthnError> {
+        self.check_client_data(client_data_json, "webauthn.get", challenge)?;
+        let auth_data = self.check_authenticator_data(authenticator_data)?;
+
+        let mut signed = authenticator_data.to_vec();
+        signed.extend_from_slice(&Sha256::digest(client_data_json));
+        let verification = match algorithm {
+            ES256 => UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, public_key)
+                .verify(&signed, signature),
+            EDDSA => UnparsedPublicKey::new(&ED25519, public_key).verify(&signed, signature),
+            _ => return Err(error("unsupported algorithm")),
+        };
+        verification.map_err(|_| error("invalid signature"))?;
+
+        Ok(VerifiedAssertion {
+            sign_count: auth_data.sign_count,
+            user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+        })
+    }
+
+    fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        ty: &str,
+        challenge: &str,
+    ) -> Result<(), WebauthnError> {
+        let client_data: ClientData = serde_json::from_slice(client_data_json)
+            .map_err(|_| error("client data is not valid JSON"))?;
+        if client_data.ty != ty {
+            return Err(error("wrong client data type"));
+        }
+        if client_data.challenge != challenge {
+            return Err(error("challenge mismatch"));
+        }
+        if !self.origins.contains(&client_data.origin) {
+            return Err(error("origin not allowed"));
+        }
+        Ok(())
+    }
+
+    fn check_authenticator_data<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> Result<AuthenticatorData<'a>, WebauthnError> {
+        if data.len() < 37 {
+            return Err(error("authenticator data is too short"));
+        }
+        let auth_data = AuthenticatorData {
+            rp_id_hash: &data[..32],
+            flags: data[32],
+            sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+            rest: &data[37..],
+        };
+        if auth_data.rp_id_hash != Sha256::digest(self.id.as_bytes()).as_slice() {
+            return Err(error("relying party id mismatch"));
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err(error("user not present"));
+        }
+        Ok(auth_data)
+    }
+}
+
+/// Random challenge, base64url encoded as it appears in the client data
+pub fn new_challenge() -> String {
+    let challenge: [u8; 32] = rand::random();
+    URL_SAFE_NO_PAD.encode(challenge)
+}
+
+/// ES256 keys as uncompressed P-256 points, EdDSA keys as the raw Ed25519 key, what `ring`
+/// verifies against
+/// The key type and curve have to be the ones of the algorithm, and P-256 points on the curve
+fn parse_cose_key(key: &Cbor) -> Result<(i64, Vec<u8>), WebauthnError> {
+    let value = |label: i64| map_get(key, |k| k.as_integer() == Some(label.into()));
+    let int = |label: i64| {
+        value(label)
+            .and_then(Cbor::as_integer)
+            .and_then(|int| i64::try_from(int).ok())
+    };
+    let bytes = |label: i64| {
+        value(label)
+            .and_then(Cbor::as_bytes)
+            .ok_or_else(|| error("public key is missing a coordinate"))
+    };
+    let algorithm = int(3).ok_or_else(|| error("public key has no algorithm"))?;
+    let (kty, crv) = (int(1), int(-1));
+
+    match algorithm {
+        ES256 => {
+            if kty != Some(KTY_EC2) || crv != Some(CRV_P256) {
+                return Err(error("ES256 key is not an EC2 P-256 key"));
+            }
+            let (x, y) = (bytes(-2)?, bytes(-3)?);
+            if x.len() != 32 || y.len() != 32 {
+                return Err(error("invalid P-256 key"));
+            }
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+            if !is_p256_point(&point) {
+                return Err(error("P-256 key is not on the curve"));
+            }
+            Ok((ES256, point))
+        }
+        EDDSA => {
+            if kty != Some(KTY_OKP) || crv != Some(CRV_ED25519) {
+                return Err(error("EdDSA key is not an OKP Ed25519 key"));
+            }
+            let x = bytes(-2)?;
+            if x.len() != 32 {
+                return Err(error("invalid Ed25519 key"));
+            }
+            Ok((EDDSA, x.to_vec()))
+        }
+        _ => Err(error("unsupported algorithm")),
+    }
+}
+
+/// `ring` has no way to parse a bare ECDSA key, but key agreement validates the peer's point
+fn is_p256_point(point: &[u8]) -> bool {
+    let Ok(private_key) = EphemeralPrivateKey::generate(&ECDH_P256, &SystemRandom::new()) else {
+        return false;
+    };
+    let peer = agreement::UnparsedPublicKey::new(&ECDH_P256, point);
+    agreement::agree_ephemeral(private_key, &peer, |_| ()).is_ok()
+}
+
+fn map_get(map: &Cbor, matches: impl Fn(&Cbor) -> bool) -> Option<&Cbor> {
+    map.as_map()?
+        .iter()
+        .find(|(key, _)| matches(key))
+        .map(|(_, value)| value)
+}
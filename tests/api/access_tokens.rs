@@ -0,0 +1,225 @@
+use chrono::{Duration, Utc};
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_token(app: &TestApp, access_token: &str, body: Value) -> reqwest::Response {
+    app.request(Method::POST, "/access_tokens", access_token, Some(&body))
+        .await
+}
+
+#[sqlx::test]
+async fn access_tokens_work_on_routes_within_their_scopes(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let created: Value = create_token(
+        &app,
+        &access_token,
+        json!({ "name": "home assistant", "scopes": ["projects:read", "stats:read"] }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let pat = created["token"].as_str().unwrap();
+    assert!(pat.starts_with("kairos_pat_"));
+
+    let projects = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", user_id),
+            pat,
+            None,
+        )
+        .await;
+    let focus = app
+        .request(
+            Method::GET,
+            &format!("/get_todays_focus_time/{}", user_id),
+            pat,
+            None,
+        )
+        .await;
+    let sessions = app
+        .request(
+            Method::GET,
+            &format!("/get_sessions/{}", user_id),
+            pat,
+            None,
+        )
+        .await;
+
+    assert_eq!(projects.status().as_u16(), 200);
+    assert_eq!(focus.status().as_u16(), 200);
+    assert_eq!(sessions.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn access_tokens_cannot_manage_tokens_or_devices(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    let created: Value = create_token(
+        &app,
+        &access_token,
+        json!({ "name": "script", "scopes": ["sessions:write"] }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let pat = created["token"].as_str().unwrap();
+
+    let escalate = create_token(
+        &app,
+        pat,
+        json!({ "name": "more", "scopes": ["projects:write"] }),
+    )
+    .await;
+    let devices = app.request(Method::GET, "/devices", pat, None).await;
+    let logout = app.request(Method::POST, "/logout_all", pat, None).await;
+
+    assert_eq!(escalate.status().as_u16(), 403);
+    assert_eq!(devices.status().as_u16(), 403);
+    assert_eq!(logout.status().as_u16(), 403);
+}
+
+#[sqlx::test]
+async fn listing_access_tokens_never_returns_the_secret(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    create_token(
+        &app,
+        &access_token,
+        json!({ "name": "editor plugin", "scopes": ["sessions:write"] }),
+    )
+    .await;
+
+    let tokens: Vec<Value> = app
+        .request(Method::GET, "/access_tokens", &access_token, None)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0]["name"], "editor plugin");
+    assert_eq!(tokens[0]["scopes"], json!(["sessions:write"]));
+    assert!(tokens[0].get("token").is_none());
+    let stored = sqlx::query!("SELECT token_hash FROM personal_access_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.token_hash.len(), 64);
+}
+
+#[sqlx::test]
+async fn revoked_and_expired_access_tokens_are_rejected(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    let created: Value = create_token(
+        &app,
+        &access_token,
+        json!({
+            "name": "cron",
+            "scopes": ["projects:read"],
+            "expiresAt": Utc::now() + Duration::days(30)
+        }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let pat = created["token"].as_str().unwrap();
+    assert_eq!(
+        app.request(Method::GET, "/health_check", pat, None)
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+
+    sqlx::query!("UPDATE personal_access_tokens SET expires_at = NOW() - INTERVAL '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let expired = app.request(Method::GET, "/health_check", pat, None).await;
+    let revoke = app
+        .request(
+            Method::DELETE,
+            &format!("/access_tokens/{}", created["tokenId"].as_str().unwrap()),
+            &access_token,
+            None,
+        )
+        .await;
+    let revoked = app.request(Method::GET, "/health_check", pat, None).await;
+    // Right id, wrong secret
+    let forged = format!("{}x", &pat[..pat.len() - 1]);
+    let forged = app
+        .request(Method::GET, "/health_check", &forged, None)
+        .await;
+
+    assert_eq!(expired.status().as_u16(), 401);
+    assert_eq!(revoke.status().as_u16(), 200);
+    assert_eq!(revoked.status().as_u16(), 401);
+    assert_eq!(forged.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn creating_access_tokens_validates_the_request(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+
+    let cases = [
+        json!({ "name": "", "scopes": ["projects:read"] }),
+        json!({ "name": "no scopes", "scopes": [] }),
+        json!({ "name": "past", "scopes": ["projects:read"], "expiresAt": Utc::now() - Duration::days(1) }),
+    ];
+    for body in cases {
+        let response = create_token(&app, &access_token, body.clone()).await;
+
+        assert_eq!(response.status().as_u16(), 400, "{}", body);
+    }
+    let unknown_scope = create_token(
+        &app,
+        &access_token,
+        json!({ "name": "admin", "scopes": ["admin"] }),
+    )
+    .await;
+    assert_eq!(unknown_scope.status().as_u16(), 400);
+}
+
+#[sqlx::test]
+async fn only_accepted_access_tokens_record_a_use(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    let created: Value = create_token(
+        &app,
+        &access_token,
+        json!({ "name": "cron", "scopes": ["projects:read"] }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let pat = created["token"].as_str().unwrap();
+    let last_used_at = || async {
+        sqlx::query_scalar!("SELECT last_used_at FROM personal_access_tokens")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+    };
+
+    // Right id, wrong secret
+    let (id, _) = pat.rsplit_once('_').unwrap();
+    let forged = app
+        .request(Method::GET, "/health_check", &format!("{}_guess", id), None)
+        .await;
+    assert_eq!(forged.status().as_u16(), 401);
+    assert!(last_used_at().await.is_none());
+
+    let accepted = app.request(Method::GET, "/health_check", pat, None).await;
+    assert_eq!(accepted.status().as_u16(), 200);
+    assert!(last_used_at().await.is_some());
+}
@@ -0,0 +1,196 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use kairos_server::{
+    create_pool,
+    repo::{RepoError, Repos},
+    Config,
+};
+use reqwest::{Method, Url};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::helpers::spawn_app;
+
+/// TCP proxy in front of the test Postgres that can be taken down and brought back
+struct DbProxy {
+    port: u16,
+    upstream: String,
+    /// Accept loop, owns the listening socket while up
+    listener: Mutex<Option<JoinHandle<()>>>,
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl DbProxy {
+    async fn start(up: bool) -> Self {
+        let upstream = database_url();
+        let upstream = format!(
+            "{}:{}",
+            upstream.host_str().unwrap(),
+            upstream.port().unwrap_or(5432)
+        );
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let proxy = DbProxy {
+            port,
+            upstream,
+            listener: Mutex::default(),
+            connections: Arc::default(),
+        };
+        if up {
+            proxy.up().await;
+        }
+        proxy
+    }
+
+    async fn up(&self) {
+        let listener = TcpListener::bind(("127.0.0.1", self.port)).await.unwrap();
+        let (upstream, connections) = (self.upstream.clone(), self.connections.clone());
+        let accept = tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let upstream = upstream.clone();
+                let connection = tokio::spawn(async move {
+                    if let Ok(mut server) = TcpStream::connect(upstream).await {
+                        let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                    }
+                });
+                connections.lock().unwrap().push(connection);
+            }
+        });
+        *self.listener.lock().unwrap() = Some(accept);
+    }
+
+    /// Drop the open connections and refuse new ones, like a restarting database
+    fn down(&self) {
+        if let Some(accept) = self.listener.lock().unwrap().take() {
+            accept.abort();
+        }
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+
+    /// Config connecting to `database` through the proxy
+    fn config(&self, database: &str) -> Config {
+        let mut url = database_url();
+        url.set_host(Some("127.0.0.1")).unwrap();
+        url.set_port(Some(self.port)).unwrap();
+        url.set_path(database);
+
+        let mut config = Config::default();
+        config.database.url = url.to_string();
+        config.database.acquire_timeout_secs = 1;
+        config.database.startup_timeout_secs = 30;
+        config
+    }
+}
+
+fn database_url() -> Url {
+    std::env::var("DATABASE_URL").unwrap().parse().unwrap()
+}
+
+#[sqlx::test]
+async fn startup_waits_for_the_database(_: PgPoolOptions, options: PgConnectOptions) {
+    let proxy = DbProxy::start(false).await;
+    let config = proxy.config(options.get_database().unwrap());
+
+    let started = Instant::now();
+    let connecting = tokio::spawn(async move { create_pool(&config.database).await });
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!connecting.is_finished());
+    proxy.up().await;
+
+    let pool = connecting
+        .await
+        .unwrap()
+        .expect("connects once the database is up");
+    assert!(started.elapsed() >= Duration::from_millis(1500));
+    sqlx::query("SELECT 1").execute(&pool).await.unwrap();
+}
+
+#[sqlx::test]
+async fn startup_gives_up_after_the_timeout(_: PgPoolOptions, options: PgConnectOptions) {
+    let proxy = DbProxy::start(false).await;
+    let mut config = proxy.config(options.get_database().unwrap());
+    config.database.startup_timeout_secs = 2;
+
+    let started = Instant::now();
+    let error = create_pool(&config.database).await.unwrap_err();
+
+    assert!(started.elapsed() >= Duration::from_secs(2));
+    assert!(started.elapsed() < Duration::from_secs(10), "{}", error);
+}
+
+#[sqlx::test]
+async fn startup_fails_fast_on_errors_retrying_wont_fix(_: PgPoolOptions, _: PgConnectOptions) {
+    let proxy = DbProxy::start(true).await;
+    let config = proxy.config("no_such_database");
+
+    let started = Instant::now();
+    let error = create_pool(&config.database).await.unwrap_err();
+
+    assert!(started.elapsed() < Duration::from_secs(5), "{}", error);
+    assert!(error.to_string().contains("no_such_database"), "{}", error);
+}
+
+#[sqlx::test]
+async fn requests_get_503_while_the_database_is_down(_: PgPoolOptions, options: PgConnectOptions) {
+    let proxy = DbProxy::start(true).await;
+    let config = proxy.config(options.get_database().unwrap());
+    let pool = create_pool(&config.database).await.unwrap();
+    let repos = Repos::postgres(pool.clone());
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let projects = format!("/get_projects/{}", user_id);
+
+    proxy.down();
+    // A failed lookup is not a missing user
+    let lookup = repos.users.get_user_by_email("ada@example.com").await;
+    assert!(
+        matches!(lookup, Err(RepoError::Database(_))),
+        "{:?}",
+        lookup
+    );
+    let response = app
+        .request(Method::GET, &projects, &access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 503);
+    assert_eq!(response.headers()["retry-after"], "5");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "service_unavailable");
+    let ready = app
+        .client
+        .get(format!("{}/readyz", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ready.status().as_u16(), 503);
+    // Scrapes keep working, without the database samples
+    let metrics = app
+        .client
+        .get(format!("{}/metrics", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(metrics.status().as_u16(), 200);
+    assert!(metrics
+        .text()
+        .await
+        .unwrap()
+        .contains("http_requests_total"));
+
+    proxy.up().await;
+    let response = app
+        .request(Method::GET, &projects, &access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
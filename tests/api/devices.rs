@@ -0,0 +1,144 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::{google_claims, id_token, spawn_app, TestApp};
+
+async fn login_from(app: &TestApp, email: &str, device: &str, platform: &str) -> Value {
+    app.client
+        .post(format!("{}/login/google", app.address))
+        .header("X-Device-Name", device)
+        .header("X-Device-Platform", platform)
+        .header("X-App-Version", "1.4.0")
+        .json(&json!({ "id_token": id_token(&google_claims(email)) }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn devices(app: &TestApp, login: &Value) -> Vec<Value> {
+    app.request(
+        Method::GET,
+        "/devices",
+        login["access_token"].as_str().unwrap(),
+        None,
+    )
+    .await
+    .json()
+    .await
+    .unwrap()
+}
+
+#[sqlx::test]
+async fn devices_lists_every_signed_in_device(pool: PgPool) {
+    let app = spawn_app(pool);
+    let laptop = login_from(&app, "ada@example.com", "Ada's MacBook", "macos").await;
+    let phone = login_from(&app, "ada@example.com", "Pixel 8", "android").await;
+    login_from(&app, "bob@example.com", "Bob's phone", "ios").await;
+
+    let devices = devices(&app, &laptop).await;
+
+    assert_eq!(devices.len(), 2);
+    let current: Vec<&Value> = devices.iter().filter(|d| d["current"] == true).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["deviceName"], "Ada's MacBook");
+    assert_eq!(current[0]["platform"], "macos");
+    assert_eq!(current[0]["appVersion"], "1.4.0");
+    assert_eq!(current[0]["ipAddress"], "127.0.0.1");
+    let other = devices.iter().find(|d| d["current"] == false).unwrap();
+    assert_eq!(other["deviceName"], "Pixel 8");
+    assert!(phone["refresh_token"].is_string());
+}
+
+#[sqlx::test]
+async fn refresh_keeps_the_device_and_updates_it(pool: PgPool) {
+    let app = spawn_app(pool);
+    let laptop = login_from(&app, "ada@example.com", "Ada's MacBook", "macos").await;
+
+    let refreshed: Value = app
+        .client
+        .post(format!("{}/token/refresh", app.address))
+        .header("X-App-Version", "1.5.0")
+        .json(&json!({ "refresh_token": laptop["refresh_token"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let devices = devices(&app, &refreshed).await;
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0]["deviceName"], "Ada's MacBook");
+    assert_eq!(devices[0]["appVersion"], "1.5.0");
+    assert_eq!(devices[0]["current"], true);
+}
+
+#[sqlx::test]
+async fn revoking_a_device_signs_it_out(pool: PgPool) {
+    let app = spawn_app(pool);
+    let laptop = login_from(&app, "ada@example.com", "Ada's MacBook", "macos").await;
+    let phone = login_from(&app, "ada@example.com", "Pixel 8", "android").await;
+    let phone_id = devices(&app, &laptop)
+        .await
+        .into_iter()
+        .find(|d| d["current"] == false)
+        .unwrap()["deviceId"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = app
+        .request(
+            Method::DELETE,
+            &format!("/devices/{}", phone_id),
+            laptop["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let phone_access = app
+        .request(
+            Method::GET,
+            "/devices",
+            phone["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    assert_eq!(phone_access.status().as_u16(), 401);
+    let phone_refresh = app
+        .post_json(
+            "/token/refresh",
+            &json!({ "refresh_token": phone["refresh_token"] }),
+        )
+        .await;
+    assert_eq!(phone_refresh.status().as_u16(), 401);
+    assert_eq!(devices(&app, &laptop).await.len(), 1);
+}
+
+#[sqlx::test]
+async fn other_users_devices_cannot_be_revoked(pool: PgPool) {
+    let app = spawn_app(pool);
+    let ada = login_from(&app, "ada@example.com", "Ada's MacBook", "macos").await;
+    let bob = login_from(&app, "bob@example.com", "Bob's phone", "ios").await;
+    let bob_device = devices(&app, &bob).await[0]["deviceId"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = app
+        .request(
+            Method::DELETE,
+            &format!("/devices/{}", bob_device),
+            ada["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(devices(&app, &bob).await.len(), 1);
+}
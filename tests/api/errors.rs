@@ -0,0 +1,90 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{project, spawn_app};
+
+async fn error_body(response: reqwest::Response, status: u16) -> Value {
+    assert_eq!(response.status().as_u16(), status);
+    response.json().await.unwrap()
+}
+
+#[sqlx::test]
+async fn conflicts_and_missing_rows_have_their_own_codes(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let body = project(&Uuid::new_v4().to_string(), &user_id, "Reading");
+    app.request(Method::POST, "/add_project", &access_token, Some(&body))
+        .await;
+
+    let response = app
+        .request(Method::POST, "/add_project", &access_token, Some(&body))
+        .await;
+    let error = error_body(response, 409).await;
+    assert_eq!(error["code"], "conflict");
+    assert_eq!(error["message"], "Project already exists");
+    assert!(error["details"].is_null());
+
+    let missing = project(&Uuid::new_v4().to_string(), &user_id, "Missing");
+    let response = app
+        .request(
+            Method::POST,
+            "/update_project",
+            &access_token,
+            Some(&missing),
+        )
+        .await;
+    let error = error_body(response, 404).await;
+    assert_eq!(error["code"], "not_found");
+    assert_eq!(error["message"], "Project not found");
+}
+
+#[sqlx::test]
+async fn auth_failures_are_json(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+
+    let response = app
+        .client
+        .get(format!("{}/devices", app.address))
+        .send()
+        .await
+        .unwrap();
+    let error = error_body(response, 401).await;
+    assert_eq!(error["code"], "unauthorized");
+
+    let path = format!("/get_projects/{}", Uuid::new_v4());
+    let response = app.request(Method::GET, &path, &access_token, None).await;
+    let error = error_body(response, 403).await;
+    assert_eq!(error["code"], "forbidden");
+}
+
+#[sqlx::test]
+async fn invalid_input_is_a_validation_error(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+
+    let response = app
+        .request(
+            Method::POST,
+            "/add_project",
+            &access_token,
+            Some(&json!({ "projectName": "No ids" })),
+        )
+        .await;
+    let error = error_body(response, 400).await;
+    assert_eq!(error["code"], "validation_failed");
+
+    let body = json!({ "name": "Ada", "email": "ada@example.org", "password": "short" });
+    let response = app.post_json("/register", &body).await;
+    let error = error_body(response, 400).await;
+    assert_eq!(error["code"], "validation_failed");
+    assert_eq!(error["details"]["field"], "password");
+
+    let response = app
+        .request(Method::GET, "/no_such_route", &access_token, None)
+        .await;
+    let error = error_body(response, 404).await;
+    assert_eq!(error["code"], "not_found");
+}
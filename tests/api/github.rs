@@ -0,0 +1,218 @@
+use std::net::TcpListener;
+
+use actix_web::{dev::ServerHandle, web, App, HttpRequest, HttpResponse, HttpServer};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::{github_client, spawn_app_with_providers, test_providers, TestApp};
+
+const GOOD_CODE: &str = "good-code";
+const ACCESS_TOKEN: &str = "gho_test_token";
+
+/// Minimal stand-in for github.com and api.github.com
+struct MockGithub {
+    address: String,
+    server: ServerHandle,
+}
+
+impl Drop for MockGithub {
+    fn drop(&mut self) {
+        drop(self.server.stop(false));
+    }
+}
+
+fn spawn_mock_github(emails: Value) -> MockGithub {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let emails = web::Data::new(emails);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(emails.clone())
+            .route(
+                "/login/oauth/access_token",
+                web::post().to(|form: web::Form<Vec<(String, String)>>| async move {
+                    let code = form.iter().find(|(k, _)| k == "code").map(|(_, v)| v);
+                    if code.map(String::as_str) == Some(GOOD_CODE) {
+                        HttpResponse::Ok().json(json!({
+                            "access_token": ACCESS_TOKEN,
+                            "token_type": "bearer",
+                            "scope": "read:user,user:email"
+                        }))
+                    } else {
+                        HttpResponse::Ok().json(json!({ "error": "bad_verification_code" }))
+                    }
+                }),
+            )
+            .route(
+                "/user",
+                web::get().to(|req: HttpRequest| async move {
+                    if !authorized(&req) {
+                        return HttpResponse::Unauthorized().finish();
+                    }
+                    HttpResponse::Ok().json(json!({
+                        "id": 583231,
+                        "login": "octocat",
+                        "name": null,
+                        "avatar_url": "https://github.com/images/error/octocat_happy.gif",
+                        "email": null
+                    }))
+                }),
+            )
+            .route(
+                "/user/emails",
+                web::get().to(|req: HttpRequest, emails: web::Data<Value>| async move {
+                    if !authorized(&req) {
+                        return HttpResponse::Unauthorized().finish();
+                    }
+                    HttpResponse::Ok().json(emails.get_ref())
+                }),
+            )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    let handle = server.handle();
+    tokio::spawn(server);
+
+    MockGithub {
+        address: format!("http://127.0.0.1:{}", port),
+        server: handle,
+    }
+}
+
+fn authorized(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        == Some(&format!("Bearer {}", ACCESS_TOKEN))
+}
+
+fn spawn_app_with_github(pool: PgPool, github: &MockGithub) -> TestApp {
+    let mut providers = test_providers();
+    providers.github = github_client(&github.address);
+    spawn_app_with_providers(pool, providers)
+}
+
+fn verified_emails() -> Value {
+    json!([
+        { "email": "octocat@users.noreply.github.com", "primary": false, "verified": true },
+        { "email": "octocat@github.com", "primary": true, "verified": true }
+    ])
+}
+
+#[sqlx::test]
+async fn github_login_creates_user_with_default_project(pool: PgPool) {
+    let github = spawn_mock_github(verified_emails());
+    let app = spawn_app_with_github(pool, &github);
+
+    let response = app
+        .post_json("/login/github", &json!({ "code": GOOD_CODE }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["email"], "octocat@github.com");
+    assert_eq!(body["user"]["name"], "octocat");
+    assert_eq!(body["user"]["oauthProvider"], "github");
+    assert!(body["access_token"].is_string());
+    let projects = sqlx::query!("SELECT project_name FROM projects")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(projects.len(), 1);
+    assert_eq!(projects[0].project_name, "Unset");
+}
+
+#[sqlx::test]
+async fn github_login_twice_returns_the_same_user(pool: PgPool) {
+    let github = spawn_mock_github(verified_emails());
+    let app = spawn_app_with_github(pool, &github);
+    let body = json!({ "code": GOOD_CODE });
+
+    let first: Value = app
+        .post_json("/login/github", &body)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let second: Value = app
+        .post_json("/login/github", &body)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(first["user"]["userId"], second["user"]["userId"]);
+    assert_eq!(second["user"]["oauthProvider"], "github");
+}
+
+#[sqlx::test]
+async fn github_login_rejects_bad_codes(pool: PgPool) {
+    let github = spawn_mock_github(verified_emails());
+    let app = spawn_app_with_github(pool, &github);
+
+    let response = app
+        .post_json("/login/github", &json!({ "code": "stolen-code" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn github_login_requires_a_verified_primary_email(pool: PgPool) {
+    let github = spawn_mock_github(json!([
+        { "email": "octocat@github.com", "primary": true, "verified": false },
+        { "email": "octocat@users.noreply.github.com", "primary": false, "verified": true }
+    ]));
+    let app = spawn_app_with_github(pool, &github);
+
+    let response = app
+        .post_json("/login/github", &json!({ "code": GOOD_CODE }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    let users = sqlx::query!("SELECT COUNT(*) AS count FROM users")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, Some(0));
+}
+
+#[sqlx::test]
+async fn github_login_requires_a_code(pool: PgPool) {
+    let github = spawn_mock_github(verified_emails());
+    let app = spawn_app_with_github(pool, &github);
+
+    let response = app.post_json("/login/github", &json!({})).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[sqlx::test]
+async fn github_login_reports_unreachable_provider(pool: PgPool) {
+    let app = crate::helpers::spawn_app(pool);
+
+    let response = app
+        .post_json("/login/github", &json!({ "code": GOOD_CODE }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 502);
+}
+
+#[sqlx::test]
+async fn github_that_never_answers_is_unavailable(pool: PgPool) {
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut providers = test_providers();
+    providers.github = github_client(&format!("http://{}", listener.local_addr().unwrap()));
+    let app = spawn_app_with_providers(pool, providers);
+
+    let response = app
+        .post_json("/login/github", &json!({ "code": GOOD_CODE }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    drop(listener);
+}
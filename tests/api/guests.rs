@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use kairos_server::jobs::{run_job, CollectGuests, RunOutcome};
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    helpers::{google_claims, id_token, project, session, spawn_app, TestApp},
+    two_factor::{enable_totp, totp_code},
+};
+
+/// Log in as a new guest, returns its id and access token
+async fn login_guest(app: &TestApp) -> (String, String) {
+    let response = app.post_json("/login/guest", &json!({})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["guest"], true);
+    (
+        body["user"]["userId"].as_str().unwrap().to_string(),
+        body["access_token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn upgrade(app: &TestApp, access_token: &str, email: &str) -> reqwest::Response {
+    let body = json!({ "id_token": id_token(&google_claims(email)) });
+    app.request(
+        Method::POST,
+        "/guest/upgrade/google",
+        access_token,
+        Some(&body),
+    )
+    .await
+}
+
+async fn get_projects(app: &TestApp, user_id: &str, access_token: &str) -> Value {
+    app.request(
+        Method::GET,
+        &format!("/get_projects/{}", user_id),
+        access_token,
+        None,
+    )
+    .await
+    .json()
+    .await
+    .unwrap()
+}
+
+/// Track a session on the guest's default project and add a second project
+async fn use_the_timer(app: &TestApp, user_id: &str, access_token: &str) -> String {
+    let projects = get_projects(app, user_id, access_token).await;
+    let default_project = projects[0]["projectId"].as_str().unwrap();
+    let session_id = Uuid::new_v4().to_string();
+    let response = app
+        .request(
+            Method::POST,
+            "/add_session",
+            access_token,
+            Some(&session(&session_id, user_id, default_project)),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = project(&Uuid::new_v4().to_string(), user_id, "Reading");
+    let response = app
+        .request(Method::POST, "/add_project", access_token, Some(&body))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    session_id
+}
+
+#[sqlx::test]
+async fn guest_login_creates_an_account_with_the_default_project(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let (user_id, access_token) = login_guest(&app).await;
+
+    let projects = get_projects(&app, &user_id, &access_token).await;
+    assert_eq!(projects.as_array().unwrap().len(), 1);
+    assert_eq!(projects[0]["projectName"], "Unset");
+}
+
+#[sqlx::test]
+async fn upgraded_guest_keeps_projects_and_sessions(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = login_guest(&app).await;
+    let session_id = use_the_timer(&app, &user_id, &access_token).await;
+
+    let response = upgrade(&app, &access_token, "ada@example.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["userId"], user_id);
+    assert_eq!(body["user"]["guest"], false);
+    assert_eq!(body["user"]["email"], "ada@example.com");
+
+    // Signing in with the provider from now on finds the same account
+    let (logged_in, access_token) = app.login_user("ada@example.com").await;
+    assert_eq!(logged_in, user_id);
+    let projects = get_projects(&app, &user_id, &access_token).await;
+    assert_eq!(projects.as_array().unwrap().len(), 2);
+    let sessions = sqlx::query!("SELECT session_id, user_id FROM sessions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].session_id.to_string(), session_id);
+}
+
+#[sqlx::test]
+async fn guest_upgraded_into_an_existing_account_moves_its_data(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (ada_id, _) = app.login_user("ada@example.com").await;
+    let (guest_id, guest_token) = login_guest(&app).await;
+    use_the_timer(&app, &guest_id, &guest_token).await;
+
+    let response = upgrade(&app, &guest_token, "ada@example.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["userId"], ada_id);
+
+    // The guest is gone and so are its logins
+    let response = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", guest_id),
+            &guest_token,
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let access_token = body["access_token"].as_str().unwrap();
+    let projects = get_projects(&app, &ada_id, access_token).await;
+    let mut names: Vec<_> = projects
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["projectName"].as_str().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["Reading", "Unset"]);
+    // The guest's session moved to Ada's default project
+    let session = sqlx::query!(
+        "SELECT projects.user_id, projects.project_name
+         FROM sessions JOIN projects ON projects.project_id = sessions.project_id"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(session.user_id.to_string(), ada_id);
+    assert_eq!(session.project_name, "Unset");
+    let guests = sqlx::query!("SELECT COUNT(*) AS count FROM users WHERE guest")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(guests.count, Some(0));
+}
+
+#[sqlx::test]
+async fn guest_upgraded_into_an_account_with_2fa_moves_after_the_second_step(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (alice_id, alice_token) = app.login_user("alice@example.com").await;
+    let (secret, _) = enable_totp(&app, &alice_token).await;
+    let (guest_id, guest_token) = login_guest(&app).await;
+    use_the_timer(&app, &guest_id, &guest_token).await;
+
+    let response = upgrade(&app, &guest_token, "alice@example.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["two_factor_required"], true);
+    assert!(body["access_token"].is_null());
+    let challenge_token = body["challenge_token"].as_str().unwrap();
+
+    // Nothing moved yet, the guest still owns its data
+    let guest_uuid = Uuid::parse_str(&guest_id).unwrap();
+    let owned = sqlx::query!(
+        "SELECT
+            (SELECT COUNT(*) FROM projects WHERE user_id = $1) AS projects,
+            (SELECT COUNT(*) FROM sessions WHERE user_id = $1) AS sessions,
+            (SELECT COUNT(*) FROM users WHERE user_id = $1 AND guest) AS guests",
+        guest_uuid
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(owned.projects, Some(2));
+    assert_eq!(owned.sessions, Some(1));
+    assert_eq!(owned.guests, Some(1));
+
+    let body = json!({ "challenge_token": challenge_token, "code": totp_code(&secret, 1) });
+    let response = app.post_json("/2fa/verify", &body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["userId"], alice_id);
+    let response = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", guest_id),
+            &guest_token,
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let access_token = body["access_token"].as_str().unwrap();
+    let projects = get_projects(&app, &alice_id, access_token).await;
+    assert_eq!(projects.as_array().unwrap().len(), 2);
+    let session = sqlx::query!("SELECT user_id FROM sessions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(session.user_id.to_string(), alice_id);
+    let guests = sqlx::query!("SELECT COUNT(*) AS count FROM users WHERE guest")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(guests.count, Some(0));
+}
+
+#[sqlx::test]
+async fn only_guests_can_be_upgraded(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    let response = upgrade(&app, &access_token, "grace@example.com").await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let (_, guest_token) = login_guest(&app).await;
+    let body = json!({ "id_token": id_token(&google_claims("grace@example.com")) });
+    let response = app
+        .request(
+            Method::POST,
+            "/identities/google",
+            &guest_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[sqlx::test]
+async fn inactive_guests_are_collected(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (stale, _) = login_guest(&app).await;
+    let (active, _) = login_guest(&app).await;
+    let (user, _) = app.login_user("ada@example.com").await;
+    sqlx::query!("UPDATE users SET created_at = NOW() - INTERVAL '40 days'",)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "UPDATE refresh_tokens SET last_used_at = NOW() - INTERVAL '40 days'
+         WHERE user_id != $1",
+        Uuid::parse_str(&active).unwrap()
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let outcome = run_job(
+        &app.job_context(),
+        Arc::new(CollectGuests { retention_days: 30 }),
+        Utc::now(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        outcome,
+        RunOutcome::Succeeded("deleted 1 guests".to_string())
+    );
+    let users: Vec<String> = sqlx::query!("SELECT user_id FROM users")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.user_id.to_string())
+        .collect();
+    assert!(!users.contains(&stale));
+    assert!(users.contains(&active));
+    assert!(users.contains(&user));
+}
@@ -0,0 +1,86 @@
+use kairos_server::migrate;
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Probes are called without a token
+async fn probe(app: &TestApp, path: &str) -> (u16, Value) {
+    let response = app
+        .client
+        .get(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .unwrap();
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[sqlx::test]
+async fn ready_when_database_and_schema_are_current(pool: PgPool) {
+    let app = spawn_app(pool.clone());
+
+    let (status, live) = probe(&app, "/livez").await;
+    assert_eq!(status, 200);
+    assert_eq!(live["status"], "ok");
+
+    let (status, ready) = probe(&app, "/readyz").await;
+    assert_eq!(status, 200);
+    assert_eq!(ready["status"], "ok");
+    assert_eq!(ready["components"]["database"]["status"], "ok");
+    assert!(ready["components"]["database"]["latency_ms"].is_u64());
+    let migrations = &ready["components"]["migrations"];
+    assert_eq!(migrations["status"], "ok");
+    let latest = migrate::status(&pool).await.unwrap().pop().unwrap();
+    assert_eq!(migrations["version"], latest.version);
+    assert_eq!(migrations["expected_version"], latest.version);
+}
+
+#[sqlx::test]
+async fn not_ready_while_a_migration_is_pending(pool: PgPool) {
+    let app = spawn_app(pool.clone());
+    let latest = migrate::status(&pool).await.unwrap().pop().unwrap();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+        .bind(latest.version)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (status, ready) = probe(&app, "/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(ready["status"], "unavailable");
+    assert_eq!(ready["components"]["database"]["status"], "ok");
+    let migrations = &ready["components"]["migrations"];
+    assert_eq!(migrations["status"], "unavailable");
+    assert_eq!(migrations["expected_version"], latest.version);
+    assert_ne!(migrations["version"], latest.version);
+    assert_eq!(migrations["error"], "not applied");
+}
+
+#[sqlx::test]
+async fn not_ready_without_a_database_but_still_live(pool: PgPool) {
+    let app = spawn_app(pool.clone());
+    pool.close().await;
+
+    let (status, ready) = probe(&app, "/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(ready["status"], "unavailable");
+    assert_eq!(ready["components"]["database"]["status"], "unavailable");
+    // No driver errors for anyone who can reach the probe
+    assert_eq!(ready["components"]["database"]["error"], "unreachable");
+    assert_eq!(ready["components"]["migrations"]["status"], "unavailable");
+
+    let (status, _) = probe(&app, "/livez").await;
+    assert_eq!(status, 200);
+}
+
+#[sqlx::test]
+async fn version_reports_the_build(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let (status, version) = probe(&app, "/version").await;
+    assert_eq!(status, 200);
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    let sha = version["git_sha"].as_str().unwrap();
+    assert!(!sha.is_empty());
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::TcpListener,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use actix_web::dev::ServerHandle;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, EncodingKey, Header};
+use kairos_server::{
+    jobs::JobContext,
+    keys::{AccessTokenKey, KeyRing},
+    mail::{Email, MailError, Mailer},
+    oauth::{GithubClient, GoogleVerifier, KeySource, Providers},
+    repo::Repos,
+    telemetry, Config, LogFormat, LoggingConfig,
+};
+use serde_json::{json, Value};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+
+pub const ACCESS_TOKEN_SECRET: &[u8] = b"test_access_secret";
+pub const REFRESH_TOKEN_SECRET: &str = "test_refresh_secret";
+pub const GOOGLE_CLIENT_ID: &str = "test-client-id.apps.googleusercontent.com";
+const ID_TOKEN_KEY: &[u8] = include_bytes!("fixtures/id_token_key.pem");
+pub const ID_TOKEN_JWKS: &str = include_str!("fixtures/id_token_jwks.json");
+
+pub struct TestApp {
+    pub address: String,
+    pub pool: PgPool,
+    pub client: reqwest::Client,
+    pub mailer: Arc<TestMailer>,
+    server: ServerHandle,
+}
+
+/// Keeps sent emails so tests can read the links out of them
+#[derive(Default)]
+pub struct TestMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+#[async_trait]
+impl Mailer for TestMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+}
+
+impl TestMailer {
+    /// Latest email sent to the address
+    pub fn last_to(&self, to: &str) -> Option<Email> {
+        let sent = self.sent.lock().unwrap();
+        sent.iter().rev().find(|email| email.to == to).cloned()
+    }
+
+    /// How many emails were sent to the address
+    pub fn sent_to(&self, to: &str) -> usize {
+        let sent = self.sent.lock().unwrap();
+        sent.iter().filter(|email| email.to == to).count()
+    }
+
+    /// Token from the `token=` link in the latest email sent to the address
+    pub fn token_for(&self, to: &str) -> String {
+        let email = self.last_to(to).expect("an email was sent");
+        let (_, rest) = email
+            .body
+            .split_once("token=")
+            .expect("email has a token link");
+        rest.split_whitespace().next().unwrap().to_string()
+    }
+}
+
+impl Drop for TestApp {
+    /// The workers run on their own threads, stop them so they release the test database
+    /// The stop command is sent right away, the returned future only waits for completion
+    fn drop(&mut self) {
+        drop(self.server.stop(false));
+    }
+}
+
+/// Providers that need no network, GitHub points at a closed port unless a test mocks it
+pub fn test_providers() -> Providers {
+    let jwks: JwkSet = serde_json::from_str(ID_TOKEN_JWKS).unwrap();
+    Providers {
+        google: GoogleVerifier::new(vec![GOOGLE_CLIENT_ID.to_string()], KeySource::Static(jwks)),
+        github: github_client("http://127.0.0.1:9"),
+        oidc: HashMap::new(),
+    }
+}
+
+pub fn github_client(base_url: &str) -> GithubClient {
+    GithubClient::new(
+        "test-github-client".to_string(),
+        "test-github-secret".to_string(),
+        format!("{}/login/oauth/access_token", base_url),
+        base_url.to_string(),
+    )
+}
+
+/// Defaults plus the secrets the environment would provide
+pub fn test_config() -> Config {
+    let mut config = Config::default();
+    config.tokens.refresh_secret = REFRESH_TOKEN_SECRET.to_string();
+    config
+}
+
+pub fn spawn_app(pool: PgPool) -> TestApp {
+    spawn_app_with_providers(pool, test_providers())
+}
+
+pub fn spawn_app_with_providers(pool: PgPool, providers: Providers) -> TestApp {
+    spawn_app_with(pool, providers, test_keys())
+}
+
+pub fn spawn_app_with_config(pool: PgPool, config: Config) -> TestApp {
+    let repos = Repos::postgres(pool.clone());
+    start_app(pool, repos, config, test_providers(), test_keys())
+}
+
+pub fn spawn_app_with(pool: PgPool, providers: Providers, keys: Arc<KeyRing>) -> TestApp {
+    let repos = Repos::postgres(pool.clone());
+    start_app(pool, repos, test_config(), providers, keys)
+}
+
+/// App on the in-memory repositories, its pool never connects so only the routes backed by the
+/// repositories work
+pub fn spawn_app_in_memory() -> TestApp {
+    let pool = PgPoolOptions::new().connect_lazy_with(PgConnectOptions::new());
+    start_app(
+        pool,
+        Repos::in_memory(),
+        test_config(),
+        test_providers(),
+        test_keys(),
+    )
+}
+
+fn test_keys() -> Arc<KeyRing> {
+    let key = AccessTokenKey::from_secret(None, ACCESS_TOKEN_SECRET);
+    Arc::new(KeyRing::single(key))
+}
+
+fn start_app(
+    pool: PgPool,
+    repos: Repos,
+    config: Config,
+    providers: Providers,
+    keys: Arc<KeyRing>,
+) -> TestApp {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let mailer = Arc::new(TestMailer::default());
+    let server = kairos_server::serve(
+        listener,
+        config,
+        pool.clone(),
+        repos,
+        providers,
+        keys,
+        mailer.clone(),
+    )
+    .expect("Failed to start");
+    let handle = server.handle();
+    tokio::spawn(server);
+
+    TestApp {
+        address: format!("http://127.0.0.1:{}", port),
+        pool,
+        client: reqwest::Client::new(),
+        mailer,
+        server: handle,
+    }
+}
+
+/// Claims of a valid Google ID token for `email`, tests override single fields to break it
+pub fn google_claims(email: &str) -> Value {
+    json!({
+        "iss": "https://accounts.google.com",
+        "aud": GOOGLE_CLIENT_ID,
+        "sub": format!("google-{}", email),
+        "email": email,
+        "email_verified": true,
+        "name": "Test User",
+        "picture": "https://example.com/picture.png",
+        "iat": Utc::now().timestamp(),
+        "exp": (Utc::now() + Duration::hours(1)).timestamp(),
+    })
+}
+
+/// Body of `/add_project`
+pub fn project(project_id: &str, user_id: &str, name: &str) -> Value {
+    json!({
+        "projectId": project_id,
+        "userId": user_id,
+        "projectName": name,
+        "colour": "red",
+        "deadline": null,
+        "priority": null
+    })
+}
+
+/// Body of `/add_session`, a running session
+pub fn session(session_id: &str, user_id: &str, project_id: &str) -> Value {
+    json!({
+        "sessionId": session_id,
+        "userId": user_id,
+        "projectId": project_id,
+        "startedAt": Utc::now(),
+        "endedAt": null,
+        "duration": 0
+    })
+}
+
+/// Sign the claims with the fixture key published in the test key set
+pub fn id_token(claims: &Value) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("test-key".to_string());
+    jsonwebtoken::encode(
+        &header,
+        claims,
+        &EncodingKey::from_rsa_pem(ID_TOKEN_KEY).unwrap(),
+    )
+    .unwrap()
+}
+
+impl TestApp {
+    /// What the background jobs get, sharing the app's database and mailer
+    pub fn job_context(&self) -> JobContext {
+        JobContext {
+            pool: self.pool.clone(),
+            mailer: self.mailer.clone(),
+        }
+    }
+
+    pub async fn post_json(&self, path: &str, body: &Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}{}", self.address, path))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Request authenticated with the given access token
+    pub async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        access_token: &str,
+        body: Option<&Value>,
+    ) -> reqwest::Response {
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.address, path))
+            .bearer_auth(access_token);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
+    pub async fn login_google(&self, claims: &Value) -> reqwest::Response {
+        self.post_json("/login/google", &json!({ "id_token": id_token(claims) }))
+            .await
+    }
+
+    /// Login a fresh google user and return the login response body
+    pub async fn login(&self, email: &str) -> Value {
+        let response = self.login_google(&google_claims(email)).await;
+        assert_eq!(response.status().as_u16(), 200);
+        response.json().await.unwrap()
+    }
+
+    /// Login a fresh google user and return its id and access token
+    pub async fn login_user(&self, email: &str) -> (String, String) {
+        let body = self.login(email).await;
+        (
+            body["user"]["userId"].as_str().unwrap().to_string(),
+            body["access_token"].as_str().unwrap().to_string(),
+        )
+    }
+}
+
+/// Log lines of every test in this binary, JSON formatted
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    /// Lines logged while handling the request with this id
+    pub fn for_request(&self, request_id: &str) -> Vec<Value> {
+        let logs = self.0.lock().unwrap();
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter(|line| line["span"]["request_id"] == request_id)
+            .collect()
+    }
+}
+
+/// Install the global log subscriber the first time
+pub fn captured_logs() -> &'static CapturedLogs {
+    static LOGS: OnceLock<CapturedLogs> = OnceLock::new();
+    LOGS.get_or_init(|| {
+        let logs = CapturedLogs::default();
+        let config = LoggingConfig {
+            format: LogFormat::Json,
+            filter: "info,sqlx::query=debug".to_string(),
+        };
+        let writer = logs.clone();
+        tracing::subscriber::set_global_default(telemetry::subscriber(&config, move || {
+            writer.clone()
+        }))
+        .expect("no other subscriber is installed");
+        logs
+    })
+}
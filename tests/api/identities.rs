@@ -0,0 +1,187 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{google_claims, id_token, spawn_app, TestApp};
+
+/// Google account with its own subject, independent of the email
+fn google_account(sub: &str, email: &str) -> Value {
+    let mut claims = google_claims(email);
+    claims["sub"] = json!(sub);
+    claims
+}
+
+async fn link_google(app: &TestApp, access_token: &str, claims: &Value) -> reqwest::Response {
+    let body = json!({ "id_token": id_token(claims) });
+    app.request(
+        Method::POST,
+        "/identities/google",
+        access_token,
+        Some(&body),
+    )
+    .await
+}
+
+async fn login_id(app: &TestApp, claims: &Value) -> String {
+    let response = app.login_google(claims).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    body["user"]["userId"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test]
+async fn login_follows_the_provider_subject(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let first = login_id(&app, &google_account("g-1", "ada@example.com")).await;
+    // The email changed at the provider, the account didn't
+    let second = login_id(&app, &google_account("g-1", "ada@new.example.com")).await;
+
+    assert_eq!(first, second);
+}
+
+#[sqlx::test]
+async fn first_login_links_the_account_with_that_email(pool: PgPool) {
+    let app = spawn_app(pool);
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (user_id, name, email, oauth_provider, user_type)
+         VALUES ($1, 'Ada', 'ada@example.com', 'google', 'free')",
+        user_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let logged_in = login_id(&app, &google_claims("ada@example.com")).await;
+
+    assert_eq!(logged_in, user_id.to_string());
+    let identities = sqlx::query!("SELECT user_id FROM user_identities")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].user_id, user_id);
+}
+
+#[sqlx::test]
+async fn linked_identity_logs_into_the_same_account(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+
+    let work = google_account("g-work", "ada@work.example.com");
+    let response = link_google(&app, &access_token, &work).await;
+    assert_eq!(response.status().as_u16(), 201);
+    // Linking again is a no-op
+    let response = link_google(&app, &access_token, &work).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(login_id(&app, &work).await, user_id);
+    let response = app
+        .request(Method::GET, "/identities", &access_token, None)
+        .await;
+    let identities: Value = response.json().await.unwrap();
+    assert_eq!(identities.as_array().unwrap().len(), 2);
+    assert_eq!(identities[1]["subject"], "g-work");
+    assert_eq!(identities[1]["email"], "ada@work.example.com");
+    let users = sqlx::query!("SELECT COUNT(*) AS count FROM users")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, Some(1));
+}
+
+#[sqlx::test]
+async fn identity_of_another_account_cannot_be_linked(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    app.login("grace@example.com").await;
+
+    let response = link_google(&app, &access_token, &google_claims("grace@example.com")).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let mut expired = google_account("g-new", "ada@work.example.com");
+    expired["exp"] = json!(0);
+    let response = link_google(&app, &access_token, &expired).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn unlinked_identity_no_longer_logs_in(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let work = google_account("g-work", "ada@work.example.com");
+    link_google(&app, &access_token, &work).await;
+
+    let response = app
+        .request(
+            Method::DELETE,
+            "/identities/google/g-work",
+            &access_token,
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .request(
+            Method::DELETE,
+            "/identities/google/g-work",
+            &access_token,
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    assert_ne!(login_id(&app, &work).await, user_id);
+}
+
+#[sqlx::test]
+async fn unlinked_identity_is_not_linked_again_by_email(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let work = google_account("g-work", "ada@work.example.com");
+    link_google(&app, &access_token, &work).await;
+    let response = app
+        .request(
+            Method::DELETE,
+            "/identities/google/google-ada@example.com",
+            &access_token,
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.login_google(&google_claims("ada@example.com")).await;
+    assert_eq!(response.status().as_u16(), 409);
+    let users = sqlx::query!("SELECT COUNT(*) AS count FROM users")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, Some(1));
+
+    // Linking it while signed in still works
+    let response = link_google(&app, &access_token, &google_claims("ada@example.com")).await;
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(
+        login_id(&app, &google_claims("ada@example.com")).await,
+        user_id
+    );
+}
+
+#[sqlx::test]
+async fn only_way_to_sign_in_cannot_be_unlinked(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+
+    let response = app
+        .request(
+            Method::DELETE,
+            "/identities/google/google-ada@example.com",
+            &access_token,
+            None,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
@@ -0,0 +1,378 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use kairos_server::jobs::{
+    run_job, Job, JobContext, JobError, PurgeExpiredTokens, RunOutcome, Schedule, Scheduler,
+    SendDigests, StopRunawaySessions,
+};
+use reqwest::Method;
+use serde_json::json;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::helpers::{session, spawn_app, TestApp};
+
+fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+}
+
+fn next(schedule: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+    schedule
+        .parse::<Schedule>()
+        .unwrap()
+        .next_after(after)
+        .unwrap()
+}
+
+/// Sleeps, then succeeds or fails as told
+struct TestJob {
+    sleep: Duration,
+    fail: bool,
+}
+
+#[async_trait]
+impl Job for TestJob {
+    fn name(&self) -> &'static str {
+        "test_job"
+    }
+
+    async fn run(&self, _: &JobContext) -> Result<String, JobError> {
+        tokio::time::sleep(self.sleep).await;
+        if self.fail {
+            Err(JobError("out of coffee".to_string()))
+        } else {
+            Ok("done".to_string())
+        }
+    }
+}
+
+fn test_job(sleep_ms: u64) -> Arc<dyn Job> {
+    Arc::new(TestJob {
+        sleep: Duration::from_millis(sleep_ms),
+        fail: false,
+    })
+}
+
+async fn runs(pool: &PgPool) -> Vec<(String, Option<String>)> {
+    sqlx::query!("SELECT status, detail FROM job_runs ORDER BY started_at")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.status, row.detail))
+        .collect()
+}
+
+/// Log in and start a session on the default project, returns the user and session ids
+async fn start_session(app: &TestApp, email: &str) -> (String, String) {
+    let login = app.login(email).await;
+    let user_id = login["user"]["userId"].as_str().unwrap().to_string();
+    let access_token = login["access_token"].as_str().unwrap();
+    let projects: serde_json::Value = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", user_id),
+            access_token,
+            None,
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    let session_id = Uuid::new_v4().to_string();
+    let body = session(
+        &session_id,
+        &user_id,
+        projects[0]["projectId"].as_str().unwrap(),
+    );
+    let response = app
+        .request(Method::POST, "/add_session", access_token, Some(&body))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    (user_id, session_id)
+}
+
+#[test]
+fn schedules_follow_cron() {
+    // A Wednesday
+    let now = at(2026, 10, 14, 10, 7);
+    assert_eq!(next("*/15 * * * *", now), at(2026, 10, 14, 10, 15));
+    assert_eq!(next("@hourly", now), at(2026, 10, 14, 11, 0));
+    assert_eq!(next("0 8 * * 1", now), at(2026, 10, 19, 8, 0));
+    assert_eq!(next("30 7 * * 1-5", now), at(2026, 10, 15, 7, 30));
+    assert_eq!(next("0 0 1 1 *", now), at(2027, 1, 1, 0, 0));
+    assert_eq!(next("0 12 29 2 *", now), at(2028, 2, 29, 12, 0));
+    // Both days restricted, either matches
+    assert_eq!(next("0 0 13 * 5", now), at(2026, 10, 16, 0, 0));
+    // Sunday can be 7
+    assert_eq!(next("0 0 * * 7", now), at(2026, 10, 18, 0, 0));
+    // Strictly after
+    assert_eq!(
+        next("7 10 * * *", at(2026, 10, 14, 10, 7)),
+        at(2026, 10, 15, 10, 7)
+    );
+
+    for invalid in [
+        "* * * *",
+        "60 * * * *",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "0 0 31 2 *",
+    ] {
+        assert!(invalid.parse::<Schedule>().is_err(), "{}", invalid);
+    }
+}
+
+#[sqlx::test]
+async fn each_scheduled_time_runs_once(pool: PgPool) {
+    let app = spawn_app(pool);
+    let ctx = app.job_context();
+    let scheduled_for = at(2026, 10, 14, 10, 0);
+
+    let first = run_job(&ctx, test_job(0), scheduled_for).await.unwrap();
+    let again = run_job(&ctx, test_job(0), scheduled_for).await.unwrap();
+    let later = run_job(&ctx, test_job(0), at(2026, 10, 14, 11, 0))
+        .await
+        .unwrap();
+
+    assert_eq!(first, RunOutcome::Succeeded("done".to_string()));
+    assert_eq!(again, RunOutcome::Skipped);
+    assert_eq!(later, RunOutcome::Succeeded("done".to_string()));
+    let succeeded = ("succeeded".to_string(), Some("done".to_string()));
+    assert_eq!(runs(&app.pool).await, [succeeded.clone(), succeeded]);
+}
+
+#[sqlx::test]
+async fn a_running_job_is_not_started_twice(pool: PgPool) {
+    let app = spawn_app(pool);
+    let ctx = app.job_context();
+
+    // Two replicas with clocks a minute apart
+    let (first, second) = tokio::join!(
+        run_job(&ctx, test_job(500), at(2026, 10, 14, 10, 0)),
+        async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            run_job(&ctx, test_job(500), at(2026, 10, 14, 10, 1)).await
+        }
+    );
+
+    assert_eq!(first.unwrap(), RunOutcome::Succeeded("done".to_string()));
+    assert_eq!(second.unwrap(), RunOutcome::Skipped);
+    assert_eq!(runs(&app.pool).await.len(), 1);
+}
+
+#[sqlx::test]
+async fn the_lock_does_not_take_a_connection_of_the_pool(pool: PgPool) {
+    let app = spawn_app(pool);
+    let single = PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(Duration::from_secs(2))
+        .connect_with((*app.pool.connect_options()).clone())
+        .await
+        .unwrap();
+    let ctx = JobContext {
+        pool: single,
+        ..app.job_context()
+    };
+    let job = Arc::new(PurgeExpiredTokens { keep_runs_days: 30 });
+
+    let outcome = run_job(&ctx, job, Utc::now()).await.unwrap();
+
+    assert!(matches!(outcome, RunOutcome::Succeeded(_)));
+}
+
+#[sqlx::test]
+async fn failures_are_recorded(pool: PgPool) {
+    let app = spawn_app(pool);
+    let failing = Arc::new(TestJob {
+        sleep: Duration::ZERO,
+        fail: true,
+    });
+
+    let outcome = run_job(&app.job_context(), failing, Utc::now())
+        .await
+        .unwrap();
+
+    assert_eq!(outcome, RunOutcome::Failed("out of coffee".to_string()));
+    assert_eq!(
+        runs(&app.pool).await,
+        [("failed".to_string(), Some("out of coffee".to_string()))]
+    );
+    let finished = sqlx::query!("SELECT finished_at FROM job_runs")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(finished.finished_at.is_some());
+}
+
+#[sqlx::test]
+async fn expired_tokens_are_purged(pool: PgPool) {
+    let app = spawn_app(pool);
+    let expired = app.login("ada@example.com").await;
+    let current = app.login("ada@example.com").await;
+    // The first login's token
+    sqlx::query!(
+        "UPDATE refresh_tokens SET expires_at = NOW() - INTERVAL '1 day'
+         WHERE token_id = (SELECT token_id FROM refresh_tokens ORDER BY created_at LIMIT 1)"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let job = Arc::new(PurgeExpiredTokens { keep_runs_days: 30 });
+    let outcome = run_job(&app.job_context(), job, Utc::now()).await.unwrap();
+
+    assert_eq!(
+        outcome,
+        RunOutcome::Succeeded("deleted 1 tokens and 0 job runs".to_string())
+    );
+    for (login, status) in [(expired, 401), (current, 200)] {
+        let body = json!({ "refresh_token": login["refresh_token"] });
+        let response = app.post_json("/token/refresh", &body).await;
+        assert_eq!(response.status().as_u16(), status);
+    }
+}
+
+#[sqlx::test]
+async fn runaway_sessions_are_stopped_at_the_limit(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, forgotten) = start_session(&app, "ada@example.com").await;
+    let (_, running) = start_session(&app, "grace@example.com").await;
+    sqlx::query!(
+        "UPDATE sessions SET started_at = NOW() - INTERVAL '10 hours' WHERE session_id = $1",
+        Uuid::parse_str(&forgotten).unwrap()
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let job = Arc::new(StopRunawaySessions { max_hours: 6 });
+    let outcome = run_job(&app.job_context(), job, Utc::now()).await.unwrap();
+
+    assert_eq!(
+        outcome,
+        RunOutcome::Succeeded("stopped 1 sessions".to_string())
+    );
+    let sessions = sqlx::query!(
+        r#"SELECT session_id, duration, ended_at - started_at AS "length" FROM sessions"#
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    for session in sessions {
+        if session.session_id.to_string() == forgotten {
+            assert_eq!(session.duration, 6 * 60 * 60);
+            assert_eq!(
+                session.length.unwrap().microseconds,
+                6 * 60 * 60 * 1_000_000
+            );
+        } else {
+            assert_eq!(session.session_id.to_string(), running);
+            assert!(session.length.is_none());
+        }
+    }
+}
+
+#[sqlx::test]
+async fn digests_go_to_users_who_asked_for_them(pool: PgPool) {
+    let app = spawn_app(pool);
+    let mut users = Vec::new();
+    for email in [
+        "ada@example.com",
+        "grace@example.com",
+        "quiet@example.com",
+        "unverified@example.com",
+    ] {
+        let (user_id, _) = start_session(&app, email).await;
+        users.push(Uuid::parse_str(&user_id).unwrap());
+    }
+    app.login("idle@example.com").await;
+    sqlx::query!(
+        "UPDATE sessions SET ended_at = NOW(), duration = 5400 WHERE user_id <> $1",
+        users[1]
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    // Everyone but quiet asked for digests, a password account nobody verified doesn't get one
+    sqlx::query!(
+        "UPDATE users SET weekly_digest = TRUE WHERE user_id <> $1",
+        users[2]
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE users SET password_hash = 'hash', email_verified_at = NULL WHERE user_id = $1",
+        users[3]
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let outcome = run_job(&app.job_context(), Arc::new(SendDigests), Utc::now())
+        .await
+        .unwrap();
+
+    assert_eq!(outcome, RunOutcome::Succeeded("sent 1 digests".to_string()));
+    let digest = app.mailer.last_to("ada@example.com").unwrap();
+    assert!(digest.body.contains("1h 30m"), "{}", digest.body);
+    // Grace's session is still running
+    for email in [
+        "grace@example.com",
+        "quiet@example.com",
+        "unverified@example.com",
+        "idle@example.com",
+    ] {
+        assert!(app.mailer.last_to(email).is_none(), "{}", email);
+    }
+}
+
+#[sqlx::test]
+async fn weekly_digests_are_a_setting(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("ada@example.com").await;
+    let get = || async {
+        let response = app
+            .request(Method::GET, "/settings", &access_token, None)
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+        response.json::<serde_json::Value>().await.unwrap()
+    };
+
+    assert_eq!(get().await, json!({ "weeklyDigest": false }));
+    let response = app
+        .request(
+            Method::POST,
+            "/settings",
+            &access_token,
+            Some(&json!({ "weeklyDigest": true })),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(get().await, json!({ "weeklyDigest": true }));
+}
+
+#[sqlx::test]
+async fn scheduler_stops_when_asked(pool: PgPool) {
+    let app = spawn_app(pool);
+    let scheduler = Scheduler::new(app.job_context()).add(
+        "* * * * *".parse().unwrap(),
+        TestJob {
+            sleep: Duration::ZERO,
+            fail: false,
+        },
+    );
+    let (shutdown, shutdown_requested) = watch::channel(false);
+    let running = tokio::spawn(scheduler.run(shutdown_requested, Duration::from_secs(5)));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.send_replace(true);
+
+    tokio::time::timeout(Duration::from_secs(1), running)
+        .await
+        .expect("the scheduler stops right away")
+        .unwrap();
+}
@@ -0,0 +1,149 @@
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use std::sync::Arc;
+
+use kairos_server::keys::{AccessTokenKey, KeyRing};
+use reqwest::Method;
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::helpers::{spawn_app_with, test_providers, TestApp};
+
+const RSA_KEY: &[u8] = include_bytes!("fixtures/access_rsa.pem");
+const RSA_PUBLIC_KEY: &[u8] = include_bytes!("fixtures/access_rsa.pub.pem");
+const ED25519_KEY: &[u8] = include_bytes!("fixtures/access_ed25519.pem");
+const ED25519_PUBLIC_KEY: &[u8] = include_bytes!("fixtures/access_ed25519.pub.pem");
+
+fn ring(key: AccessTokenKey) -> Arc<KeyRing> {
+    Arc::new(KeyRing::single(key))
+}
+
+pub async fn get_jwks(app: &TestApp) -> JwkSet {
+    let response = app
+        .client
+        .get(format!("{}/.well-known/jwks.json", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+/// Verify an access token the way another service would, using only the published key set
+async fn verify_offline(app: &TestApp, access_token: &str, algorithm: Algorithm) -> Value {
+    let header = jsonwebtoken::decode_header(access_token).unwrap();
+    assert_eq!(header.alg, algorithm);
+    let kid = header.kid.expect("access token has a kid");
+
+    let jwks = get_jwks(app).await;
+    let jwk = jwks.find(&kid).expect("kid is published in the JWKS");
+    let key = DecodingKey::from_jwk(jwk).unwrap();
+
+    jsonwebtoken::decode::<Value>(access_token, &key, &Validation::new(algorithm))
+        .unwrap()
+        .claims
+}
+
+async fn assert_signs_and_publishes(
+    pool: PgPool,
+    access_key: AccessTokenKey,
+    algorithm: Algorithm,
+) {
+    let app = spawn_app_with(pool, test_providers(), ring(access_key));
+    let (user_id, access_token) = app.login_user("alice@example.com").await;
+
+    let claims = verify_offline(&app, &access_token, algorithm).await;
+    assert_eq!(claims["sub"], user_id.as_str());
+
+    let response = app
+        .request(Method::GET, "/health_check", &access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn rs256_access_tokens_verify_against_jwks(pool: PgPool) {
+    let access_key =
+        AccessTokenKey::from_pem(Algorithm::RS256, None, Some(RSA_KEY), RSA_PUBLIC_KEY).unwrap();
+    assert_signs_and_publishes(pool, access_key, Algorithm::RS256).await;
+}
+
+#[sqlx::test]
+async fn eddsa_access_tokens_verify_against_jwks(pool: PgPool) {
+    let access_key = AccessTokenKey::from_pem(
+        Algorithm::EdDSA,
+        Some("ed-2026".to_string()),
+        Some(ED25519_KEY),
+        ED25519_PUBLIC_KEY,
+    )
+    .unwrap();
+    assert_signs_and_publishes(pool, access_key, Algorithm::EdDSA).await;
+}
+
+#[sqlx::test]
+async fn configured_kid_is_used(pool: PgPool) {
+    let access_key = AccessTokenKey::from_pem(
+        Algorithm::EdDSA,
+        Some("ed-2026".to_string()),
+        Some(ED25519_KEY),
+        ED25519_PUBLIC_KEY,
+    )
+    .unwrap();
+    let app = spawn_app_with(pool, test_providers(), ring(access_key));
+    let (_, access_token) = app.login_user("alice@example.com").await;
+
+    let header = jsonwebtoken::decode_header(&access_token).unwrap();
+    assert_eq!(header.kid.as_deref(), Some("ed-2026"));
+}
+
+#[test]
+fn mismatched_key_pair_is_rejected() {
+    assert!(
+        AccessTokenKey::from_pem(Algorithm::RS256, None, Some(RSA_KEY), ED25519_PUBLIC_KEY)
+            .is_err()
+    );
+    assert!(
+        AccessTokenKey::from_pem(Algorithm::EdDSA, None, Some(ED25519_KEY), RSA_PUBLIC_KEY)
+            .is_err()
+    );
+}
+
+#[sqlx::test]
+async fn shared_secret_publishes_no_keys(pool: PgPool) {
+    let app = spawn_app_with(
+        pool,
+        test_providers(),
+        ring(AccessTokenKey::from_secret(None, b"test_access_secret")),
+    );
+    assert!(get_jwks(&app).await.keys.is_empty());
+}
+
+#[sqlx::test]
+async fn token_signed_with_another_key_is_rejected(pool: PgPool) {
+    let app = spawn_app_with(
+        pool,
+        test_providers(),
+        ring(
+            AccessTokenKey::from_pem(Algorithm::RS256, None, Some(RSA_KEY), RSA_PUBLIC_KEY)
+                .unwrap(),
+        ),
+    );
+    let (_, access_token) = app.login_user("alice@example.com").await;
+
+    let other = spawn_app_with(
+        app.pool.clone(),
+        test_providers(),
+        ring(
+            AccessTokenKey::from_pem(
+                Algorithm::EdDSA,
+                None,
+                Some(ED25519_KEY),
+                ED25519_PUBLIC_KEY,
+            )
+            .unwrap(),
+        ),
+    );
+    let response = other
+        .request(Method::GET, "/health_check", &access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
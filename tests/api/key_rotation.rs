@@ -0,0 +1,184 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use chrono::{Duration, Utc};
+use kairos_server::keys::KeyRing;
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    helpers::{spawn_app_with, test_providers, TestApp},
+    jwks::get_jwks,
+};
+
+/// Directory holding a key ring manifest and the fixture keys it points at
+struct KeyDir(PathBuf);
+
+impl KeyDir {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("kairos-keys-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in [
+            "access_rsa.pem",
+            "access_rsa.pub.pem",
+            "access_ed25519.pem",
+            "access_ed25519.pub.pem",
+        ] {
+            let fixture = format!("{}/tests/api/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file);
+            fs::copy(fixture, dir.join(file)).unwrap();
+        }
+        Self(dir)
+    }
+
+    fn manifest(&self) -> PathBuf {
+        self.0.join("keys.json")
+    }
+
+    fn write(&self, manifest: &Value) {
+        fs::write(self.manifest(), manifest.to_string()).unwrap();
+    }
+}
+
+impl Drop for KeyDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn rsa_key(private: bool) -> Value {
+    let mut key = json!({
+        "kid": "rsa-1",
+        "algorithm": "RS256",
+        "public_key": "access_rsa.pub.pem",
+    });
+    if private {
+        key["private_key"] = json!("access_rsa.pem");
+    }
+    key
+}
+
+fn ed_key() -> Value {
+    json!({
+        "kid": "ed-2",
+        "algorithm": "EdDSA",
+        "private_key": "access_ed25519.pem",
+        "public_key": "access_ed25519.pub.pem",
+    })
+}
+
+/// Rotate from the RSA key to the Ed25519 one, keeping the RSA key until `retires_at`
+fn rotated(retires_at: chrono::DateTime<Utc>) -> Value {
+    let mut old = rsa_key(false);
+    old["retires_at"] = json!(retires_at);
+    json!({ "active": "ed-2", "keys": [ed_key(), old] })
+}
+
+fn spawn(pool: PgPool, dir: &KeyDir) -> (TestApp, Arc<KeyRing>) {
+    let keys = Arc::new(KeyRing::load(dir.manifest()).unwrap());
+    (spawn_app_with(pool, test_providers(), keys.clone()), keys)
+}
+
+async fn health_check(app: &TestApp, access_token: &str) -> u16 {
+    app.request(Method::GET, "/health_check", access_token, None)
+        .await
+        .status()
+        .as_u16()
+}
+
+fn kid(access_token: &str) -> String {
+    jsonwebtoken::decode_header(access_token)
+        .unwrap()
+        .kid
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn rotation_keeps_issued_tokens_valid(pool: PgPool) {
+    let dir = KeyDir::new();
+    dir.write(&json!({ "active": "rsa-1", "keys": [rsa_key(true)] }));
+    let (app, keys) = spawn(pool, &dir);
+
+    let (_, old_token) = app.login_user("alice@example.com").await;
+    assert_eq!(kid(&old_token), "rsa-1");
+
+    dir.write(&rotated(Utc::now() + Duration::hours(1)));
+    keys.reload().unwrap();
+
+    assert_eq!(health_check(&app, &old_token).await, 200);
+
+    let (_, new_token) = app.login_user("bob@example.com").await;
+    assert_eq!(kid(&new_token), "ed-2");
+    assert_eq!(health_check(&app, &new_token).await, 200);
+
+    let jwks = get_jwks(&app).await;
+    assert!(jwks.find("rsa-1").is_some());
+    assert!(jwks.find("ed-2").is_some());
+}
+
+#[sqlx::test]
+async fn retired_key_no_longer_verifies(pool: PgPool) {
+    let dir = KeyDir::new();
+    dir.write(&json!({ "active": "rsa-1", "keys": [rsa_key(true)] }));
+    let (app, keys) = spawn(pool, &dir);
+    let (_, old_token) = app.login_user("alice@example.com").await;
+
+    dir.write(&rotated(Utc::now() - Duration::minutes(1)));
+    keys.reload().unwrap();
+
+    assert_eq!(health_check(&app, &old_token).await, 401);
+    assert!(get_jwks(&app).await.find("rsa-1").is_none());
+}
+
+#[sqlx::test]
+async fn failed_reload_keeps_current_keys(pool: PgPool) {
+    let dir = KeyDir::new();
+    dir.write(&json!({ "active": "rsa-1", "keys": [rsa_key(true)] }));
+    let (app, keys) = spawn(pool, &dir);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+
+    dir.write(&json!({ "active": "missing", "keys": [rsa_key(true)] }));
+    assert!(keys.reload().is_err());
+
+    assert_eq!(health_check(&app, &access_token).await, 200);
+    let (_, access_token) = app.login_user("bob@example.com").await;
+    assert_eq!(kid(&access_token), "rsa-1");
+}
+
+#[test]
+fn invalid_manifests_are_rejected() {
+    let dir = KeyDir::new();
+
+    // The active key has to be able to sign
+    dir.write(&json!({ "active": "rsa-1", "keys": [rsa_key(false)] }));
+    assert!(KeyRing::load(dir.manifest()).is_err());
+
+    dir.write(&json!({ "active": "ed-2", "keys": [ed_key(), ed_key()] }));
+    assert!(KeyRing::load(dir.manifest()).is_err());
+
+    let mut retiring = ed_key();
+    retiring["retires_at"] = json!(Utc::now());
+    dir.write(&json!({ "active": "ed-2", "keys": [retiring] }));
+    assert!(KeyRing::load(dir.manifest()).is_err());
+
+    dir.write(&json!({ "active": "hs-1", "keys": [{ "kid": "hs-1", "algorithm": "HS256" }] }));
+    assert!(KeyRing::load(dir.manifest()).is_err());
+}
+
+#[sqlx::test]
+async fn shared_secrets_rotate_by_kid(pool: PgPool) {
+    let dir = KeyDir::new();
+    let first = json!({ "kid": "hs-1", "algorithm": "HS256", "secret": "first secret" });
+    dir.write(&json!({ "active": "hs-1", "keys": [first] }));
+    let (app, keys) = spawn(pool, &dir);
+    let (_, old_token) = app.login_user("alice@example.com").await;
+
+    let second = json!({ "kid": "hs-2", "algorithm": "HS256", "secret": "second secret" });
+    dir.write(&json!({ "active": "hs-2", "keys": [second, first] }));
+    keys.reload().unwrap();
+
+    assert_eq!(health_check(&app, &old_token).await, 200);
+    let (_, new_token) = app.login_user("bob@example.com").await;
+    assert_eq!(kid(&new_token), "hs-2");
+    assert!(get_jwks(&app).await.keys.is_empty());
+}
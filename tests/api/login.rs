@@ -0,0 +1,128 @@
+use std::net::TcpListener;
+
+use chrono::{Duration, Utc};
+use kairos_server::oauth::{GoogleVerifier, KeySource};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::helpers::{
+    google_claims, id_token, spawn_app, spawn_app_with_providers, test_providers, GOOGLE_CLIENT_ID,
+};
+
+#[sqlx::test]
+async fn google_login_creates_user_with_default_project(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let body = app.login("ada@example.com").await;
+
+    assert_eq!(body["user"]["email"], "ada@example.com");
+    assert!(body["access_token"].is_string());
+    assert!(body["refresh_token"].is_string());
+    let projects = sqlx::query!("SELECT project_name FROM projects")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(projects.len(), 1);
+    assert_eq!(projects[0].project_name, "Unset");
+}
+
+#[sqlx::test]
+async fn google_login_twice_returns_the_same_user(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let first = app.login("ada@example.com").await;
+    let second = app.login("ada@example.com").await;
+
+    assert_eq!(first["user"]["userId"], second["user"]["userId"]);
+    let tokens = sqlx::query!("SELECT COUNT(*) AS count FROM refresh_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, Some(2));
+}
+
+#[sqlx::test]
+async fn google_login_rejects_invalid_id_tokens(pool: PgPool) {
+    let app = spawn_app(pool);
+    let expired = (Utc::now() - Duration::hours(2)).timestamp();
+
+    let cases = [
+        ("aud", json!("someone-elses-app"), "wrong audience"),
+        ("iss", json!("https://evil.example.com"), "wrong issuer"),
+        ("exp", json!(expired), "expired"),
+        ("email_verified", json!(false), "unverified email"),
+    ];
+    for (claim, value, reason) in cases {
+        let mut claims = google_claims("ada@example.com");
+        claims[claim] = value;
+
+        let response = app.login_google(&claims).await;
+
+        assert_eq!(response.status().as_u16(), 401, "{}", reason);
+    }
+}
+
+#[sqlx::test]
+async fn google_login_rejects_tampered_and_unsigned_tokens(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    // Swap the payload of a valid token for one claiming another account
+    let valid = id_token(&google_claims("ada@example.com"));
+    let forged = id_token(&google_claims("eve@example.com"));
+    let parts: Vec<&str> = valid.split('.').collect();
+    let forged_parts: Vec<&str> = forged.split('.').collect();
+    let tampered = format!("{}.{}.{}", parts[0], forged_parts[1], parts[2]);
+    // alg "none" with the same claims
+    let unsigned = format!(
+        "eyJhbGciOiJub25lIiwia2lkIjoidGVzdC1rZXkifQ.{}.",
+        forged_parts[1]
+    );
+
+    for token in [tampered, unsigned, "not-a-jwt".to_string()] {
+        let response = app
+            .post_json("/login/google", &json!({ "id_token": token }))
+            .await;
+
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let users = sqlx::query!("SELECT COUNT(*) AS count FROM users")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(users.count, Some(0));
+}
+
+#[sqlx::test]
+async fn login_no_longer_accepts_client_supplied_user_details(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let response = app
+        .post_json(
+            "/login/google",
+            &json!({
+                "sub": "123",
+                "name": "Ada",
+                "email": "ada@example.com",
+                "picture": "https://example.com/picture.png"
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[sqlx::test]
+async fn google_keys_that_never_arrive_answer_unavailable(pool: PgPool) {
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/certs", listener.local_addr().unwrap());
+    let mut providers = test_providers();
+    providers.google =
+        GoogleVerifier::new(vec![GOOGLE_CLIENT_ID.to_string()], KeySource::Remote(url));
+    let app = spawn_app_with_providers(pool, providers);
+
+    let response = app.login_google(&google_claims("ada@example.com")).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    drop(listener);
+}
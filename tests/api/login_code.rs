@@ -0,0 +1,185 @@
+use kairos_server::mail::{Email, FileMailer, Mailer};
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn request_code(app: &TestApp, email: &str) -> String {
+    let response = app
+        .post_json("/login_code", &json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let email = app
+        .mailer
+        .last_to(&email.to_lowercase())
+        .expect("login code was sent");
+    let (_, rest) = email.body.split_once("login code is ").unwrap();
+    rest[..6].to_string()
+}
+
+async fn login_with_code(app: &TestApp, email: &str, code: &str) -> reqwest::Response {
+    app.post_json(
+        "/login_code/verify",
+        &json!({ "email": email, "code": code }),
+    )
+    .await
+}
+
+#[sqlx::test]
+async fn login_code_creates_account_with_default_project(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let code = request_code(&app, "Alice@Example.com").await;
+    let response = login_with_code(&app, "alice@example.com", &code).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let login: Value = response.json().await.unwrap();
+    assert_eq!(login["user"]["email"], "alice@example.com");
+
+    let user_id = login["user"]["userId"].as_str().unwrap();
+    let response = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", user_id),
+            login["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    let projects: Value = response.json().await.unwrap();
+    assert_eq!(projects[0]["projectName"], "Unset");
+
+    // Same account next time, and the code only works once
+    let response = login_with_code(&app, "alice@example.com", &code).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let code = request_code(&app, "alice@example.com").await;
+    let response = login_with_code(&app, "alice@example.com", &code).await;
+    let again: Value = response.json().await.unwrap();
+    assert_eq!(again["user"]["userId"], login["user"]["userId"]);
+}
+
+#[sqlx::test]
+async fn login_link_is_single_use(pool: PgPool) {
+    let app = spawn_app(pool);
+    request_code(&app, "alice@example.com").await;
+    let token = app.mailer.token_for("alice@example.com");
+
+    let response = app
+        .post_json("/login_code/verify", &json!({ "token": token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .post_json("/login_code/verify", &json!({ "token": token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn code_is_locked_after_too_many_wrong_guesses(pool: PgPool) {
+    let app = spawn_app(pool);
+    let code = request_code(&app, "alice@example.com").await;
+    let wrong = if code == "000000" { "000001" } else { "000000" };
+
+    for _ in 0..5 {
+        let response = login_with_code(&app, "alice@example.com", wrong).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = login_with_code(&app, "alice@example.com", &code).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // No fresh code to guess at until the locked one expires
+    let response = app
+        .post_json("/login_code", &json!({ "email": "alice@example.com" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    let response = login_with_code(&app, "alice@example.com", &code).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    sqlx::query!("UPDATE login_codes SET expires_at = NOW() - INTERVAL '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let code = request_code(&app, "alice@example.com").await;
+    let response = login_with_code(&app, "alice@example.com", &code).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn login_codes_per_email_are_limited_per_hour(pool: PgPool) {
+    let app = spawn_app(pool);
+    for _ in 0..5 {
+        request_code(&app, "alice@example.com").await;
+    }
+
+    let response = app
+        .post_json("/login_code", &json!({ "email": "alice@example.com" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(
+        app.mailer.sent_to("alice@example.com"),
+        5,
+        "no mail for refused requests"
+    );
+    // Other emails have their own limit
+    request_code(&app, "bob@example.com").await;
+
+    sqlx::query!("UPDATE login_code_requests SET requested_at = NOW() - INTERVAL '61 minutes'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    request_code(&app, "alice@example.com").await;
+}
+
+#[sqlx::test]
+async fn only_the_latest_unexpired_code_works(pool: PgPool) {
+    let app = spawn_app(pool);
+    let first = request_code(&app, "alice@example.com").await;
+    let second = request_code(&app, "alice@example.com").await;
+    if first != second {
+        let response = login_with_code(&app, "alice@example.com", &first).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    sqlx::query!("UPDATE login_codes SET expires_at = NOW() - INTERVAL '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = login_with_code(&app, "alice@example.com", &second).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn code_is_required(pool: PgPool) {
+    let app = spawn_app(pool);
+    let response = app
+        .post_json(
+            "/login_code/verify",
+            &json!({ "email": "alice@example.com" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn file_mailer_writes_emails_to_disk() {
+    let dir = std::env::temp_dir().join(format!("kairos-mail-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mailer = FileMailer::new(&dir, "Kairos <noreply@example.com>").unwrap();
+
+    mailer
+        .send(Email {
+            to: "alice@example.com".to_string(),
+            subject: "Your Kairos login code".to_string(),
+            body: "Your login code is 123456".to_string(),
+        })
+        .await
+        .unwrap();
+
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+    assert!(contents.contains("To: alice@example.com"));
+    assert!(contents.contains("Your login code is 123456"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,117 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn refresh(app: &TestApp, login: &Value) -> u16 {
+    app.post_json(
+        "/token/refresh",
+        &json!({ "refresh_token": login["refresh_token"] }),
+    )
+    .await
+    .status()
+    .as_u16()
+}
+
+async fn health_check(app: &TestApp, login: &Value) -> u16 {
+    app.request(
+        Method::GET,
+        "/health_check",
+        login["access_token"].as_str().unwrap(),
+        None,
+    )
+    .await
+    .status()
+    .as_u16()
+}
+
+#[sqlx::test]
+async fn logout_revokes_only_the_presented_login(pool: PgPool) {
+    let app = spawn_app(pool);
+    let laptop = app.login("ada@example.com").await;
+    let phone = app.login("ada@example.com").await;
+
+    let response = app
+        .post_json(
+            "/logout",
+            &json!({ "refresh_token": laptop["refresh_token"] }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    // Access token is dead before its expiry
+    assert_eq!(health_check(&app, &laptop).await, 401);
+    assert_eq!(refresh(&app, &laptop).await, 401);
+    assert_eq!(health_check(&app, &phone).await, 200);
+    assert_eq!(refresh(&app, &phone).await, 200);
+}
+
+#[sqlx::test]
+async fn logout_rejects_invalid_refresh_tokens(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = app.login("ada@example.com").await;
+
+    let response = app
+        .post_json(
+            "/logout",
+            &json!({ "refresh_token": login["access_token"] }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(health_check(&app, &login).await, 200);
+}
+
+#[sqlx::test]
+async fn logout_all_revokes_every_login_of_the_user(pool: PgPool) {
+    let app = spawn_app(pool);
+    let laptop = app.login("ada@example.com").await;
+    let phone = app.login("ada@example.com").await;
+    let other_user = app.login("bob@example.com").await;
+
+    let response = app
+        .request(
+            Method::POST,
+            "/logout_all",
+            laptop["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    for login in [&laptop, &phone] {
+        assert_eq!(health_check(&app, login).await, 401);
+        assert_eq!(refresh(&app, login).await, 401);
+    }
+    assert_eq!(health_check(&app, &other_user).await, 200);
+    assert_eq!(refresh(&app, &other_user).await, 200);
+}
+
+#[sqlx::test]
+async fn logout_all_requires_an_access_token(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let response = app.post_json("/logout_all", &json!({})).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn refresh_token_reuse_also_kills_the_access_tokens(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = app.login("ada@example.com").await;
+    let rotated: Value = app
+        .post_json(
+            "/token/refresh",
+            &json!({ "refresh_token": login["refresh_token"] }),
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(refresh(&app, &login).await, 401);
+
+    assert_eq!(health_check(&app, &rotated).await, 401);
+}
@@ -0,0 +1,26 @@
+mod access_tokens;
+mod config;
+mod database_outage;
+mod devices;
+mod errors;
+mod github;
+mod guests;
+mod health;
+mod helpers;
+mod identities;
+mod jobs;
+mod jwks;
+mod key_rotation;
+mod login;
+mod login_code;
+mod logout;
+mod metrics;
+mod migrations;
+mod oidc;
+mod ownership;
+mod passkeys;
+mod password;
+mod repos;
+mod request_tracing;
+mod token;
+mod two_factor;
@@ -0,0 +1,155 @@
+use log::LevelFilter;
+use reqwest::Method;
+use serde_json::json;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use uuid::Uuid;
+
+use crate::helpers::{captured_logs, session, spawn_app, TestApp};
+
+async fn scrape(app: &TestApp) -> String {
+    let response = app
+        .client
+        .get(format!("{}/metrics", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    response.text().await.unwrap()
+}
+
+/// Value of a series, e.g. `logins_total{provider="google"}`
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| {
+        line.strip_prefix(series)
+            .and_then(|value| value.strip_prefix(' '))
+            .map(|value| value.parse().unwrap())
+    })
+}
+
+#[sqlx::test]
+async fn requests_are_counted_per_route(pool: sqlx::PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    for _ in 0..2 {
+        app.request(
+            Method::GET,
+            &format!("/get_projects/{}", user_id),
+            &access_token,
+            None,
+        )
+        .await;
+    }
+    app.request(Method::GET, "/wp-login.php", &access_token, None)
+        .await;
+    let brew = Method::from_bytes(b"BREW").unwrap();
+    app.request(brew, "/health_check", &access_token, None)
+        .await;
+
+    let metrics = scrape(&app).await;
+    let projects = r#"method="GET",route="/get_projects/{user_id}""#;
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!("http_requests_total{{{},status=\"200\"}}", projects)
+        ),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!("http_request_duration_seconds_count{{{}}}", projects)
+        ),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",route="unmatched",status="404"}"#
+        ),
+        Some(1.0)
+    );
+    assert!(!metrics.contains("wp-login"));
+    assert!(metrics.contains(r#"http_requests_total{method="other",route="#));
+    assert!(!metrics.contains("BREW"));
+}
+
+#[sqlx::test]
+async fn domain_and_pool_metrics(pool: sqlx::PgPool) {
+    let app = spawn_app(pool);
+    let login = app.login("ada@example.com").await;
+    let user_id = login["user"]["userId"].as_str().unwrap();
+    let access_token = login["access_token"].as_str().unwrap();
+
+    let projects: serde_json::Value = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", user_id),
+            access_token,
+            None,
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    let project_id = projects[0]["projectId"].as_str().unwrap();
+    let running = session(&Uuid::new_v4().to_string(), user_id, project_id);
+    let response = app
+        .request(Method::POST, "/add_session", access_token, Some(&running))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let refresh = json!({ "refresh_token": login["refresh_token"] });
+    assert_eq!(
+        app.post_json("/token/refresh", &refresh)
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(
+        app.post_json("/token/refresh", &refresh)
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+
+    let metrics = scrape(&app).await;
+    assert_eq!(sample(&metrics, "running_sessions"), Some(1.0));
+    assert_eq!(
+        sample(&metrics, r#"logins_total{provider="google"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, r#"token_refreshes_total{result="rotated"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"token_refreshes_total{result="reuse_detected"}"#
+        ),
+        Some(1.0)
+    );
+    assert!(sample(&metrics, "db_pool_max_connections").unwrap() >= 1.0);
+    assert!(sample(&metrics, r#"db_pool_connections{state="idle"}"#).is_some());
+    assert!(sample(&metrics, r#"db_pool_connections{state="in_use"}"#).is_some());
+}
+
+#[sqlx::test]
+async fn pool_acquire_times_are_recorded(
+    pool_options: PgPoolOptions,
+    connect_options: PgConnectOptions,
+) {
+    captured_logs();
+    let pool = pool_options
+        .acquire_time_level(LevelFilter::Debug)
+        .connect_with(connect_options)
+        .await
+        .unwrap();
+    let app = spawn_app(pool);
+    app.login("ada@example.com").await;
+
+    let metrics = scrape(&app).await;
+    assert!(sample(&metrics, "db_pool_acquire_seconds_count").unwrap() > 0.0);
+}
@@ -0,0 +1,69 @@
+use kairos_server::migrate::{self, MigrationState, SchemaError};
+use sqlx::PgPool;
+
+#[sqlx::test(migrations = false)]
+async fn fresh_database_is_behind_until_migrated(pool: PgPool) {
+    let status = migrate::status(&pool).await.unwrap();
+    assert!(!status.is_empty());
+    assert!(status.iter().all(|m| m.state == MigrationState::Pending));
+    match migrate::check_schema(&pool).await {
+        Err(SchemaError::Behind(pending)) => assert_eq!(pending.len(), status.len()),
+        other => panic!("expected the schema to be behind, got {:?}", other),
+    }
+
+    migrate::up(&pool).await.unwrap();
+
+    let status = migrate::status(&pool).await.unwrap();
+    assert!(status.iter().all(|m| m.state == MigrationState::Applied));
+    migrate::check_schema(&pool).await.unwrap();
+    // Nothing left to do the second time
+    migrate::up(&pool).await.unwrap();
+}
+
+#[sqlx::test(migrations = false)]
+async fn missing_and_changed_migrations_block_startup(pool: PgPool) {
+    migrate::up(&pool).await.unwrap();
+    let latest = migrate::status(&pool).await.unwrap().pop().unwrap();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+        .bind(latest.version)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let first = migrate::status(&pool).await.unwrap()[0].version;
+    sqlx::query("UPDATE _sqlx_migrations SET checksum = '\\x00' WHERE version = $1")
+        .bind(first)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let Err(SchemaError::Behind(behind)) = migrate::check_schema(&pool).await else {
+        panic!("expected the schema to be behind");
+    };
+    let states: Vec<_> = behind.iter().map(|m| (m.version, m.state)).collect();
+    assert_eq!(
+        states,
+        [
+            (first, MigrationState::Modified),
+            (latest.version, MigrationState::Pending)
+        ]
+    );
+}
+
+#[sqlx::test(migrations = false)]
+async fn versions_from_a_newer_binary_are_tolerated(pool: PgPool) {
+    migrate::up(&pool).await.unwrap();
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99990101000000, 'from the future', TRUE, '\\x00', 0)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let status = migrate::status(&pool).await.unwrap();
+    let newest = status.last().unwrap();
+    assert_eq!(newest.version, 99990101000000);
+    assert_eq!(newest.state, MigrationState::Unknown);
+    migrate::check_schema(&pool).await.unwrap();
+    migrate::up(&pool).await.unwrap();
+}
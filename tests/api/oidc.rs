@@ -0,0 +1,320 @@
+use std::net::TcpListener;
+
+use actix_web::{dev::ServerHandle, web, App, HttpResponse, HttpServer};
+use chrono::{Duration, Utc};
+use kairos_server::oauth::OidcProvider;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::{id_token, spawn_app_with_providers, test_providers, TestApp, ID_TOKEN_JWKS};
+
+const CLIENT_ID: &str = "kairos-test";
+const GOOD_CODE: &str = "good-code";
+
+/// Local OpenID Connect issuer, signs ID tokens with the fixture key
+struct StubIssuer {
+    address: String,
+    server: ServerHandle,
+}
+
+impl Drop for StubIssuer {
+    fn drop(&mut self) {
+        drop(self.server.stop(false));
+    }
+}
+
+#[derive(Clone, Default)]
+struct Overrides {
+    /// Replaces claims of the issued ID token
+    claims: Value,
+    /// Issuer advertised by the discovery document
+    discovery_issuer: Option<String>,
+}
+
+fn spawn_stub_issuer(overrides: Overrides) -> StubIssuer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let state = web::Data::new((address.clone(), overrides));
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .route(
+                "/.well-known/openid-configuration",
+                web::get().to(|state: web::Data<(String, Overrides)>| async move {
+                    let (address, overrides) = state.get_ref();
+                    HttpResponse::Ok().json(json!({
+                        "issuer": overrides.discovery_issuer.clone().unwrap_or(address.clone()),
+                        "authorization_endpoint": format!("{}/auth", address),
+                        "token_endpoint": format!("{}/token", address),
+                        "jwks_uri": format!("{}/jwks", address),
+                    }))
+                }),
+            )
+            .route(
+                "/jwks",
+                web::get().to(|| async {
+                    HttpResponse::Ok()
+                        .content_type("application/json")
+                        .body(ID_TOKEN_JWKS)
+                }),
+            )
+            .route(
+                "/token",
+                web::post().to(
+                    |state: web::Data<(String, Overrides)>,
+                     form: web::Form<Vec<(String, String)>>| async move {
+                        let param = |key: &str| {
+                            form.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+                        };
+                        if param("code") != Some(GOOD_CODE) || param("client_id") != Some(CLIENT_ID)
+                        {
+                            return HttpResponse::BadRequest()
+                                .json(json!({ "error": "invalid_grant" }));
+                        }
+                        let (address, overrides) = state.get_ref();
+                        let mut claims = json!({
+                            "iss": address,
+                            "aud": CLIENT_ID,
+                            "sub": "f:1234:jdoe",
+                            "email": "jdoe@example.com",
+                            "email_verified": true,
+                            "preferred_username": "jdoe",
+                            "iat": Utc::now().timestamp(),
+                            "exp": (Utc::now() + Duration::minutes(5)).timestamp(),
+                        });
+                        if let Some(replacements) = overrides.claims.as_object() {
+                            for (claim, value) in replacements {
+                                claims[claim] = value.clone();
+                            }
+                        }
+                        HttpResponse::Ok().json(json!({
+                            "access_token": "opaque",
+                            "token_type": "Bearer",
+                            "id_token": id_token(&claims),
+                        }))
+                    },
+                ),
+            )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    let handle = server.handle();
+    tokio::spawn(server);
+
+    StubIssuer {
+        address,
+        server: handle,
+    }
+}
+
+fn spawn_app_with_issuer(pool: PgPool, issuer: &StubIssuer) -> TestApp {
+    let mut providers = test_providers();
+    providers.oidc.insert(
+        "keycloak".to_string(),
+        OidcProvider::new(
+            issuer.address.clone(),
+            CLIENT_ID.to_string(),
+            "test-secret".to_string(),
+            vec!["openid".to_string(), "email".to_string()],
+        ),
+    );
+    spawn_app_with_providers(pool, providers)
+}
+
+#[sqlx::test]
+async fn oidc_login_creates_user_with_default_project(pool: PgPool) {
+    let issuer = spawn_stub_issuer(Overrides::default());
+    let app = spawn_app_with_issuer(pool, &issuer);
+
+    let response = app
+        .post_json(
+            "/login/keycloak",
+            &json!({ "code": GOOD_CODE, "redirect_uri": "kairos://callback" }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["email"], "jdoe@example.com");
+    assert_eq!(body["user"]["name"], "jdoe");
+    assert_eq!(body["user"]["oauthProvider"], "keycloak");
+    let projects = sqlx::query!("SELECT project_name FROM projects")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(projects.len(), 1);
+    assert_eq!(projects[0].project_name, "Unset");
+}
+
+#[sqlx::test]
+async fn oidc_login_rejects_invalid_id_tokens(pool: PgPool) {
+    let cases = [
+        json!({ "aud": "another-client" }),
+        json!({ "iss": "https://evil.example.com" }),
+        json!({ "exp": (Utc::now() - Duration::hours(1)).timestamp() }),
+        json!({ "email_verified": false }),
+    ];
+
+    for claims in cases {
+        let issuer = spawn_stub_issuer(Overrides {
+            claims: claims.clone(),
+            discovery_issuer: None,
+        });
+        let app = spawn_app_with_issuer(pool.clone(), &issuer);
+
+        let response = app
+            .post_json("/login/keycloak", &json!({ "code": GOOD_CODE }))
+            .await;
+
+        assert_eq!(response.status().as_u16(), 401, "{}", claims);
+    }
+}
+
+#[sqlx::test]
+async fn oidc_login_rejects_bad_codes(pool: PgPool) {
+    let issuer = spawn_stub_issuer(Overrides::default());
+    let app = spawn_app_with_issuer(pool, &issuer);
+
+    let response = app
+        .post_json("/login/keycloak", &json!({ "code": "replayed-code" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn oidc_login_refuses_discovery_for_another_issuer(pool: PgPool) {
+    let issuer = spawn_stub_issuer(Overrides {
+        claims: Value::Null,
+        discovery_issuer: Some("https://evil.example.com".to_string()),
+    });
+    let app = spawn_app_with_issuer(pool, &issuer);
+
+    let response = app
+        .post_json("/login/keycloak", &json!({ "code": GOOD_CODE }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 502);
+}
+
+#[sqlx::test]
+async fn unknown_providers_are_not_found(pool: PgPool) {
+    let issuer = spawn_stub_issuer(Overrides::default());
+    let app = spawn_app_with_issuer(pool, &issuer);
+
+    let login = app
+        .post_json("/login/authentik", &json!({ "code": GOOD_CODE }))
+        .await;
+    let authorize = app
+        .client
+        .get(format!(
+            "{}/login/authentik/authorize?redirect_uri=kairos://callback",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(login.status().as_u16(), 404);
+    assert_eq!(authorize.status().as_u16(), 404);
+}
+
+#[sqlx::test]
+async fn authorize_returns_the_provider_authorization_url(pool: PgPool) {
+    let issuer = spawn_stub_issuer(Overrides::default());
+    let app = spawn_app_with_issuer(pool, &issuer);
+
+    let response = app
+        .client
+        .get(format!(
+            "{}/login/keycloak/authorize?redirect_uri=kairos://callback&state=xyz",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    let url = reqwest::Url::parse(body["authorization_url"].as_str().unwrap()).unwrap();
+    assert_eq!(
+        url.as_str().split('?').next(),
+        Some(format!("{}/auth", issuer.address).as_str())
+    );
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    for expected in [
+        ("response_type", "code"),
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", "kairos://callback"),
+        ("scope", "openid email"),
+        ("state", "xyz"),
+    ] {
+        assert!(
+            params.contains(&(expected.0.to_string(), expected.1.to_string())),
+            "{:?}",
+            expected
+        );
+    }
+}
+
+#[sqlx::test]
+async fn authorize_passes_the_pkce_challenge_through(pool: PgPool) {
+    let issuer = spawn_stub_issuer(Overrides::default());
+    let app = spawn_app_with_issuer(pool, &issuer);
+    let authorize = |challenge: &str| {
+        app.client
+            .get(format!(
+                "{}/login/keycloak/authorize?redirect_uri=kairos://callback&code_challenge={}",
+                app.address, challenge
+            ))
+            .send()
+    };
+
+    let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    let response = authorize(challenge).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    let url = reqwest::Url::parse(body["authorization_url"].as_str().unwrap()).unwrap();
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    for expected in [
+        ("code_challenge", challenge),
+        ("code_challenge_method", "S256"),
+    ] {
+        assert!(
+            params.contains(&(expected.0.to_string(), expected.1.to_string())),
+            "{:?}",
+            expected
+        );
+    }
+
+    // A plain verifier instead of its S256 challenge
+    let response = authorize("verifier").await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[sqlx::test]
+async fn issuer_that_never_answers_is_unavailable(pool: PgPool) {
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut providers = test_providers();
+    providers.oidc.insert(
+        "keycloak".to_string(),
+        OidcProvider::new(
+            format!("http://{}", listener.local_addr().unwrap()),
+            CLIENT_ID.to_string(),
+            "test-secret".to_string(),
+            vec!["openid".to_string()],
+        ),
+    );
+    let app = spawn_app_with_providers(pool, providers);
+
+    let response = app
+        .post_json("/login/keycloak", &json!({ "code": GOOD_CODE }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    drop(listener);
+}
@@ -0,0 +1,203 @@
+use reqwest::Method;
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{project, session, spawn_app, TestApp};
+
+struct Users {
+    alice: (String, String),
+    bob: (String, String),
+    bob_project: String,
+    bob_spare_project: String,
+    bob_session: String,
+}
+
+/// Alice and Bob, Bob owns a project with one running session and a spare empty project
+async fn setup(app: &TestApp) -> Users {
+    let alice = app.login_user("alice@example.com").await;
+    let bob = app.login_user("bob@example.com").await;
+    let projects: Value = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", bob.0),
+            &bob.1,
+            None,
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    let bob_project = projects[0]["projectId"].as_str().unwrap().to_string();
+    let bob_session = Uuid::new_v4().to_string();
+    let response = app
+        .request(
+            Method::POST,
+            "/add_session",
+            &bob.1,
+            Some(&session(&bob_session, &bob.0, &bob_project)),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let bob_spare_project = Uuid::new_v4().to_string();
+    let response = app
+        .request(
+            Method::POST,
+            "/add_project",
+            &bob.1,
+            Some(&project(&bob_spare_project, &bob.0, "Spare")),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Users {
+        alice,
+        bob,
+        bob_project,
+        bob_spare_project,
+        bob_session,
+    }
+}
+
+/// Every route that takes a user id, pointed at Bob's data
+fn foreign_requests(users: &Users) -> Vec<(Method, String, Option<Value>)> {
+    let bob = &users.bob.0;
+    vec![
+        (Method::GET, format!("/get_projects/{}", bob), None),
+        (Method::GET, format!("/get_sessions/{}", bob), None),
+        (Method::GET, format!("/check_active_session/{}", bob), None),
+        (Method::GET, format!("/get_todays_focus_time/{}", bob), None),
+        (
+            Method::POST,
+            "/add_project".to_string(),
+            Some(project(&Uuid::new_v4().to_string(), bob, "Mine now")),
+        ),
+        (
+            Method::POST,
+            "/update_project".to_string(),
+            Some(project(&users.bob_project, bob, "Renamed")),
+        ),
+        (
+            Method::DELETE,
+            "/delete_project".to_string(),
+            Some(project(&users.bob_spare_project, bob, "Spare")),
+        ),
+        (
+            Method::POST,
+            "/add_session".to_string(),
+            Some(session(
+                &Uuid::new_v4().to_string(),
+                bob,
+                &users.bob_project,
+            )),
+        ),
+        (
+            Method::POST,
+            "/update_session".to_string(),
+            Some(session(&users.bob_session, bob, &users.bob_project)),
+        ),
+    ]
+}
+
+#[sqlx::test]
+async fn routes_reject_other_users_ids_with_forbidden(pool: PgPool) {
+    let app = spawn_app(pool);
+    let users = setup(&app).await;
+
+    for (method, path, body) in foreign_requests(&users) {
+        let response = app
+            .request(method.clone(), &path, &users.alice.1, body.as_ref())
+            .await;
+
+        assert_eq!(response.status().as_u16(), 403, "{} {}", method, path);
+    }
+    let projects = sqlx::query!(
+        "SELECT project_name FROM projects WHERE user_id = $1 ORDER BY project_name DESC",
+        Uuid::parse_str(&users.bob.0).unwrap()
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    let names: Vec<&str> = projects.iter().map(|p| p.project_name.as_str()).collect();
+    assert_eq!(names, ["Unset", "Spare"]);
+}
+
+#[sqlx::test]
+async fn routes_allow_the_owner(pool: PgPool) {
+    let app = spawn_app(pool);
+    let users = setup(&app).await;
+
+    for (method, path, body) in foreign_requests(&users) {
+        let response = app
+            .request(method.clone(), &path, &users.bob.1, body.as_ref())
+            .await;
+
+        assert_eq!(response.status().as_u16(), 200, "{} {}", method, path);
+    }
+}
+
+#[sqlx::test]
+async fn own_user_id_cannot_reach_other_users_rows(pool: PgPool) {
+    let app = spawn_app(pool);
+    let users = setup(&app).await;
+    let alice = &users.alice.0;
+
+    // Alice's own id in the body but Bob's project or session id
+    let cases = [
+        (
+            Method::POST,
+            "/update_project",
+            project(&users.bob_project, alice, "Renamed"),
+            404,
+        ),
+        (
+            Method::DELETE,
+            "/delete_project",
+            project(&users.bob_spare_project, alice, "Spare"),
+            404,
+        ),
+        (
+            Method::POST,
+            "/add_session",
+            session(&Uuid::new_v4().to_string(), alice, &users.bob_project),
+            403,
+        ),
+        (
+            Method::POST,
+            "/update_session",
+            session(&users.bob_session, alice, &users.bob_project),
+            404,
+        ),
+    ];
+    for (method, path, body, status) in cases {
+        let response = app
+            .request(method.clone(), path, &users.alice.1, Some(&body))
+            .await;
+
+        assert_eq!(response.status().as_u16(), status, "{} {}", method, path);
+    }
+}
+
+#[sqlx::test]
+async fn routes_require_an_access_token(pool: PgPool) {
+    let app = spawn_app(pool);
+    let users = setup(&app).await;
+    let mut requests = foreign_requests(&users);
+    requests.push((Method::GET, "/health_check".to_string(), None));
+
+    for (method, path, body) in requests {
+        let mut request = app
+            .client
+            .request(method.clone(), format!("{}{}", app.address, path));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401, "{} {}", method, path);
+    }
+    let response = app
+        .request(Method::GET, "/health_check", &users.alice.1, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,444 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value as Cbor;
+use reqwest::Method;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::helpers::{spawn_app, TestApp};
+
+const ORIGIN: &str = "http://localhost:6080";
+
+/// A P-256 authenticator in memory, like a security key without the hardware
+struct SoftAuthenticator {
+    key: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+    origin: String,
+}
+
+impl SoftAuthenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        SoftAuthenticator {
+            key,
+            credential_id: rand::random::<[u8; 16]>().to_vec(),
+            sign_count: 0,
+            origin: ORIGIN.to_string(),
+        }
+    }
+
+    fn credential_id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.credential_id)
+    }
+
+    fn client_data(&self, ty: &str, challenge: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": ty,
+            "challenge": challenge,
+            "origin": self.origin,
+        }))
+        .unwrap()
+    }
+
+    fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+        let mut data = Sha256::digest(b"localhost").to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    /// COSE encoding of the public key, as the authenticator reports it
+    fn cose_key(&self) -> Cbor {
+        let point = self.key.public_key().as_ref();
+        Cbor::Map(vec![
+            (Cbor::from(1), Cbor::from(2)),
+            (Cbor::from(3), Cbor::from(-7)),
+            (Cbor::from(-1), Cbor::from(1)),
+            (Cbor::from(-2), Cbor::Bytes(point[1..33].to_vec())),
+            (Cbor::from(-3), Cbor::Bytes(point[33..].to_vec())),
+        ])
+    }
+
+    /// Answer to `navigator.credentials.create()`
+    fn create(&self, options: &Value) -> Value {
+        self.create_with_key(options, &self.cose_key())
+    }
+
+    /// Answer reporting `cose_key` as the credential's public key
+    fn create_with_key(&self, options: &Value, cose_key: &Cbor) -> Value {
+        let challenge = options["publicKey"]["challenge"].as_str().unwrap();
+        let client_data = self.client_data("webauthn.create", challenge);
+
+        // user present, user verified, attested credential data
+        let mut auth_data = self.authenticator_data(0x45);
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        ciborium::into_writer(cose_key, &mut auth_data).unwrap();
+
+        let attestation = Cbor::Map(vec![
+            (Cbor::from("fmt"), Cbor::from("none")),
+            (Cbor::from("attStmt"), Cbor::Map(vec![])),
+            (Cbor::from("authData"), Cbor::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        json!({
+            "challenge_id": options["challenge_id"],
+            "name": "Laptop",
+            "credential": {
+                "id": self.credential_id(),
+                "response": {
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                    "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+                },
+            },
+        })
+    }
+
+    /// Answer to `navigator.credentials.get()`, counting up like a real authenticator
+    fn get(&mut self, options: &Value, flags: u8) -> Value {
+        self.sign_count += 1;
+        let challenge = options["publicKey"]["challenge"].as_str().unwrap();
+        let client_data = self.client_data("webauthn.get", challenge);
+        let auth_data = self.authenticator_data(flags);
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        let signature = self.key.sign(&SystemRandom::new(), &signed).unwrap();
+
+        json!({
+            "challenge_id": options["challenge_id"],
+            "credential": {
+                "id": self.credential_id(),
+                "response": {
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                    "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                    "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                },
+            },
+        })
+    }
+}
+
+async fn register(app: &TestApp, access_token: &str, authenticator: &SoftAuthenticator) -> Value {
+    let response = app
+        .request(Method::POST, "/passkeys/register/start", access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let options: Value = response.json().await.unwrap();
+    assert_eq!(options["publicKey"]["rp"]["id"], "localhost");
+
+    let body = authenticator.create(&options);
+    let response = app
+        .request(
+            Method::POST,
+            "/passkeys/register/finish",
+            access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+async fn start_login(app: &TestApp, email: &str) -> Value {
+    let response = app
+        .post_json("/passkeys/login/start", &json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+async fn finish_login(app: &TestApp, body: &Value) -> reqwest::Response {
+    app.post_json("/passkeys/login/finish", body).await
+}
+
+#[sqlx::test]
+async fn registered_passkey_logs_in(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("alice@example.com").await;
+    let mut authenticator = SoftAuthenticator::new();
+    let passkey = register(&app, &access_token, &authenticator).await;
+    assert_eq!(passkey["name"], "Laptop");
+
+    let options = start_login(&app, "alice@example.com").await;
+    assert_eq!(
+        options["publicKey"]["allowCredentials"][0]["id"],
+        authenticator.credential_id()
+    );
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["userId"], user_id);
+    let access_token = body["access_token"].as_str().unwrap();
+    assert!(body["refresh_token"].is_string());
+
+    let response = app
+        .request(Method::GET, "/passkeys", access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let passkeys: Value = response.json().await.unwrap();
+    assert_eq!(passkeys.as_array().unwrap().len(), 1);
+    assert_eq!(passkeys[0]["passkeyId"], passkey["passkeyId"]);
+    assert!(passkeys[0]["lastUsedAt"].is_string());
+
+    // Discoverable credentials log in without an email
+    let response = app.post_json("/passkeys/login/start", &json!({})).await;
+    let options: Value = response.json().await.unwrap();
+    assert_eq!(options["publicKey"]["allowCredentials"], json!([]));
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn same_credential_cannot_register_twice(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let authenticator = SoftAuthenticator::new();
+    register(&app, &access_token, &authenticator).await;
+
+    let response = app
+        .request(
+            Method::POST,
+            "/passkeys/register/start",
+            &access_token,
+            None,
+        )
+        .await;
+    let options: Value = response.json().await.unwrap();
+    assert_eq!(
+        options["publicKey"]["excludeCredentials"][0]["id"],
+        authenticator.credential_id()
+    );
+    let body = authenticator.create(&options);
+    let response = app
+        .request(
+            Method::POST,
+            "/passkeys/register/finish",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[sqlx::test]
+async fn registration_challenge_is_bound_to_the_user(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, alice_token) = app.login_user("alice@example.com").await;
+    let (_, bob_token) = app.login_user("bob@example.com").await;
+
+    let response = app
+        .request(Method::POST, "/passkeys/register/start", &alice_token, None)
+        .await;
+    let options: Value = response.json().await.unwrap();
+    let body = SoftAuthenticator::new().create(&options);
+    let response = app
+        .request(
+            Method::POST,
+            "/passkeys/register/finish",
+            &bob_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[sqlx::test]
+async fn keys_not_matching_their_algorithm_are_rejected(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let authenticator = SoftAuthenticator::new();
+    let point = authenticator.key.public_key().as_ref();
+    let (x, y) = (point[1..33].to_vec(), point[33..].to_vec());
+    let key = |kty: i64, alg: i64, crv: i64, x: &[u8], y: Option<&[u8]>| {
+        let mut entries = vec![
+            (Cbor::from(1), Cbor::from(kty)),
+            (Cbor::from(3), Cbor::from(alg)),
+            (Cbor::from(-1), Cbor::from(crv)),
+            (Cbor::from(-2), Cbor::Bytes(x.to_vec())),
+        ];
+        if let Some(y) = y {
+            entries.push((Cbor::from(-3), Cbor::Bytes(y.to_vec())));
+        }
+        Cbor::Map(entries)
+    };
+
+    let mut off_curve = y.clone();
+    off_curve[31] ^= 1;
+    for (case, cose_key) in [
+        ("ES256 as OKP", key(1, -7, 1, &x, Some(&y))),
+        ("ES256 on P-384", key(2, -7, 2, &x, Some(&y))),
+        ("EdDSA as EC2", key(2, -8, 6, &x, None)),
+        ("EdDSA on X25519", key(1, -8, 4, &x, None)),
+        ("point off the curve", key(2, -7, 1, &x, Some(&off_curve))),
+    ] {
+        let response = app
+            .request(
+                Method::POST,
+                "/passkeys/register/start",
+                &access_token,
+                None,
+            )
+            .await;
+        let options: Value = response.json().await.unwrap();
+        let body = authenticator.create_with_key(&options, &cose_key);
+        let response = app
+            .request(
+                Method::POST,
+                "/passkeys/register/finish",
+                &access_token,
+                Some(&body),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 400, "{}", case);
+        let error: Value = response.json().await.unwrap();
+        let message = error["message"].as_str().unwrap();
+        assert!(message.contains("key"), "{}: {}", case, message);
+    }
+    let response = app
+        .request(Method::GET, "/passkeys", &access_token, None)
+        .await;
+    let passkeys: Value = response.json().await.unwrap();
+    assert_eq!(passkeys.as_array().unwrap().len(), 0);
+}
+
+#[sqlx::test]
+async fn wrong_origin_is_rejected(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let mut authenticator = SoftAuthenticator::new();
+    register(&app, &access_token, &authenticator).await;
+
+    authenticator.origin = "https://evil.example.com".to_string();
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn signature_from_another_key_is_rejected(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let authenticator = SoftAuthenticator::new();
+    register(&app, &access_token, &authenticator).await;
+
+    let mut impostor = SoftAuthenticator::new();
+    impostor.credential_id = authenticator.credential_id.clone();
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &impostor.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn challenge_cannot_be_reused(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let mut authenticator = SoftAuthenticator::new();
+    register(&app, &access_token, &authenticator).await;
+
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn sign_counter_must_increase(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let mut authenticator = SoftAuthenticator::new();
+    register(&app, &access_token, &authenticator).await;
+
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A clone of the authenticator replays the same counter
+    authenticator.sign_count -= 1;
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn deleted_passkey_cannot_log_in(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let mut authenticator = SoftAuthenticator::new();
+    let passkey = register(&app, &access_token, &authenticator).await;
+
+    let path = format!("/passkeys/{}", passkey["passkeyId"].as_str().unwrap());
+    let (_, bob_token) = app.login_user("bob@example.com").await;
+    let response = app.request(Method::DELETE, &path, &bob_token, None).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app
+        .request(Method::DELETE, &path, &access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let options = start_login(&app, "alice@example.com").await;
+    assert_eq!(options["publicKey"]["allowCredentials"], json!([]));
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn passkey_without_user_verification_still_asks_for_totp(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let mut authenticator = SoftAuthenticator::new();
+    register(&app, &access_token, &authenticator).await;
+    let response = app
+        .request(Method::POST, "/2fa/totp/enroll", &access_token, None)
+        .await;
+    let secret = response.json::<Value>().await.unwrap()["secret"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let secret = totp_rs::Secret::Encoded(secret).to_bytes().unwrap();
+    let totp = totp_rs::TOTP::new(
+        totp_rs::Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        None,
+        String::new(),
+    )
+    .unwrap();
+    let body = json!({ "code": totp.generate_current().unwrap() });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/confirm",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // User present only
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &authenticator.get(&options, 0x01)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["two_factor_required"], true);
+
+    // User verified counts as the second factor
+    let options = start_login(&app, "alice@example.com").await;
+    let response = finish_login(&app, &authenticator.get(&options, 0x05)).await;
+    let body: Value = response.json().await.unwrap();
+    assert!(body["access_token"].is_string());
+}
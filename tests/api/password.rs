@@ -0,0 +1,291 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::{google_claims, spawn_app, TestApp};
+
+const PASSWORD: &str = "correct horse battery";
+
+async fn register(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    app.post_json(
+        "/register",
+        &json!({ "name": "Alice", "email": email, "password": password }),
+    )
+    .await
+}
+
+async fn password_login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    app.post_json(
+        "/password/login",
+        &json!({ "email": email, "password": password }),
+    )
+    .await
+}
+
+async fn verify(app: &TestApp, email: &str) -> u16 {
+    let token = app.mailer.token_for(email);
+    app.post_json("/register/verify", &json!({ "token": token }))
+        .await
+        .status()
+        .as_u16()
+}
+
+async fn refresh(app: &TestApp, login: &Value) -> u16 {
+    app.post_json(
+        "/token/refresh",
+        &json!({ "refresh_token": login["refresh_token"] }),
+    )
+    .await
+    .status()
+    .as_u16()
+}
+
+/// Register and verify an account, returns the login response body
+async fn registered_user(app: &TestApp, email: &str) -> Value {
+    assert_eq!(register(app, email, PASSWORD).await.status().as_u16(), 201);
+    assert_eq!(verify(app, email).await, 200);
+    let response = password_login(app, email, PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[sqlx::test]
+async fn registered_user_logs_in_after_verifying_email(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let response = register(&app, "Alice@Example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let user: Value = response.json().await.unwrap();
+    assert_eq!(user["email"], "alice@example.com");
+    assert!(user["oauthProvider"].is_null());
+
+    let response = password_login(&app, "alice@example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    assert_eq!(verify(&app, "alice@example.com").await, 200);
+    let response = password_login(&app, "alice@example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let login: Value = response.json().await.unwrap();
+    assert_eq!(login["user"]["userId"], user["userId"]);
+
+    let user_id = user["userId"].as_str().unwrap();
+    let access_token = login["access_token"].as_str().unwrap();
+    let response = app
+        .request(
+            Method::GET,
+            &format!("/get_projects/{}", user_id),
+            access_token,
+            None,
+        )
+        .await;
+    let projects: Value = response.json().await.unwrap();
+    assert_eq!(projects.as_array().unwrap().len(), 1);
+    assert_eq!(projects[0]["projectName"], "Unset");
+}
+
+#[sqlx::test]
+async fn verification_token_is_single_use(pool: PgPool) {
+    let app = spawn_app(pool);
+    register(&app, "alice@example.com", PASSWORD).await;
+
+    assert_eq!(verify(&app, "alice@example.com").await, 200);
+    assert_eq!(verify(&app, "alice@example.com").await, 400);
+}
+
+#[sqlx::test]
+async fn invalid_registrations_are_rejected(pool: PgPool) {
+    let app = spawn_app(pool);
+
+    let response = register(&app, "alice@example.com", "short").await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = register(&app, "not-an-email", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    register(&app, "alice@example.com", PASSWORD).await;
+    let response = register(&app, "ALICE@example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[sqlx::test]
+async fn wrong_password_and_unknown_email_fail_alike(pool: PgPool) {
+    let app = spawn_app(pool);
+    registered_user(&app, "alice@example.com").await;
+    // Google accounts have no password
+    app.login("bob@example.com").await;
+
+    for (email, password) in [
+        ("alice@example.com", "wrong password"),
+        ("nobody@example.com", PASSWORD),
+        ("bob@example.com", PASSWORD),
+    ] {
+        let response = password_login(&app, email, password).await;
+        assert_eq!(response.status().as_u16(), 401);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["message"], "Invalid email or password");
+    }
+}
+
+#[sqlx::test]
+async fn change_password_requires_current_password(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = registered_user(&app, "alice@example.com").await;
+    let access_token = login["access_token"].as_str().unwrap();
+
+    let body = json!({ "current_password": "wrong password", "new_password": "new password 123" });
+    let response = app
+        .request(Method::POST, "/password/change", access_token, Some(&body))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let body = json!({ "current_password": PASSWORD, "new_password": "new password 123" });
+    let response = app
+        .request(Method::POST, "/password/change", access_token, Some(&body))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = password_login(&app, "alice@example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = password_login(&app, "alice@example.com", "new password 123").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn change_password_signs_out_other_sessions(pool: PgPool) {
+    let app = spawn_app(pool);
+    let other = registered_user(&app, "alice@example.com").await;
+    let current = password_login(&app, "alice@example.com", PASSWORD).await;
+    let current: Value = current.json().await.unwrap();
+    let access_token = current["access_token"].as_str().unwrap();
+
+    let body = json!({ "current_password": PASSWORD, "new_password": "new password 123" });
+    let response = app
+        .request(Method::POST, "/password/change", access_token, Some(&body))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(refresh(&app, &other).await, 401);
+    assert_eq!(refresh(&app, &current).await, 200);
+    let response = app
+        .request(Method::GET, "/health_check", access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn password_reset_sets_new_password_and_signs_out(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = registered_user(&app, "alice@example.com").await;
+
+    let response = app
+        .post_json(
+            "/password/reset/request",
+            &json!({ "email": "nobody@example.com" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    assert!(app.mailer.last_to("nobody@example.com").is_none());
+
+    let response = app
+        .post_json(
+            "/password/reset/request",
+            &json!({ "email": "alice@example.com" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let token = app.mailer.token_for("alice@example.com");
+
+    let body = json!({ "token": token, "new_password": "new password 123" });
+    let response = app.post_json("/password/reset", &body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app.post_json("/password/reset", &body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .request(
+            Method::GET,
+            "/health_check",
+            login["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = password_login(&app, "alice@example.com", "new password 123").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn provider_login_drops_unverified_password(pool: PgPool) {
+    let app = spawn_app(pool);
+    // Someone registers an email they don't own, the owner later signs in with Google
+    register(&app, "alice@example.com", PASSWORD).await;
+    app.login("alice@example.com").await;
+
+    let token = app.mailer.token_for("alice@example.com");
+    app.post_json("/register/verify", &json!({ "token": token }))
+        .await;
+    let response = password_login(&app, "alice@example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn provider_and_password_emails_match_in_any_case(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = registered_user(&app, "alice@example.com").await;
+
+    // Google reports the email with the case the user typed when signing up there
+    let response = app.login_google(&google_claims("Alice@Example.com")).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["user"]["userId"], login["user"]["userId"]);
+    assert_eq!(body["user"]["email"], "alice@example.com");
+
+    // The other way round, the Google account exists first
+    let body = app.login("Bob@Example.com").await;
+    assert_eq!(body["user"]["email"], "bob@example.com");
+    let response = register(&app, "bob@example.com", PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[sqlx::test]
+async fn password_emails_are_limited_per_hour(pool: PgPool) {
+    let app = spawn_app(pool);
+    register(&app, "alice@example.com", PASSWORD).await;
+    let email = json!({ "email": "alice@example.com" });
+    for path in [
+        "/password/reset/request",
+        "/register/resend",
+        "/password/reset/request",
+        "/register/resend",
+        "/password/reset/request",
+    ] {
+        let response = app.post_json(path, &email).await;
+        assert_eq!(response.status().as_u16(), 202);
+    }
+
+    for path in ["/password/reset/request", "/register/resend"] {
+        let response = app.post_json(path, &email).await;
+        assert_eq!(response.status().as_u16(), 429);
+    }
+    // The verification email of the registration and one per accepted request
+    assert_eq!(
+        app.mailer.sent_to("alice@example.com"),
+        6,
+        "no mail for refused requests"
+    );
+    // Emails without an account are counted the same, so the limit doesn't give them away
+    let nobody = json!({ "email": "nobody@example.com" });
+    for _ in 0..5 {
+        let response = app.post_json("/password/reset/request", &nobody).await;
+        assert_eq!(response.status().as_u16(), 202);
+    }
+    let response = app.post_json("/password/reset/request", &nobody).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    sqlx::query!("UPDATE password_email_requests SET requested_at = NOW() - INTERVAL '61 minutes'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.post_json("/password/reset/request", &email).await;
+    assert_eq!(response.status().as_u16(), 202);
+}
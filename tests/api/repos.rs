@@ -0,0 +1,288 @@
+use chrono::{Duration, Utc};
+use kairos_server::repo::Repos;
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{
+    google_claims, id_token, project, session, spawn_app, spawn_app_in_memory, TestApp,
+};
+
+/// Same requests and answers whichever repositories the app runs on
+async fn projects_and_sessions(app: &TestApp, user_id: &str, access_token: &str) {
+    let status = |method: Method, path: String, body: Option<Value>| async move {
+        app.request(method, &path, access_token, body.as_ref())
+            .await
+            .status()
+            .as_u16()
+    };
+    let get = |path: String| async move {
+        let response = app.request(Method::GET, &path, access_token, None).await;
+        assert_eq!(response.status().as_u16(), 200, "{}", path);
+        response.json::<Value>().await.unwrap()
+    };
+
+    let project_id = Uuid::new_v4().to_string();
+    let body = project(&project_id, user_id, "Thesis");
+    let add = || status(Method::POST, "/add_project".to_string(), Some(body.clone()));
+    assert_eq!(add().await, 200);
+    assert_eq!(add().await, 409);
+    let mut renamed = body.clone();
+    renamed["projectName"] = json!("Dissertation");
+    let update = status(Method::POST, "/update_project".to_string(), Some(renamed));
+    assert_eq!(update.await, 200);
+    let unknown = project(&Uuid::new_v4().to_string(), user_id, "Unknown");
+    let update = status(Method::POST, "/update_project".to_string(), Some(unknown));
+    assert_eq!(update.await, 404);
+    let projects = get(format!("/get_projects/{}", user_id)).await;
+    let names: Vec<&Value> = projects
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|p| p["projectId"] == project_id.as_str())
+        .map(|p| &p["projectName"])
+        .collect();
+    assert_eq!(names, [&json!("Dissertation")]);
+
+    let session_id = Uuid::new_v4().to_string();
+    let mut body = session(&session_id, user_id, &project_id);
+    let add = |body: Value| status(Method::POST, "/add_session".to_string(), Some(body));
+    assert_eq!(add(body.clone()).await, 200);
+    assert_eq!(add(body.clone()).await, 409);
+    let elsewhere = session(
+        &Uuid::new_v4().to_string(),
+        user_id,
+        &Uuid::new_v4().to_string(),
+    );
+    assert_eq!(add(elsewhere).await, 403);
+    let active = get(format!("/check_active_session/{}", user_id)).await;
+    assert_eq!(active["sessionId"], session_id.as_str());
+
+    body["endedAt"] = json!(Utc::now());
+    body["duration"] = json!(1500);
+    let update = status(Method::POST, "/update_session".to_string(), Some(body));
+    assert_eq!(update.await, 200);
+    let active = status(
+        Method::GET,
+        format!("/check_active_session/{}", user_id),
+        None,
+    );
+    assert_eq!(active.await, 404);
+    let focus = get(format!("/get_todays_focus_time/{}", user_id)).await;
+    assert_eq!(focus, 1500);
+    let sessions = get(format!("/get_sessions/{}", user_id)).await;
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+
+    // One without sessions, those keep their project
+    let spare = project(&Uuid::new_v4().to_string(), user_id, "Spare");
+    assert_eq!(
+        status(
+            Method::POST,
+            "/add_project".to_string(),
+            Some(spare.clone())
+        )
+        .await,
+        200
+    );
+    let delete = || {
+        status(
+            Method::DELETE,
+            "/delete_project".to_string(),
+            Some(spare.clone()),
+        )
+    };
+    assert_eq!(delete().await, 200);
+    assert_eq!(delete().await, 404);
+}
+
+/// Every way to sign in, with the storage behind it
+async fn sign_in_methods(app: &TestApp) {
+    let body = |response: reqwest::Response| async move {
+        assert_eq!(response.status().as_u16(), 200);
+        response.json::<Value>().await.unwrap()
+    };
+
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let login = app.login("ada@example.com").await;
+    assert_eq!(login["user"]["userId"], user_id.as_str());
+    let identities = body(
+        app.request(Method::GET, "/identities", &access_token, None)
+            .await,
+    )
+    .await;
+    assert_eq!(identities.as_array().unwrap().len(), 1);
+    let subject = identities[0]["subject"].as_str().unwrap();
+    let unlink = app
+        .request(
+            Method::DELETE,
+            &format!("/identities/google/{}", subject),
+            &access_token,
+            None,
+        )
+        .await;
+    assert_eq!(unlink.status().as_u16(), 409);
+
+    // Personal access tokens
+    let scopes = json!({ "name": "script", "scopes": ["projects:read"] });
+    let created = app
+        .request(Method::POST, "/access_tokens", &access_token, Some(&scopes))
+        .await;
+    let created = body(created).await;
+    let pat = created["token"].as_str().unwrap();
+    let projects_path = format!("/get_projects/{}", user_id);
+    let projects = app.request(Method::GET, &projects_path, pat, None).await;
+    assert_eq!(body(projects).await.as_array().unwrap().len(), 1);
+    let revoke = format!("/access_tokens/{}", created["tokenId"].as_str().unwrap());
+    let revoked = app
+        .request(Method::DELETE, &revoke, &access_token, None)
+        .await;
+    assert_eq!(revoked.status().as_u16(), 200);
+    let projects = app.request(Method::GET, &projects_path, pat, None).await;
+    assert_eq!(projects.status().as_u16(), 401);
+
+    // Password account, verified by email
+    let credentials = json!({ "email": "grace@example.com", "password": "correct horse" });
+    let mut registration = credentials.clone();
+    registration["name"] = json!("Grace");
+    let registered = app.post_json("/register", &registration).await;
+    assert_eq!(registered.status().as_u16(), 201);
+    let password_login = || app.post_json("/password/login", &credentials);
+    assert_eq!(password_login().await.status().as_u16(), 403);
+    let token = app.mailer.token_for("grace@example.com");
+    let verified = app
+        .post_json("/register/verify", &json!({ "token": token }))
+        .await;
+    assert_eq!(verified.status().as_u16(), 200);
+    let login = body(password_login().await).await;
+
+    // Login link to the same account
+    let email = json!({ "email": "grace@example.com" });
+    let requested = app.post_json("/login_code", &email).await;
+    assert_eq!(requested.status().as_u16(), 202);
+    let link = json!({ "token": app.mailer.token_for("grace@example.com") });
+    let verify = || app.post_json("/login_code/verify", &link);
+    let link_login = body(verify().await).await;
+    assert_eq!(link_login["user"]["userId"], login["user"]["userId"]);
+    assert_eq!(verify().await.status().as_u16(), 401);
+
+    // A guest signing in with Ada's Google account moves into her account
+    let guest = body(app.post_json("/login/guest", &json!({})).await).await;
+    let guest_token = guest["access_token"].as_str().unwrap();
+    let upgrade = json!({ "id_token": id_token(&google_claims("ada@example.com")) });
+    let upgraded = app
+        .request(
+            Method::POST,
+            "/guest/upgrade/google",
+            guest_token,
+            Some(&upgrade),
+        )
+        .await;
+    assert_eq!(body(upgraded).await["user"]["userId"], user_id.as_str());
+    let projects = app
+        .request(Method::GET, &projects_path, &access_token, None)
+        .await;
+    assert_eq!(body(projects).await.as_array().unwrap().len(), 1);
+}
+
+/// Rotation, revocation and devices, `user_id` must be a user the repositories know
+async fn token_rules(repos: &Repos, user_id: Uuid) {
+    let tokens = &repos.tokens;
+    let expires_at = Utc::now() + Duration::days(30);
+    let (family_id, first, second) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let store = |token_id: Uuid, family_id: Uuid| {
+        tokens.store_refresh_token(
+            user_id,
+            token_id,
+            family_id,
+            "hash".to_string(),
+            expires_at,
+            Default::default(),
+        )
+    };
+
+    store(first, family_id).await.unwrap();
+    let stored = tokens.get_refresh_token(first).await.unwrap().unwrap();
+    assert_eq!((stored.user_id, stored.family_id), (user_id, family_id));
+    assert!(stored.rotated_at.is_none());
+    assert!(tokens.rotate_refresh_token(first).await.unwrap());
+    assert!(!tokens.rotate_refresh_token(first).await.unwrap());
+    let stored = tokens.get_refresh_token(first).await.unwrap().unwrap();
+    assert!(stored.rotated_at.is_some());
+    assert!(tokens
+        .get_refresh_token(Uuid::new_v4())
+        .await
+        .unwrap()
+        .is_none());
+
+    store(second, family_id).await.unwrap();
+    assert!(store(second, family_id).await.is_err());
+    assert!(tokens.owns_token_family(user_id, family_id).await.unwrap());
+    assert!(!tokens
+        .owns_token_family(Uuid::new_v4(), family_id)
+        .await
+        .unwrap());
+    // Only the live token of the family, on Postgres next to the login of the user
+    let devices = tokens.get_devices(user_id, Some(family_id)).await.unwrap();
+    let device: Vec<_> = devices
+        .iter()
+        .filter(|d| d.device_id == family_id)
+        .collect();
+    assert_eq!(device.len(), 1);
+    assert!(device[0].current);
+
+    let expiry = Utc::now() + Duration::minutes(15);
+    assert!(!tokens.is_token_family_revoked(family_id).await.unwrap());
+    tokens.revoke_token_family(family_id, expiry).await.unwrap();
+    assert!(tokens.is_token_family_revoked(family_id).await.unwrap());
+    assert!(tokens.get_refresh_token(second).await.unwrap().is_none());
+    let devices = tokens.get_devices(user_id, None).await.unwrap();
+    assert!(devices.iter().all(|d| d.device_id != family_id));
+
+    let other_family = Uuid::new_v4();
+    store(Uuid::new_v4(), other_family).await.unwrap();
+    tokens
+        .revoke_user_tokens(user_id, None, expiry)
+        .await
+        .unwrap();
+    assert!(tokens.is_token_family_revoked(other_family).await.unwrap());
+}
+
+#[sqlx::test]
+async fn projects_and_sessions_on_postgres(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+
+    projects_and_sessions(&app, &user_id, &access_token).await;
+}
+
+#[tokio::test]
+async fn projects_and_sessions_in_memory() {
+    let app = spawn_app_in_memory();
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+
+    projects_and_sessions(&app, &user_id, &access_token).await;
+}
+
+#[sqlx::test]
+async fn sign_in_methods_on_postgres(pool: PgPool) {
+    sign_in_methods(&spawn_app(pool)).await;
+}
+
+#[tokio::test]
+async fn sign_in_methods_in_memory() {
+    sign_in_methods(&spawn_app_in_memory()).await;
+}
+
+#[sqlx::test]
+async fn token_rules_on_postgres(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, _) = app.login_user("ada@example.com").await;
+
+    token_rules(&Repos::postgres(app.pool.clone()), user_id.parse().unwrap()).await;
+}
+
+#[tokio::test]
+async fn token_rules_in_memory() {
+    token_rules(&Repos::in_memory(), Uuid::new_v4()).await;
+}
@@ -0,0 +1,92 @@
+use reqwest::Method;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::helpers::{captured_logs, spawn_app};
+
+fn request_id(response: &reqwest::Response) -> String {
+    response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[sqlx::test]
+async fn every_response_has_a_request_id(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let projects = format!("/get_projects/{}", user_id);
+
+    let ok = app
+        .request(Method::GET, &projects, &access_token, None)
+        .await;
+    let unauthorized = app.client.get(format!("{}{}", app.address, projects));
+    let unauthorized = unauthorized.send().await.unwrap();
+    let missing = app
+        .request(Method::GET, "/nowhere", &access_token, None)
+        .await;
+
+    assert_eq!(unauthorized.status().as_u16(), 401);
+    assert_eq!(missing.status().as_u16(), 404);
+    let ids: Vec<String> = [&ok, &unauthorized, &missing]
+        .iter()
+        .map(|response| request_id(response))
+        .collect();
+    for id in &ids {
+        Uuid::parse_str(id).expect("generated ids are uuids");
+    }
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[sqlx::test]
+async fn incoming_request_ids_are_kept_if_sane(pool: PgPool) {
+    let app = spawn_app(pool);
+    let send = |id: &'static str| {
+        app.client
+            .get(format!("{}/.well-known/jwks.json", app.address))
+            .header("X-Request-Id", id)
+            .send()
+    };
+
+    let kept = send("lb-7f3a.42").await.unwrap();
+    assert_eq!(request_id(&kept), "lb-7f3a.42");
+
+    let replaced = send("<script>alert(1)</script>").await.unwrap();
+    Uuid::parse_str(&request_id(&replaced)).expect("a fresh id replaces the invalid one");
+}
+
+#[sqlx::test]
+async fn requests_are_logged_with_route_user_status_and_queries(pool: PgPool) {
+    let logs = captured_logs();
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("ada@example.com").await;
+    let request_id = Uuid::new_v4().to_string();
+
+    let response = app
+        .client
+        .get(format!("{}/get_projects/{}", app.address, user_id))
+        .bearer_auth(&access_token)
+        .header("X-Request-Id", &request_id)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let lines = logs.for_request(&request_id);
+    let finished = lines
+        .iter()
+        .find(|line| line["message"] == "request finished")
+        .expect("the request is logged");
+    assert_eq!(finished["level"], "INFO");
+    assert_eq!(finished["span"]["method"], "GET");
+    assert_eq!(finished["span"]["route"], "/get_projects/{user_id}");
+    assert_eq!(finished["span"]["user_id"], user_id);
+    assert_eq!(finished["span"]["status"], 200);
+    assert!(finished["span"]["latency_ms"].is_u64());
+
+    let query = lines
+        .iter()
+        .find(|line| line["target"] == "sqlx::query")
+        .expect("queries are logged inside the request span");
+    assert!(query["elapsed_secs"].is_f64());
+}
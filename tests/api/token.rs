@@ -0,0 +1,68 @@
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::helpers::spawn_app;
+
+#[sqlx::test]
+async fn refresh_rotates_the_token_pair(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = app.login("ada@example.com").await;
+
+    let response = app
+        .post_json(
+            "/token/refresh",
+            &json!({ "refresh_token": login["refresh_token"] }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_ne!(body["refresh_token"], login["refresh_token"]);
+    let access = app
+        .client
+        .get(format!("{}/health_check", app.address))
+        .bearer_auth(body["access_token"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(access.status().as_u16(), 200);
+}
+
+#[sqlx::test]
+async fn reusing_a_rotated_refresh_token_revokes_the_family(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = app.login("ada@example.com").await;
+    let first = json!({ "refresh_token": login["refresh_token"] });
+    let rotated: Value = app
+        .post_json("/token/refresh", &first)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let reuse = app.post_json("/token/refresh", &first).await;
+
+    assert_eq!(reuse.status().as_u16(), 401);
+    // The legitimately rotated token died with the family
+    let response = app
+        .post_json(
+            "/token/refresh",
+            &json!({ "refresh_token": rotated["refresh_token"] }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn refresh_rejects_access_tokens_and_garbage(pool: PgPool) {
+    let app = spawn_app(pool);
+    let login = app.login("ada@example.com").await;
+
+    for token in [login["access_token"].clone(), json!("garbage")] {
+        let response = app
+            .post_json("/token/refresh", &json!({ "refresh_token": token }))
+            .await;
+
+        assert_eq!(response.status().as_u16(), 401);
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::Utc;
+use reqwest::Method;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::helpers::{google_claims, spawn_app, TestApp};
+
+/// Code of the authenticator `steps` time steps from now
+pub fn totp_code(secret: &str, steps: i64) -> String {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret, None, String::new()).unwrap();
+    totp.generate((Utc::now().timestamp() + steps * 30) as u64)
+}
+
+/// Enrol and confirm TOTP, returns the secret and the recovery codes
+pub async fn enable_totp(app: &TestApp, access_token: &str) -> (String, Vec<String>) {
+    let response = app
+        .request(Method::POST, "/2fa/totp/enroll", access_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let enrollment: Value = response.json().await.unwrap();
+    let secret = enrollment["secret"].as_str().unwrap().to_string();
+    let uri = enrollment["otpauth_uri"].as_str().unwrap();
+    assert!(uri.starts_with("otpauth://totp/Kairos:alice%40example.com?"));
+    assert!(uri.contains(&format!("secret={}", secret)));
+
+    let body = json!({ "code": totp_code(&secret, 0) });
+    let response = app
+        .request(Method::POST, "/2fa/totp/confirm", access_token, Some(&body))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    let codes = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+    (secret, codes)
+}
+
+/// First login step, returns the challenge token
+async fn login_challenge(app: &TestApp) -> String {
+    let response = app.login_google(&google_claims("alice@example.com")).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["two_factor_required"], true);
+    assert!(body["access_token"].is_null());
+    body["challenge_token"].as_str().unwrap().to_string()
+}
+
+async fn verify(app: &TestApp, body: Value) -> reqwest::Response {
+    app.post_json("/2fa/verify", &body).await
+}
+
+#[sqlx::test]
+async fn login_requires_totp_once_enabled(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+
+    let response = app
+        .request(Method::POST, "/2fa/totp/enroll", &access_token, None)
+        .await;
+    let secret = response.json::<Value>().await.unwrap()["secret"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let body = json!({ "code": "000000" });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/confirm",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    // Not enabled until confirmed
+    app.login("alice@example.com").await;
+
+    let body = json!({ "code": totp_code(&secret, 0) });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/confirm",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let challenge_token = login_challenge(&app).await;
+    let code = totp_code(&secret, 1);
+    let response = verify(
+        &app,
+        json!({ "challenge_token": challenge_token, "code": code }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let login: Value = response.json().await.unwrap();
+    let response = app
+        .request(
+            Method::GET,
+            "/health_check",
+            login["access_token"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A code can't be replayed
+    let response = verify(
+        &app,
+        json!({ "challenge_token": challenge_token, "code": code }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn recovery_codes_work_once(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let (_, recovery_codes) = enable_totp(&app, &access_token).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let challenge_token = login_challenge(&app).await;
+    let body = json!({ "challenge_token": challenge_token, "recovery_code": recovery_codes[0].to_uppercase() });
+    assert_eq!(verify(&app, body.clone()).await.status().as_u16(), 200);
+    assert_eq!(verify(&app, body).await.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn challenge_token_is_not_an_access_token(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let (secret, _) = enable_totp(&app, &access_token).await;
+
+    let challenge_token = login_challenge(&app).await;
+    let response = app
+        .request(Method::GET, "/health_check", &challenge_token, None)
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_json(
+            "/token/refresh",
+            &json!({ "refresh_token": challenge_token }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // Nor does a refresh token pass as a challenge
+    let login = app.login("bob@example.com").await;
+    let body = json!({ "challenge_token": login["refresh_token"], "code": totp_code(&secret, 1) });
+    assert_eq!(verify(&app, body).await.status().as_u16(), 401);
+}
+
+#[sqlx::test]
+async fn repeated_wrong_codes_lock_the_second_step(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let (secret, _) = enable_totp(&app, &access_token).await;
+    let challenge_token = login_challenge(&app).await;
+
+    let wrong = if totp_code(&secret, 1) == "000000" {
+        "000001"
+    } else {
+        "000000"
+    };
+    for _ in 0..5 {
+        let body = json!({ "challenge_token": challenge_token, "code": wrong });
+        assert_eq!(verify(&app, body).await.status().as_u16(), 401);
+    }
+    let body = json!({ "challenge_token": challenge_token, "code": totp_code(&secret, 1) });
+    assert_eq!(verify(&app, body).await.status().as_u16(), 429);
+}
+
+#[sqlx::test]
+async fn disabling_requires_reauthentication(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (_, access_token) = app.login_user("alice@example.com").await;
+    let (_, recovery_codes) = enable_totp(&app, &access_token).await;
+
+    let body = json!({ "recovery_code": "aaaaa-aaaaa" });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/disable",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let body = json!({ "recovery_code": recovery_codes[0] });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/disable",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let login = app.login("alice@example.com").await;
+    assert!(login["access_token"].is_string());
+}
+
+#[sqlx::test]
+async fn disabling_needs_the_password_of_password_accounts(pool: PgPool) {
+    let app = spawn_app(pool);
+    let (user_id, access_token) = app.login_user("alice@example.com").await;
+    let hash = bcrypt::hash("correct horse battery", 4).unwrap();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $2 WHERE user_id = $1::TEXT::UUID",
+        user_id,
+        hash
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    let (secret, _) = enable_totp(&app, &access_token).await;
+
+    let body = json!({ "code": totp_code(&secret, 1), "password": "wrong password" });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/disable",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let body = json!({ "code": totp_code(&secret, 1), "password": "correct horse battery" });
+    let response = app
+        .request(
+            Method::POST,
+            "/2fa/totp/disable",
+            &access_token,
+            Some(&body),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}